@@ -2,14 +2,18 @@ use clap::{Parser, Subcommand};
 
 use std::path::{Path, PathBuf};
 use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
 use footnote_core::model::contact::Contact;
+use footnote_core::model::device::DeviceRole;
 use footnote_core::model::note::Note;
 use footnote_core::model::vault::Vault;
 use footnote_core::service::join_service::{JoinEvent, JoinService};
-use footnote_core::service::sync_service::SyncService;
+use footnote_core::service::ping_service::{PingOutcome, PingService};
+use footnote_core::service::push_service::{PushEvent, PushService};
+use footnote_core::service::sync_service::{InboundEvent, ShareOutcome, SyncService};
 use footnote_core::service::ALPN_SYNC;
-use footnote_core::util::lamport_timestamp::LamportTimestamp;
+use footnote_core::util::change_signal::{ChangeSignal, VaultWatcher};
 use footnote_core::util::tombstone::tombstone_create;
 
 #[derive(Parser)]
@@ -40,6 +44,25 @@ pub enum Commands {
         #[command(subcommand)]
         action: ContactAction,
     },
+    Share {
+        #[command(subcommand)]
+        action: ShareAction,
+    },
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    /// Folders several contacts add and edit notes in
+    Folder {
+        #[command(subcommand)]
+        action: FolderAction,
+    },
+    /// Sync through a file instead of the network, for devices that can't
+    /// reach each other
+    Bundle {
+        #[command(subcommand)]
+        action: BundleAction,
+    },
 }
 
 #[derive(Subcommand)]
@@ -63,6 +86,29 @@ pub enum VaultAction {
         #[arg(short, long, default_value_t = false)]
         fix: bool,
     },
+
+    /// Show how this device finds others, or change it. Both are on unless
+    /// turned off; with public relays off, devices only reach each other on
+    /// the local network or through --relays. Takes effect the next time a
+    /// service starts.
+    Network {
+        #[arg(long)]
+        local_discovery: Option<bool>,
+        #[arg(long)]
+        public_relays: Option<bool>,
+        /// use these relay servers in place of the public ones. give no urls
+        /// to go back to the public relays.
+        #[arg(long, num_args = 0..)]
+        relays: Option<Vec<String>>,
+    },
+
+    /// On the device leader, describe one of your devices in your signed
+    /// record: "often-on", "mobile" or "desktop". Contacts deliver shares to
+    /// an often-on device first. Give no role to clear it.
+    DeviceRole {
+        device_name: String,
+        role: Option<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -97,6 +143,15 @@ pub enum ServiceAction {
     /// share to the primary device for the user with the given nickname you
     /// have previously joined
     Share { to_nickname: String },
+
+    /// check whether one of your devices, or each device of a contact, can be
+    /// reached and would accept a sync, without syncing anything
+    Ping { name: String },
+
+    /// Run as the often-on device: listen for devices and contacts, push
+    /// changes to them as notes change and on a timer. Stops on SIGTERM or
+    /// ctrl-c. Prints one JSON event per line.
+    Daemon {},
 }
 
 #[derive(Subcommand)]
@@ -108,6 +163,95 @@ pub enum ContactAction {
     Import { nickname: String, path: PathBuf },
     /// show all trusted contacts
     Read {},
+    /// Limit what a contact can store on this device. Unset limits keep their
+    /// current value.
+    Quota {
+        nickname: String,
+        #[arg(long)]
+        max_note_bytes: Option<u64>,
+        #[arg(long)]
+        max_total_bytes: Option<u64>,
+        #[arg(long)]
+        max_files: Option<usize>,
+    },
+    /// show the space each contact's shared notes use on this device
+    Usage {},
+}
+
+#[derive(Subcommand)]
+pub enum ShareAction {
+    /// Share every note under a folder or matching a glob with the given
+    /// contacts, e.g. `share add-rule recipes/ mom dad`. Adding a rule for an
+    /// existing pattern replaces its contacts.
+    AddRule {
+        pattern: String,
+        #[arg(num_args = 1..)]
+        nicknames: Vec<String>,
+    },
+    /// Remove the share rule with the given pattern
+    DeleteRule { pattern: String },
+    /// show all share rules
+    Rules {},
+    /// List every note the contact receives on the next share, and why:
+    /// share_with, a group they are in, a share rule or a shared folder
+    Preview { nickname: String },
+}
+
+#[derive(Subcommand)]
+pub enum GroupAction {
+    /// Create a named set of contacts. The name can be used in share_with or
+    /// a share rule in place of listing each contact.
+    Create {
+        name: String,
+        #[arg(num_args = 0..)]
+        nicknames: Vec<String>,
+    },
+    /// Add a contact to a group
+    Add { name: String, nickname: String },
+    /// Remove a contact from a group
+    Remove { name: String, nickname: String },
+    /// Delete a group. Notes shared with the group are no longer shared with
+    /// its members.
+    Delete { name: String },
+    /// show all groups and their members
+    Read {},
+}
+
+#[derive(Subcommand)]
+pub enum FolderAction {
+    /// Start a shared folder with the given contacts, e.g. `folder create
+    /// trips mom dad`. Prints the id each member joins it with.
+    Create {
+        path: String,
+        #[arg(num_args = 1..)]
+        nicknames: Vec<String>,
+    },
+    /// Join a shared folder another member started, at path in this vault
+    Join {
+        id: Uuid,
+        path: String,
+        #[arg(num_args = 1..)]
+        nicknames: Vec<String>,
+    },
+    /// Stop sharing a folder. Its notes stay in the vault.
+    Leave { path: String },
+    /// show all shared folders and their members
+    Read {},
+}
+
+#[derive(Subcommand)]
+pub enum BundleAction {
+    /// Write what a replicate to one of your devices, or a share with a
+    /// contact, would send to a signed bundle file
+    Export {
+        #[arg(long, conflicts_with = "contact", required_unless_present = "contact")]
+        device: Option<String>,
+        #[arg(long)]
+        contact: Option<String>,
+        path: PathBuf,
+    },
+    /// Apply a bundle exported for this device, or for you by a contact
+    Import { path: PathBuf },
 }
 
 #[derive(Subcommand)]
@@ -125,6 +269,13 @@ pub enum NoteAction {
     Delete {
         path: PathBuf,
     },
+    /// Add a line to the end of a note, creating it as a log if it doesn't
+    /// exist. Lines appended to a log on two devices at once are both kept.
+    Append {
+        path: PathBuf,
+        #[arg(allow_hyphen_values = true)]
+        text: String,
+    },
 }
 
 pub async fn execute(cli: Cli) -> anyhow::Result<()> {
@@ -136,6 +287,12 @@ pub async fn execute(cli: Cli) -> anyhow::Result<()> {
             } => vault_create_primary(username, device_name),
             VaultAction::CreateStandalone {} => vault_create_standalone(),
             VaultAction::Doctor { fix } => vault_doctor(fix),
+            VaultAction::Network {
+                local_discovery,
+                public_relays,
+                relays,
+            } => vault_network(local_discovery, public_relays, relays),
+            VaultAction::DeviceRole { device_name, role } => vault_device_role(device_name, role),
         },
         Commands::Service { action } => match action {
             ServiceAction::JoinListen {} => service_join_listen().await,
@@ -147,6 +304,8 @@ pub async fn execute(cli: Cli) -> anyhow::Result<()> {
             ServiceAction::Replicate { to_device_name } => service_replicate(to_device_name).await,
             ServiceAction::ShareListen {} => service_share_listen().await,
             ServiceAction::Share { to_nickname } => service_share(to_nickname).await,
+            ServiceAction::Ping { name } => service_ping(name).await,
+            ServiceAction::Daemon {} => service_daemon().await,
         },
         Commands::Note { action } => match action {
             NoteAction::Create { path, content } => note_create(&path, &content),
@@ -156,11 +315,50 @@ pub async fn execute(cli: Cli) -> anyhow::Result<()> {
                 share,
             } => note_update(&path, &content, share),
             NoteAction::Delete { path } => note_delete(&path).await,
+            NoteAction::Append { path, text } => note_append(&path, &text),
         },
         Commands::Contact { action } => match action {
             ContactAction::Export {} => contact_export(),
             ContactAction::Import { nickname, path } => contact_import(&nickname, &path),
             ContactAction::Read {} => contact_read(),
+            ContactAction::Quota {
+                nickname,
+                max_note_bytes,
+                max_total_bytes,
+                max_files,
+            } => contact_quota(&nickname, max_note_bytes, max_total_bytes, max_files),
+            ContactAction::Usage {} => contact_usage(),
+        },
+        Commands::Share { action } => match action {
+            ShareAction::AddRule { pattern, nicknames } => share_rule_add(&pattern, nicknames),
+            ShareAction::DeleteRule { pattern } => share_rule_delete(&pattern),
+            ShareAction::Rules {} => share_rule_read(),
+            ShareAction::Preview { nickname } => share_preview(&nickname),
+        },
+        Commands::Group { action } => match action {
+            GroupAction::Create { name, nicknames } => group_create(&name, nicknames),
+            GroupAction::Add { name, nickname } => group_add(&name, &nickname),
+            GroupAction::Remove { name, nickname } => group_remove(&name, &nickname),
+            GroupAction::Delete { name } => group_delete(&name),
+            GroupAction::Read {} => group_read(),
+        },
+        Commands::Folder { action } => match action {
+            FolderAction::Create { path, nicknames } => folder_create(&path, nicknames),
+            FolderAction::Join {
+                id,
+                path,
+                nicknames,
+            } => folder_join(id, &path, nicknames),
+            FolderAction::Leave { path } => folder_leave(&path),
+            FolderAction::Read {} => folder_read(),
+        },
+        Commands::Bundle { action } => match action {
+            BundleAction::Export {
+                device,
+                contact,
+                path,
+            } => bundle_export(device, contact, &path).await,
+            BundleAction::Import { path } => bundle_import(&path).await,
         },
     }
 }
@@ -197,6 +395,44 @@ fn vault_doctor(fix: bool) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn vault_network(
+    local_discovery: Option<bool>,
+    public_relays: Option<bool>,
+    relays: Option<Vec<String>>,
+) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    if let Some(relays) = relays {
+        vault.relays_set(relays)?;
+    }
+    let mut settings = vault.settings_read()?;
+    if let Some(local_discovery) = local_discovery {
+        settings.disable_local_discovery = !local_discovery;
+    }
+    if let Some(public_relays) = public_relays {
+        settings.disable_public_relays = !public_relays;
+    }
+    if local_discovery.is_some() || public_relays.is_some() {
+        vault.settings_write(&settings)?;
+    }
+    println!("local_discovery:{}", !settings.disable_local_discovery);
+    println!("public_relays:{}", !settings.disable_public_relays);
+    for relay_url in &settings.relay_urls {
+        println!("relay:{}", relay_url);
+    }
+    Ok(())
+}
+
+fn vault_device_role(device_name: String, role: Option<String>) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let role = role.map(|r| r.parse::<DeviceRole>()).transpose()?;
+    let user_record = vault.device_role_set(&device_name, role)?;
+    for device in user_record.devices {
+        let metadata = serde_json::to_string(&device.metadata)?;
+        println!("{}:{}", device.name, metadata);
+    }
+    Ok(())
+}
+
 async fn service_join_listen() -> anyhow::Result<()> {
     let vault = Vault::new(&std::env::current_dir()?)?;
     let cancel_token = CancellationToken::new();
@@ -294,13 +530,233 @@ async fn service_share_listen() -> anyhow::Result<()> {
 async fn service_share(to_nickname: String) -> anyhow::Result<()> {
     let vault = Vault::new(&std::env::current_dir()?)?;
     let endpoint = vault.build_endpoint(ALPN_SYNC).await?;
-    SyncService::share_to_contact(&vault, endpoint, &to_nickname).await?;
+    let detail = match SyncService::share_to_contact(&vault, endpoint, &to_nickname).await? {
+        ShareOutcome::Delivered => "",
+        ShareOutcome::UpToDate => "up to date",
+    };
     println!(
         "{}",
         serde_json::json!(
             {
                 "event": "push.success",
-                "detail": ""
+                "detail": detail
+            }
+        )
+    );
+    Ok(())
+}
+
+async fn service_ping(name: String) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let endpoint = vault.build_endpoint(ALPN_SYNC).await?;
+    for (device, report) in PingService::ping_peer(&vault, endpoint, &name).await? {
+        let status = match report.outcome {
+            PingOutcome::Reachable { .. } => "reachable",
+            PingOutcome::Rejected { .. } => "rejected",
+            PingOutcome::Unreachable { .. } => "unreachable",
+        };
+        println!(
+            "{}",
+            serde_json::json!(
+                {
+                    "event": "ping",
+                    "device": device,
+                    "endpoint_id": report.endpoint_id,
+                    "status": status,
+                    "detail": report.summary()
+                }
+            )
+        );
+    }
+    Ok(())
+}
+
+async fn service_daemon() -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let endpoint = vault.build_endpoint(ALPN_SYNC).await?;
+    let cancel_token = CancellationToken::new();
+
+    let changes = ChangeSignal::new();
+    let peer_changes = ChangeSignal::new();
+    // the watchers stop when dropped, keep them until shutdown
+    let _note_watcher = VaultWatcher::start(&vault.base_path(), changes.clone())?;
+    let _peer_watcher = VaultWatcher::start_peer_records(&vault.base_path(), peer_changes.clone())?;
+
+    let (connects_tx, connects_rx) = tokio::sync::mpsc::channel(32);
+    let (inbound_tx, mut inbound_rx) = tokio::sync::mpsc::channel(32);
+    let listen = tokio::spawn(SyncService::listen_reporting(
+        vault.clone(),
+        endpoint.clone(),
+        cancel_token.clone(),
+        connects_tx,
+        inbound_tx,
+    ));
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+    let push = tokio::spawn(
+        PushService::new(vault, endpoint.clone(), changes)
+            .peer_changes(peer_changes)
+            .connects(connects_rx)
+            .events(tx)
+            .run(cancel_token.clone()),
+    );
+
+    println!(
+        "{}",
+        serde_json::json!(
+            {
+                "event": "listening",
+                "endpoint_id": endpoint.id().to_string()
+            }
+        )
+    );
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            result = &mut shutdown, if !cancel_token.is_cancelled() => {
+                if let Err(e) = result {
+                    eprintln!("could not wait for shutdown signal: {}", e);
+                }
+                cancel_token.cancel();
+            }
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                println!("{}", push_event_json(&event));
+            }
+            Some(event) = inbound_rx.recv() => {
+                println!("{}", inbound_event_json(&event));
+            }
+        }
+    }
+
+    if let Err(e) = listen.await? {
+        eprintln!("listen stopped with error: {}", e);
+    }
+    push.await?;
+    endpoint.close().await;
+    println!(
+        "{}",
+        serde_json::json!(
+            {
+                "event": "shutdown",
+            }
+        )
+    );
+    Ok(())
+}
+
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => {}
+            result = tokio::signal::ctrl_c() => result?,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+fn push_event_json(event: &PushEvent) -> serde_json::Value {
+    match event {
+        PushEvent::Mirrored { device_name } => serde_json::json!(
+            {
+                "event": "push.success",
+                "device": device_name
+            }
+        ),
+        PushEvent::Shared { nickname } => serde_json::json!(
+            {
+                "event": "push.success",
+                "contact": nickname
+            }
+        ),
+        PushEvent::Failed { peer, error } => serde_json::json!(
+            {
+                "event": "push.failure",
+                "peer": peer,
+                "detail": error
+            }
+        ),
+        PushEvent::PeersReloaded { devices, contacts } => serde_json::json!(
+            {
+                "event": "peers.reload",
+                "devices": devices,
+                "contacts": contacts
+            }
+        ),
+    }
+}
+
+fn inbound_event_json(event: &InboundEvent) -> serde_json::Value {
+    match event {
+        InboundEvent::Received { peer } => serde_json::json!(
+            {
+                "event": "receive.success",
+                "peer": peer
+            }
+        ),
+        InboundEvent::Rejected {
+            endpoint_id,
+            reason,
+        } => serde_json::json!(
+            {
+                "event": "receive.rejected",
+                "endpoint_id": endpoint_id,
+                "detail": reason
+            }
+        ),
+        InboundEvent::Failed { peer, error } => serde_json::json!(
+            {
+                "event": "receive.failure",
+                "peer": peer,
+                "detail": error
+            }
+        ),
+    }
+}
+
+async fn bundle_export(
+    device: Option<String>,
+    contact: Option<String>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let bytes = match (device, contact) {
+        (Some(device_name), _) => SyncService::mirror_bundle(&vault, &device_name).await?,
+        (None, Some(nickname)) => SyncService::share_bundle(&vault, &nickname).await?,
+        (None, None) => anyhow::bail!("export needs --device or --contact"),
+    };
+    std::fs::write(path, bytes)?;
+    println!(
+        "{}",
+        serde_json::json!(
+            {
+                "event": "bundle.exported",
+                "detail": path.display().to_string()
+            }
+        )
+    );
+    Ok(())
+}
+
+async fn bundle_import(path: &Path) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let bytes = std::fs::read(path)?;
+    let header = SyncService::import_bundle(&vault, &bytes).await?;
+    println!(
+        "{}",
+        serde_json::json!(
+            {
+                "event": "bundle.imported",
+                "detail": header.from
             }
         )
     );
@@ -309,7 +765,8 @@ async fn service_share(to_nickname: String) -> anyhow::Result<()> {
 
 fn note_create(path: &Path, content: &str) -> anyhow::Result<()> {
     let note_path = std::env::current_dir()?.join(path);
-    Note::create(&note_path, content)?;
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    Note::create(&note_path, content, &vault.clock())?;
     Ok(())
 }
 
@@ -319,7 +776,23 @@ fn note_update(path: &Path, content: &str, shares: Option<Vec<String>>) -> anyho
     if let Some(updated_shares) = shares {
         n.frontmatter.share_with = updated_shares;
     }
-    n.update(path, content)?;
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    n.update(path, content, &vault.clock())?;
+    Ok(())
+}
+
+fn note_append(path: &Path, text: &str) -> anyhow::Result<()> {
+    let note_path = std::env::current_dir()?.join(path);
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let clock = vault.clock();
+    let mut n = if note_path.exists() {
+        Note::from_path(&note_path, false)?
+    } else {
+        let mut n = Note::new(&clock);
+        n.frontmatter.log = true;
+        n
+    };
+    n.append(&note_path, text, &clock)?;
     Ok(())
 }
 
@@ -327,10 +800,11 @@ async fn note_delete(path: &Path) -> anyhow::Result<()> {
     let note_path = std::env::current_dir()?.join(path);
     let n = Note::from_path(note_path, false)?;
 
+    let vault = Vault::new(&std::env::current_dir()?)?;
     let result = tombstone_create(
-        &std::env::current_dir()?,
+        &vault.path,
         n.frontmatter.uuid,
-        LamportTimestamp::new(Some(n.frontmatter.modified)),
+        vault.version_after(Some(n.frontmatter.modified)),
     )
     .await;
     if let Err(e) = result {
@@ -368,3 +842,128 @@ fn contact_read() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+fn contact_quota(
+    nickname: &str,
+    max_note_bytes: Option<u64>,
+    max_total_bytes: Option<u64>,
+    max_files: Option<usize>,
+) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let mut quota = vault.settings_read()?.quota_for(nickname);
+    if let Some(max_note_bytes) = max_note_bytes {
+        quota.max_note_bytes = max_note_bytes;
+    }
+    if let Some(max_total_bytes) = max_total_bytes {
+        quota.max_total_bytes = max_total_bytes;
+    }
+    if let Some(max_files) = max_files {
+        quota.max_files = max_files;
+    }
+    vault.contact_quota_set(nickname, quota)?;
+    Ok(())
+}
+
+fn contact_usage() -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    for (nickname, usage, quota) in vault.contact_storage_report()? {
+        println!(
+            "{}:{}/{} files {}/{} bytes",
+            nickname, usage.files, quota.max_files, usage.bytes, quota.max_total_bytes
+        );
+    }
+    Ok(())
+}
+
+fn share_rule_add(pattern: &str, nicknames: Vec<String>) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.share_rule_add(pattern, nicknames)?;
+    Ok(())
+}
+
+fn share_rule_delete(pattern: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.share_rule_delete(pattern)?;
+    Ok(())
+}
+
+fn share_rule_read() -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    for rule in vault.settings_read()?.share_rules {
+        println!("{}:{}", rule.pattern, rule.share_with.join(" "));
+    }
+    Ok(())
+}
+
+fn share_preview(nickname: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    for preview in vault.share_preview(nickname)? {
+        let reasons: Vec<String> = preview.reasons.iter().map(|r| r.to_string()).collect();
+        println!("{}:{}", preview.entry.path.display(), reasons.join(", "));
+    }
+    Ok(())
+}
+
+fn group_create(name: &str, nicknames: Vec<String>) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.group_create(name, nicknames)?;
+    Ok(())
+}
+
+fn group_add(name: &str, nickname: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.group_member_add(name, nickname)?;
+    Ok(())
+}
+
+fn group_remove(name: &str, nickname: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.group_member_delete(name, nickname)?;
+    Ok(())
+}
+
+fn group_delete(name: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.group_delete(name)?;
+    Ok(())
+}
+
+fn group_read() -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    for group in vault.group_read()? {
+        println!("{}:{}", group.name, group.members.join(" "));
+    }
+    Ok(())
+}
+
+fn folder_create(path: &str, nicknames: Vec<String>) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    let id = vault.shared_folder_create(path, nicknames)?;
+    println!("{}", id);
+    Ok(())
+}
+
+fn folder_join(id: Uuid, path: &str, nicknames: Vec<String>) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.shared_folder_join(id, path, nicknames)?;
+    Ok(())
+}
+
+fn folder_leave(path: &str) -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    vault.shared_folder_leave(path)?;
+    Ok(())
+}
+
+fn folder_read() -> anyhow::Result<()> {
+    let vault = Vault::new(&std::env::current_dir()?)?;
+    for folder in vault.settings_read()?.shared_folders {
+        println!(
+            "{}:{}:{}",
+            folder.path.display(),
+            folder.id,
+            folder.members.join(" ")
+        );
+    }
+    Ok(())
+}
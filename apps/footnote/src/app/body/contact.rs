@@ -3,9 +3,11 @@ use dioxus::prelude::*;
 use footnote_core::model::contact::Contact;
 use footnote_core::model::device::Device;
 use footnote_core::util::sync_status_record::SyncDirection;
+use std::path::Component;
 
 use crate::context::app_context::AppContext;
 use crate::context::sync_status_context::SyncStatusContext;
+use crate::route::Route;
 
 #[component]
 pub fn ContactBrowser() -> Element {
@@ -61,7 +63,27 @@ fn ContactRow(contact: Contact) -> Element {
                 }
             }
             if expanded() {
+                StorageUsageRow { nickname: contact.nickname.clone() }
                 DeviceItems { devices: contact.devices.clone() }
+                SharePreview { nickname: contact.nickname.clone() }
+            }
+        }
+    }
+}
+
+/// space this contact's shared notes take on this device
+#[component]
+fn StorageUsageRow(nickname: String) -> Element {
+    let app_context = use_context::<AppContext>();
+    let report = app_context.vault.read().contact_storage_report();
+    let entry = report
+        .ok()
+        .and_then(|r| r.into_iter().find(|(n, _, _)| n == &nickname));
+
+    rsx! {
+        if let Some((_, usage, quota)) = entry {
+            div { class: "px-6 py-3 bg-zinc-900/20 border-t border-zinc-800 text-xs text-zinc-400",
+                "Storing {usage.files} of {quota.max_files} notes, {format_bytes(usage.bytes)} of {format_bytes(quota.max_total_bytes)}"
             }
         }
     }
@@ -82,6 +104,13 @@ fn DeviceItems(devices: Vec<Device>) -> Element {
 
 #[component]
 fn DeviceItem(device: Device) -> Element {
+    let app_context = use_context::<AppContext>();
+    let presence = app_context
+        .vault
+        .read()
+        .device_presence(&device.iroh_endpoint_id)
+        .ok()
+        .flatten();
     let sync_status_context = use_context::<SyncStatusContext>();
     let outbound_status =
         sync_status_context.get(&device.iroh_endpoint_id, SyncDirection::Outbound);
@@ -94,6 +123,23 @@ fn DeviceItem(device: Device) -> Element {
                 span { class: "text-sm font-medium text-zinc-300", "{device.name}" }
                 span { class: "text-xs font-mono text-zinc-500", "{truncated_id}" }
             }
+            if let Some(metadata) = &device.metadata {
+                div { class: "text-xs text-zinc-500", "{metadata.summary()}" }
+            }
+
+            match presence {
+                Some(presence) => rsx! {
+                    div { class: "text-xs text-zinc-400",
+                        "Last seen {presence.last_seen.relative_time_string()}"
+                        if let Some(version) = presence.app_version {
+                            ", running v{version}"
+                        }
+                    }
+                },
+                None => rsx! {
+                    div { class: "text-xs text-zinc-500", "Not seen yet" }
+                },
+            }
 
             if let Some(status) = outbound_status {
                 if let Some(current) = status.current {
@@ -160,6 +206,16 @@ fn DeviceItem(device: Device) -> Element {
                         "No record of failed incoming transfer"
                     }
                 }
+                if let Some(quota_hit) = status.last_quota_hit {
+                    div { class: "text-xs text-red-100",
+                        "Stopped receiving files, {quota_hit.reason} {quota_hit.at.relative_time_string()}"
+                    }
+                }
+                for rejected in status.rejected_files {
+                    div { class: "text-xs text-zinc-500",
+                        "Rejected {rejected.filename}: {rejected.reason}"
+                    }
+                }
             } else {
                 div { class: "text-xs text-zinc-500",
                     "No incoming sync record"
@@ -169,6 +225,73 @@ fn DeviceItem(device: Device) -> Element {
     }
 }
 
+/// the notes this contact will receive on the next share
+#[component]
+fn SharePreview(nickname: String) -> Element {
+    let app_context = use_context::<AppContext>();
+    let nav = navigator();
+    let preview = app_context.vault.read().share_preview(&nickname);
+
+    rsx! {
+        div { class: "px-6 pb-4 bg-zinc-900/20 border-t border-zinc-800",
+            div { class: "text-sm font-medium text-zinc-300 pt-4 mb-2", "Shared notes" }
+            match preview {
+                Ok(entries) if entries.is_empty() => rsx! {
+                    div { class: "text-xs text-zinc-500", "Nothing is shared with {nickname}" }
+                },
+                Ok(entries) => rsx! {
+                    div { class: "space-y-1",
+                        for preview_entry in entries {
+                            {
+                                let path = preview_entry.entry.path.to_string_lossy().to_string();
+                                let segments: Vec<String> = preview_entry
+                                    .entry
+                                    .path
+                                    .components()
+                                    .filter_map(|component| match component {
+                                        Component::Normal(os_str) => Some(os_str.to_string_lossy().into_owned()),
+                                        _ => None,
+                                    })
+                                    .collect();
+                                let reasons: Vec<String> =
+                                    preview_entry.reasons.iter().map(|r| r.to_string()).collect();
+                                let reasons = reasons.join(", ");
+
+                                rsx! {
+                                    button {
+                                        key: "{path}",
+                                        class: "w-full flex items-center justify-between text-left text-xs hover:bg-zinc-800 rounded px-1 py-0.5",
+                                        onclick: move |_| {
+                                            nav.push(Route::NoteView { vault_relative_path_segments: segments.clone() });
+                                        },
+                                        span { class: "font-mono text-zinc-300", "{path}" }
+                                        span { class: "text-zinc-500", "{reasons}" }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                Err(e) => rsx! {
+                    div { class: "text-xs text-red-100", "Could not build share preview: {e}" }
+                },
+            }
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else if bytes >= KIB {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
 fn truncate_endpoint_id(id: &str) -> String {
     if id.len() > 9 {
         format!("{}...{}", &id[..4], &id[id.len() - 5..])
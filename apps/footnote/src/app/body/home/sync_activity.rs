@@ -1,5 +1,6 @@
 use dioxus::prelude::*;
 use footnote_core::model::device::Device;
+use footnote_core::util::sync_status_record::SyncDirection;
 
 use crate::context::app_context::AppContext;
 use crate::context::menu_context::MenuContext;
@@ -130,6 +131,44 @@ pub fn SyncActivity() -> Element {
                 }
             }
 
+            if !available_devices.is_empty() {
+                div { class: "px-6 py-3 border-b border-zinc-800 space-y-1",
+                    span { class: "text-xs font-semibold font-mono text-zinc-500", "Connections" }
+                    for device in &available_devices {
+                        {
+                            let ping = sync_context.ping(&device.iroh_endpoint_id);
+                            let failure = sync_context
+                                .get(&device.iroh_endpoint_id, SyncDirection::Outbound)
+                                .and_then(|s| {
+                                    // a push that succeeded since makes the failure moot
+                                    let succeeded_at = s.last_success.map(|ok| ok.completed_at);
+                                    s.last_failure.filter(|f| Some(f.failed_at) > succeeded_at)
+                                });
+                            rsx! {
+                                div { class: "flex items-center justify-between text-xs",
+                                    span { class: "text-zinc-300 font-mono", "{device.name}" }
+                                    match ping {
+                                        Some(ping) => rsx! {
+                                            span { class: "text-zinc-500 ml-4 truncate",
+                                                "{ping.summary()} ({ping.checked_at.relative_time_string()})"
+                                            }
+                                        },
+                                        None => rsx! {
+                                            span { class: "text-zinc-600 ml-4", "not checked" }
+                                        },
+                                    }
+                                }
+                                if let Some(failure) = failure {
+                                    div { class: "text-xs text-red-400/80 truncate",
+                                        "last push failed {failure.failed_at.relative_time_string()}: {failure.error}"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
             if recent_files.read().is_empty() {
                 div {
                     class: "px-6 py-8 text-sm text-zinc-500 text-center",
@@ -12,6 +12,7 @@ use std::path::PathBuf;
 use uuid::Uuid;
 
 use footnote_core::model::note::Note;
+use footnote_core::util::hybrid_timestamp::HybridTimestamp;
 use footnote_core::util::lamport_timestamp::LamportTimestamp;
 use footnote_core::util::manifest::find_responses;
 use footnote_core::util::sync_status_record::RecentFile;
@@ -48,7 +49,7 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
     let mut footnotes = use_signal(move || IndexMap::new());
     let mut loaded_note_full_path = use_signal(move || String::new());
     let mut loaded_note_uuid = use_signal(move || Option::<Uuid>::None);
-    let mut loaded_note_timestamp = use_signal(move || Option::<LamportTimestamp>::None);
+    let mut loaded_note_timestamp = use_signal(move || Option::<HybridTimestamp>::None);
     // responses: Vec<(relative_path, content)> for inline display (author view)
     let mut responses = use_signal(|| Vec::<(String, String)>::new());
     // responder state: editable response textarea
@@ -195,7 +196,10 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                 let result = tombstone_create(
                     &app_context.vault.read().base_path(),
                     note_uuid,
-                    LamportTimestamp::new(loaded_note_timestamp()),
+                    app_context
+                        .vault
+                        .read()
+                        .version_after(loaded_note_timestamp()),
                 )
                 .await;
                 if let Err(e) = result {
@@ -221,7 +225,7 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
             Ok(n) => n,
             Err(e) => {
                 tracing::info!("failed to load note for RMW, {}", e);
-                Note::new()
+                Note::new(&app_context.vault.read().clock())
             }
         };
 
@@ -251,7 +255,8 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                 note_copy.content = note_body;
                 note_copy.footnotes = footnotes_vec;
 
-                if let Err(e) = note_copy.to_file(&full_path) {
+                let clock = app_context.vault.read().clock();
+                if let Err(e) = note_copy.to_file(&full_path, &clock) {
                     tracing::error!("Failed to save note: {e}");
                     save_status.set(SaveStatus::Unsaved);
                     return;
@@ -259,7 +264,7 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                 record_local_edit_as_sync_record(
                     &full_path,
                     note_copy.frontmatter.uuid,
-                    note_copy.frontmatter.modified,
+                    note_copy.frontmatter.modified.to_lamport(),
                 );
 
                 loaded_note_full_path.set(full_path.to_string_lossy().to_string());
@@ -455,6 +460,9 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                         if !app_context.contacts.read().is_empty() {
                             ShareDropdown {
                                 share_with: share_with,
+                                relative_path: relative_path(),
+                                note_uuid: loaded_note_uuid(),
+                                note_modified: loaded_note_timestamp(),
                                 on_change: move |_| save_status.set(SaveStatus::Unsaved),
                             }
                         }
@@ -539,10 +547,11 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                                                 let _ = fs::create_dir_all(parent);
                                             }
 
+                                            let clock = app_context.vault.read().clock();
                                             let mut reply_note = if reply_path.exists() {
-                                                Note::from_path(&reply_path, true).unwrap_or_else(|_| Note::new())
+                                                Note::from_path(&reply_path, true).unwrap_or_else(|_| Note::new(&clock))
                                             } else {
-                                                let mut n = Note::new();
+                                                let mut n = Note::new(&clock);
                                                 n.frontmatter.reply_to = Some(target_uuid);
                                                 if !owner.is_empty() {
                                                     n.frontmatter.share_with = vec![owner];
@@ -551,7 +560,7 @@ pub fn NoteView(vault_relative_path_segments: ReadSignal<Vec<String>>) -> Elemen
                                             };
 
                                             reply_note.content = response_body();
-                                            match reply_note.to_file(&reply_path) {
+                                            match reply_note.to_file(&reply_path, &clock) {
                                                 Ok(_) => response_save_status.set(SaveStatus::Saved),
                                                 Err(e) => {
                                                     tracing::error!("failed to save response: {e}");
@@ -1,14 +1,31 @@
 use dioxus::prelude::*;
 
+use std::path::Path;
+
 use crate::context::app_context::AppContext;
 
-use footnote_core::model::contact::Contact;
+use footnote_core::model::group::{expand_share_names, ContactGroup};
+use footnote_core::util::delivery_ledger::{DeliveryLedger, DeliveryStatus};
+use footnote_core::util::hybrid_timestamp::HybridTimestamp;
+use uuid::Uuid;
+
+#[derive(Clone, PartialEq)]
+struct ShareTarget {
+    name: String,
+    is_group: bool,
+}
 
 #[component]
-pub fn ShareDropdown(share_with: Signal<String>, on_change: EventHandler<()>) -> Element {
+pub fn ShareDropdown(
+    share_with: Signal<String>,
+    relative_path: String,
+    note_uuid: Option<Uuid>,
+    note_modified: Option<HybridTimestamp>,
+    on_change: EventHandler<()>,
+) -> Element {
     let app_context = use_context::<AppContext>();
     let mut show_dropdown = use_signal(|| false);
-    let mut sorted_contacts = use_signal(Vec::<Contact>::new);
+    let mut sorted_targets = use_signal(Vec::<ShareTarget>::new);
 
     let current_shares: Vec<String> = share_with
         .read()
@@ -16,6 +33,21 @@ pub fn ShareDropdown(share_with: Signal<String>, on_change: EventHandler<()>) ->
         .map(|s| s.to_string())
         .collect();
 
+    let groups: Vec<ContactGroup> = app_context.groups.read().clone();
+
+    // share rules live in vault settings, they can't be toggled per note
+    let rule_names: Vec<String> = match app_context.vault.read().settings_read() {
+        Ok(settings) => settings.rule_share_with(Path::new(&relative_path)),
+        Err(e) => {
+            tracing::warn!("could not read share rules: {}", e);
+            Vec::new()
+        }
+    };
+    let mut rule_shares = expand_share_names(&rule_names, &groups);
+    rule_shares.extend(rule_names.iter().cloned());
+    let group_shares = expand_share_names(&current_shares, &groups);
+    let sort_rule_shares = rule_shares.clone();
+
     rsx! {
         div {
             class: "relative",
@@ -25,27 +57,37 @@ pub fn ShareDropdown(share_with: Signal<String>, on_change: EventHandler<()>) ->
                 onclick: move |_| {
                     if !show_dropdown() {
                         let contacts = app_context.contacts.read();
+                        let groups = app_context.groups.read();
                         let shares: Vec<String> = share_with
                             .read()
                             .split_whitespace()
                             .map(|s| s.to_string())
                             .collect();
 
-                        let mut sorted: Vec<_> = contacts.iter().cloned().collect();
+                        let mut sorted: Vec<ShareTarget> = groups
+                            .iter()
+                            .map(|g| ShareTarget { name: g.name.clone(), is_group: true })
+                            .chain(contacts.iter().map(|c| ShareTarget {
+                                name: c.nickname.clone(),
+                                is_group: false,
+                            }))
+                            .collect();
                         sorted.sort_by(|a, b| {
-                            let a_selected = shares.contains(&a.nickname);
-                            let b_selected = shares.contains(&b.nickname);
+                            let a_selected = shares.contains(&a.name)
+                                || sort_rule_shares.contains(&a.name);
+                            let b_selected = shares.contains(&b.name)
+                                || sort_rule_shares.contains(&b.name);
                             match (a_selected, b_selected) {
                                 (true, false) => std::cmp::Ordering::Less,
                                 (false, true) => std::cmp::Ordering::Greater,
-                                _ => a.nickname.cmp(&b.nickname),
+                                _ => b.is_group.cmp(&a.is_group).then(a.name.cmp(&b.name)),
                             }
                         });
-                        sorted_contacts.set(sorted);
+                        sorted_targets.set(sorted);
                     }
                     show_dropdown.toggle();
                 },
-                if share_with.read().len() > 0 {
+                if share_with.read().len() > 0 || !rule_shares.is_empty() {
                     "Shared"
                 }
                 else {
@@ -60,21 +102,42 @@ pub fn ShareDropdown(share_with: Signal<String>, on_change: EventHandler<()>) ->
                 }
 
                 div {
-                    class: "absolute top-full left-0 mt-1 w-48 bg-zinc-900 border border-zinc-700 rounded-md shadow-2xl z-50",
+                    class: "absolute top-full left-0 mt-1 w-64 bg-zinc-900 border border-zinc-700 rounded-md shadow-2xl z-50",
                     onclick: move |e| e.stop_propagation(),
 
                     div {
                         class: "py-1",
 
-                        for contact in sorted_contacts.read().iter() {
+                        for target in sorted_targets.read().iter() {
                             {
-                                let nickname = contact.nickname.clone();
-                                let is_selected = current_shares.contains(&nickname);
-                                let name_for_click = nickname.clone();
+                                let name = target.name.clone();
+                                let is_group = target.is_group;
+                                let is_selected = current_shares.contains(&name);
+                                let is_rule_shared = rule_shares.contains(&name);
+                                let is_group_shared =
+                                    !is_group && !is_selected && group_shares.contains(&name);
+                                let member_count = groups
+                                    .iter()
+                                    .find(|g| is_group && g.name == name)
+                                    .map(|g| g.members.len())
+                                    .unwrap_or(0);
+                                let name_for_click = name.clone();
+                                let is_shared =
+                                    !is_group && (is_selected || is_rule_shared || is_group_shared);
+                                // the saved version is what the next share delivers
+                                let delivery = match (is_shared, note_uuid, note_modified) {
+                                    (true, Some(uuid), Some(modified)) => {
+                                        let vault_path = app_context.vault.read().base_path();
+                                        DeliveryLedger::read(&vault_path, &name)
+                                            .map(|ledger| ledger.status(&uuid, modified))
+                                            .ok()
+                                    }
+                                    _ => None,
+                                };
 
                                 rsx! {
                                     button {
-                                        key: "{nickname}",
+                                        key: "{name}",
                                         class: "w-full px-3 py-2 text-left text-sm text-zinc-300 hover:bg-zinc-800 flex items-center gap-3",
                                         onclick: move |_| {
                                             let mut shares: Vec<String> = share_with
@@ -102,7 +165,28 @@ pub fn ShareDropdown(share_with: Signal<String>, on_change: EventHandler<()>) ->
                                                 ""
                                             }
                                         },
-                                        span { "{nickname}" }
+                                        if is_group {
+                                            span { "{name} ({member_count})" }
+                                        } else {
+                                            span { "{name}" }
+                                        }
+                                        div {
+                                            class: "ml-auto flex flex-col items-end text-xs",
+                                            if is_rule_shared {
+                                                span { class: "text-zinc-500", "shared via folder rule" }
+                                            } else if is_group_shared {
+                                                span { class: "text-zinc-500", "shared via group" }
+                                            }
+                                            match delivery {
+                                                Some(DeliveryStatus::Delivered) => rsx! {
+                                                    span { class: "text-green-500", "delivered" }
+                                                },
+                                                Some(DeliveryStatus::Pending) => rsx! {
+                                                    span { class: "text-yellow-500", "pending" }
+                                                },
+                                                None => rsx! {},
+                                            }
+                                        }
                                     }
                                 }
                             }
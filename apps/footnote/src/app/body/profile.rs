@@ -82,15 +82,21 @@ fn EditUsernameModal(oncancel: EventHandler) -> Element {
     });
     let mut err_message = use_signal(|| String::new());
 
-    let save_username = move |_| {
+    let save_username = move |_| async move {
         let vault = app_context.vault.read().clone();
-        match vault.user_update(username.read().as_str()) {
-            Ok(_) => {
+        let username = username.read().clone();
+        // waits on the vault lock if the CLI holds it
+        let result = tokio::task::spawn_blocking(move || vault.user_update(&username)).await;
+        match result {
+            Ok(Ok(_)) => {
                 if let Err(e) = app_context.reload() {
                     tracing::warn!("failed to reload app: {}", e);
                 }
                 oncancel.call(());
             }
+            Ok(Err(e)) => {
+                err_message.set(format!("Error updating username: {}", e));
+            }
             Err(e) => {
                 err_message.set(format!("Error updating username: {}", e));
             }
@@ -198,23 +204,38 @@ fn DeviceRow(device: Device, read_only: bool) -> Element {
         sync_status_context.get(&device.iroh_endpoint_id, SyncDirection::Outbound);
     let inbound_status = sync_status_context.get(&device.iroh_endpoint_id, SyncDirection::Inbound);
     let truncated_id = truncate_endpoint_id(&device.iroh_endpoint_id);
+    let presence = app_context
+        .vault
+        .read()
+        .device_presence(&device.iroh_endpoint_id)
+        .ok()
+        .flatten();
 
     let mut delete_dialog_open = use_signal(|| false);
     let mut delete_dialog_error = use_signal(|| String::new());
     let device_id = device.iroh_endpoint_id.clone();
     let delete_app_context = app_context.clone();
-    let delete_device_confirm =
-        move || match delete_app_context.vault.read().device_delete(&device_id) {
-            Ok(_) => {
-                if let Err(e) = app_context.reload() {
-                    tracing::warn!("failed to reload app: {}", e);
+    let delete_device_confirm = move || {
+        let vault = delete_app_context.vault.read().clone();
+        let device_id = device_id.clone();
+        spawn(async move {
+            let result = tokio::task::spawn_blocking(move || vault.device_delete(&device_id)).await;
+            match result {
+                Ok(Ok(_)) => {
+                    if let Err(e) = app_context.reload() {
+                        tracing::warn!("failed to reload app: {}", e);
+                    }
+                    delete_dialog_open.set(false);
+                }
+                Ok(Err(e)) => {
+                    delete_dialog_error.set(format!("{}", e));
+                }
+                Err(e) => {
+                    delete_dialog_error.set(format!("{}", e));
                 }
-                delete_dialog_open.set(false);
-            }
-            Err(e) => {
-                delete_dialog_error.set(format!("{}", e));
             }
-        };
+        });
+    };
 
     rsx! {
         div { class: "flex items-start justify-between",
@@ -224,6 +245,9 @@ fn DeviceRow(device: Device, read_only: bool) -> Element {
                         span { class: "text-xs font-mono text-zinc-500 mb-2", "{truncated_id}" }
                     }
                 }
+                if let Some(metadata) = &device.metadata {
+                    div { class: "text-xs text-zinc-500 mb-1", "{metadata.summary()}" }
+                }
                 if !read_only {
                     button {
                         class: "p-1 text-zinc-500 hover:text-zinc-100 hover:bg-zinc-800 rounded transition-all",
@@ -249,6 +273,19 @@ fn DeviceRow(device: Device, read_only: bool) -> Element {
                     }
                 }
                 else {
+                    match presence {
+                        Some(presence) => rsx! {
+                            div { class: "text-xs text-zinc-400",
+                                "Last seen {presence.last_seen.relative_time_string()}"
+                                if let Some(version) = presence.app_version {
+                                    ", running v{version}"
+                                }
+                            }
+                        },
+                        None => rsx! {
+                            div { class: "text-xs text-zinc-500", "Not seen yet" }
+                        },
+                    }
                     if let Some(status) = outbound_status {
                         if let Some(current) = status.current {
                             div { class: "text-xs text-zinc-400",
@@ -353,15 +390,24 @@ fn EditDeviceNameModal(device_id: String, current_name: String, oncancel: EventH
 
     let save_device_name = move |_| {
         let vault = app_context.vault.read().clone();
-        match vault.device_update(&device_id, device_name.read().as_str()) {
-            Ok(_) => {
-                if let Err(e) = app_context.reload() {
-                    tracing::warn!("failed to reload app: {}", e);
+        let device_id = device_id.clone();
+        let name = device_name.read().clone();
+        async move {
+            let result =
+                tokio::task::spawn_blocking(move || vault.device_update(&device_id, &name)).await;
+            match result {
+                Ok(Ok(_)) => {
+                    if let Err(e) = app_context.reload() {
+                        tracing::warn!("failed to reload app: {}", e);
+                    }
+                    oncancel.call(());
+                }
+                Ok(Err(e)) => {
+                    err_message.set(format!("Error updating device name: {}", e));
+                }
+                Err(e) => {
+                    err_message.set(format!("Error updating device name: {}", e));
                 }
-                oncancel.call(());
-            }
-            Err(e) => {
-                err_message.set(format!("Error updating device name: {}", e));
             }
         }
     };
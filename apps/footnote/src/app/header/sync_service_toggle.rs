@@ -1,12 +1,13 @@
 use dioxus::prelude::*;
 
-use iroh::Endpoint;
-use tokio::time::{interval, Duration};
+use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 
-use footnote_core::model::vault::Vault;
+use footnote_core::service::push_service::PushService;
 use footnote_core::service::sync_service::SyncService;
-use footnote_core::service::ALPN_SYNC;
+use footnote_core::service::{ALPN_PING, ALPN_SYNC};
+use footnote_core::util::change_signal::{ChangeSignal, VaultWatcher};
+use footnote_core::util::endpoint;
 
 use crate::context::app_context::AppContext;
 use crate::context::sync_status_context::SyncStatusContext;
@@ -47,6 +48,7 @@ pub fn SyncServiceToggle() -> Element {
                 tracing::warn!("could not get secret key");
                 return;
             };
+            let endpoint_vault = vault.clone();
             let send_vault_clone = vault.clone();
             let listen_vault_clone = vault.clone();
 
@@ -65,28 +67,47 @@ pub fn SyncServiceToggle() -> Element {
             });
 
             tokio::spawn(async move {
-                let Ok(endpoint) = Endpoint::builder()
-                    .secret_key(secret_key.clone())
-                    .alpns(vec![ALPN_SYNC.to_vec()])
-                    .bind()
-                    .await
+                let alpns = vec![ALPN_SYNC.to_vec(), ALPN_PING.to_vec()];
+                let Ok(endpoint) =
+                    endpoint::endpoint_bind(&endpoint_vault, secret_key, alpns).await
                 else {
                     tracing::warn!("could not create endpoint");
                     return;
                 };
 
+                let (connects_tx, connects_rx) = tokio::sync::mpsc::channel(32);
                 let endpoint_clone = endpoint.clone();
                 tokio::spawn(async move {
                     tracing::info!("spawning listen thread");
-                    let _ =
-                        SyncService::listen(listen_vault_clone, endpoint_clone, listen_token_clone)
-                            .await;
+                    let _ = SyncService::listen_notifying(
+                        listen_vault_clone,
+                        endpoint_clone,
+                        listen_token_clone,
+                        connects_tx,
+                    )
+                    .await;
                 });
 
                 let endpoint_clone = endpoint.clone();
                 tokio::spawn(async move {
                     tracing::info!("spawning change push thread");
-                    push_changes(send_vault_clone, endpoint_clone, send_token_clone).await;
+                    let changes = ChangeSignal::new();
+                    // the watcher stops when dropped, keep it for the life of the push loop
+                    let _watcher =
+                        match VaultWatcher::start(&send_vault_clone.base_path(), changes.clone()) {
+                            Ok(watcher) => Some(watcher),
+                            Err(e) => {
+                                tracing::warn!(
+                                    "could not watch vault, pushing on timer only: {}",
+                                    e
+                                );
+                                None
+                            }
+                        };
+                    PushService::new(send_vault_clone, endpoint_clone, changes)
+                        .connects(connects_rx)
+                        .run(send_token_clone)
+                        .await;
                 });
             });
         }
@@ -102,34 +123,3 @@ pub fn SyncServiceToggle() -> Element {
         }
     }
 }
-
-async fn push_changes(vault: Vault, endpoint: Endpoint, cancel_token: CancellationToken) {
-    tracing::info!("start push changes loop");
-    let mut sync_interval = interval(Duration::from_secs(60));
-    loop {
-        tracing::info!("tokio::select");
-        tokio::select! {
-            _ = cancel_token.cancelled() => {
-                tracing::info!("tokio::select:: push_changes cancelled");
-                break;
-            }
-            _ = sync_interval.tick() => {
-                tracing::info!("tokio::select:: tick");
-                let devices = vault.device_read().unwrap_or_default();
-                for device in devices {
-                    tracing::info!("attempting to push changes to {}", device.name);
-                    if let Err(e) = SyncService::mirror_to_device(&vault, endpoint.clone(), &device.name).await {
-                        tracing::warn!("Failed to sync to {}: {}", device.name, e);
-                    }
-                }
-                let contacts = vault.contact_read().unwrap_or_default();
-                for contact in contacts {
-                    tracing::info!("attempting to share with {}", contact.nickname);
-                    if let Err(e) = SyncService::share_to_contact(&vault, endpoint.clone(), &contact.nickname).await {
-                        tracing::warn!("Failed to share with {}: {}", contact.nickname, e);
-                    }
-                }
-            }
-        }
-    }
-}
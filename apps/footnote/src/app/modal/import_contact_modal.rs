@@ -21,15 +21,22 @@ pub fn ImportContactModal() -> Element {
         }
     });
 
-    let import_contact = move |_| {
+    let import_contact = move |_| async move {
         let vault = app_context.vault.read().clone();
-        match vault.contact_import(&nickname.read().clone(), &contact_json.read().clone()) {
-            Ok(()) => {
+        let nickname = nickname.read().clone();
+        let contact_json = contact_json.read().clone();
+        let importing = vault.clone();
+        let result =
+            tokio::task::spawn_blocking(move || importing.contact_import(&nickname, &contact_json))
+                .await;
+        match result {
+            Ok(Ok(())) => {
                 app_context
                     .contacts
                     .set(vault.contact_read().expect("could not load contacts"));
                 consume_context::<MenuContext>().close_all();
             }
+            Ok(Err(e)) => err_message.set(format!("Failed to import contact: {e}")),
             Err(e) => err_message.set(format!("Failed to import contact: {e}")),
         };
     };
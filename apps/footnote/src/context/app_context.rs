@@ -2,6 +2,7 @@ use dioxus::prelude::*;
 
 use footnote_core::model::contact::Contact;
 use footnote_core::model::device::Device;
+use footnote_core::model::group::ContactGroup;
 use footnote_core::model::vault::{Vault, VaultState};
 use footnote_core::util::manifest::{create_manifest_local, Manifest};
 
@@ -10,6 +11,7 @@ use footnote_core::util::manifest::{create_manifest_local, Manifest};
 /// Vault: mostly just a path,
 /// Devices: rarely changed list of devices, needs a trigger when it changes
 /// Contacts: infrequently changed list of contacts, needs a trigger
+/// Groups: named sets of contacts, changes with contacts
 /// FileList: might be special and might not belong in the AppContext
 #[derive(Clone, Copy)]
 pub struct AppContext {
@@ -17,6 +19,7 @@ pub struct AppContext {
     pub vault_state: Signal<VaultState>,
     pub devices: Signal<Vec<Device>>,
     pub contacts: Signal<Vec<Contact>>,
+    pub groups: Signal<Vec<ContactGroup>>,
     pub manifest: Signal<Manifest>,
     pub user: Signal<Option<Contact>>,
 }
@@ -28,6 +31,7 @@ impl AppContext {
             vault_state: Signal::new(vault.state_read().unwrap_or(VaultState::Uninitialized)),
             devices: Signal::new(vault.device_read().expect("could not load devices")),
             contacts: Signal::new(vault.contact_read().expect("could not load contacts")),
+            groups: Signal::new(vault.group_read().unwrap_or_default()),
             manifest: Signal::new(
                 create_manifest_local(&vault.base_path())
                     .expect("could not load local list of files"),
@@ -42,6 +46,7 @@ impl AppContext {
             .set(vault.state_read().unwrap_or(VaultState::Uninitialized));
         self.devices.set(vault.device_read()?);
         self.contacts.set(vault.contact_read()?);
+        self.groups.set(vault.group_read()?);
         self.user.set(vault.user_read().ok().flatten());
         Ok(())
     }
@@ -6,12 +6,14 @@ use std::collections::HashSet;
 
 use footnote_core::{
     model::{device::Device, vault::Vault},
+    service::ping_service::PingReport,
     util::sync_status_record::{RecentFile, SyncDirection, SyncStatusRecord},
 };
 
 #[derive(Clone, Copy)]
 pub struct SyncStatusContext {
     statuses: Signal<HashMap<(String, SyncDirection), SyncStatusRecord>>,
+    pings: Signal<HashMap<String, PingReport>>,
     vault_path: Signal<std::path::PathBuf>,
 }
 
@@ -19,10 +21,14 @@ impl SyncStatusContext {
     pub fn new(vault: &Vault) -> Self {
         let vault_path = vault.base_path();
         let mut statuses = HashMap::new();
+        let mut pings = HashMap::new();
 
         if let Ok(entries) = std::fs::read_dir(vault_path.join(".footnote").join("status")) {
             for entry in entries.flatten() {
                 let endpoint_id = entry.file_name().to_string_lossy().to_string();
+                if let Ok(Some(ping)) = PingReport::read(&vault_path, &endpoint_id) {
+                    pings.insert(endpoint_id.clone(), ping);
+                }
 
                 for direction in [SyncDirection::Inbound, SyncDirection::Outbound] {
                     if let Ok(Some(mut status)) =
@@ -40,6 +46,7 @@ impl SyncStatusContext {
 
         Self {
             statuses: Signal::new(statuses),
+            pings: Signal::new(pings),
             vault_path: Signal::new(vault_path),
         }
     }
@@ -51,6 +58,11 @@ impl SyncStatusContext {
             .cloned()
     }
 
+    /// the last reachability check of a peer endpoint
+    pub fn ping(&self, endpoint_id: &str) -> Option<PingReport> {
+        self.pings.read().get(endpoint_id).cloned()
+    }
+
     pub fn set(&mut self, record: SyncStatusRecord) {
         self.statuses.write().insert(
             (record.endpoint_id.clone(), record.direction.clone()),
@@ -61,10 +73,14 @@ impl SyncStatusContext {
     pub fn reload_all(&mut self) -> Result<()> {
         let vault_path = self.vault_path.read().clone();
         let mut statuses = HashMap::new();
+        let mut pings = HashMap::new();
 
         if let Ok(entries) = std::fs::read_dir(vault_path.join(".footnote").join("status")) {
             for entry in entries.flatten() {
                 let endpoint_id = entry.file_name().to_string_lossy().to_string();
+                if let Ok(Some(ping)) = PingReport::read(&vault_path, &endpoint_id) {
+                    pings.insert(endpoint_id.clone(), ping);
+                }
 
                 for direction in [SyncDirection::Inbound, SyncDirection::Outbound] {
                     if let Ok(Some(status)) =
@@ -77,6 +93,7 @@ impl SyncStatusContext {
         }
 
         self.statuses.set(statuses);
+        self.pings.set(pings);
         Ok(())
     }
 
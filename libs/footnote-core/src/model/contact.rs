@@ -150,6 +150,7 @@ impl Contact {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::device::{DeviceMetadata, DeviceRole};
     use ed25519_dalek::SigningKey;
     use rand_core::OsRng;
 
@@ -201,6 +202,32 @@ mod tests {
         contact.verify().unwrap();
     }
 
+    #[test]
+    fn test_device_metadata_is_signed() {
+        let signing_key = create_test_signing_key();
+        let verifying_key = signing_key.verifying_key();
+
+        let mut server = Device::new("server".to_string(), "abc123".to_string());
+        server.metadata = Some(DeviceMetadata::this_device(Some(DeviceRole::OftenOn)));
+        let mut contact = Contact {
+            format_version: FORMAT_VERSION,
+            username: "alice".to_string(),
+            nickname: "".to_string(),
+            id_public_key: hex::encode(verifying_key.to_bytes()),
+            device_leader: "abc123".to_string(),
+            devices: vec![server],
+            updated_at: LamportTimestamp(1000),
+            signature: String::new(),
+        };
+
+        contact.sign(&signing_key).unwrap();
+        Contact::from_json(&contact.to_json().unwrap()).unwrap();
+
+        let metadata = contact.devices[0].metadata.as_mut().unwrap();
+        metadata.role = Some(DeviceRole::Mobile);
+        assert!(contact.verify().is_err());
+    }
+
     #[test]
     fn test_verify_fails_with_wrong_signature() {
         let signing_key = create_test_signing_key();
@@ -1,9 +1,82 @@
 use serde::{Deserialize, Serialize};
 
+use crate::service::{ALPN_SYNC, APP_VERSION};
+
+/// what this build supports beyond plain sync, announced in its metadata
+pub const CAPABILITIES: &[&str] = &["bundle", "ping", "presence"];
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Device {
     pub name: String,
     pub iroh_endpoint_id: String,
+    /// the relay the device can be reached through, when it uses its own
+    /// instead of the public ones. left out of records that don't set it, so
+    /// their signatures still verify.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relay_url: Option<String>,
+    /// what the device is, signed with the rest of the record. left out like
+    /// the relay when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<DeviceMetadata>,
+}
+
+/// How a device is used. Roles from newer versions are kept as written, so
+/// a record that carries one still verifies.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeviceRole {
+    /// usually online, preferred for delivering shares
+    OftenOn,
+    Mobile,
+    Desktop,
+    #[serde(untagged)]
+    Other(String),
+}
+
+/// Optional description of a device, set by the device leader. Every field
+/// is optional so older and newer records read the same way.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeviceMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<DeviceRole>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+}
+
+impl DeviceMetadata {
+    /// metadata describing the running build
+    pub fn this_device(role: Option<DeviceRole>) -> Self {
+        Self {
+            platform: Some(std::env::consts::OS.to_string()),
+            role,
+            app_version: Some(APP_VERSION.to_string()),
+            protocol_version: Some(String::from_utf8_lossy(ALPN_SYNC).to_string()),
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// role, platform and version, "often-on, linux, v0.4.7"
+    pub fn summary(&self) -> String {
+        let parts: Vec<String> = [
+            self.role.as_ref().map(|r| r.to_string()),
+            self.platform.clone(),
+            self.app_version.as_ref().map(|v| format!("v{}", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        parts.join(", ")
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
 }
 
 impl Device {
@@ -11,10 +84,44 @@ impl Device {
         Self {
             name,
             iroh_endpoint_id,
+            relay_url: None,
+            metadata: None,
+        }
+    }
+
+    pub fn role(&self) -> Option<&DeviceRole> {
+        self.metadata.as_ref().and_then(|m| m.role.as_ref())
+    }
+
+    pub fn is_often_on(&self) -> bool {
+        self.role() == Some(&DeviceRole::OftenOn)
+    }
+}
+
+impl std::fmt::Display for DeviceRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceRole::OftenOn => write!(f, "often-on"),
+            DeviceRole::Mobile => write!(f, "mobile"),
+            DeviceRole::Desktop => write!(f, "desktop"),
+            DeviceRole::Other(role) => write!(f, "{}", role),
         }
     }
 }
 
+impl std::str::FromStr for DeviceRole {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "often-on" => DeviceRole::OftenOn,
+            "mobile" => DeviceRole::Mobile,
+            "desktop" => DeviceRole::Desktop,
+            other => DeviceRole::Other(other.to_string()),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -42,4 +149,33 @@ mod tests {
 
         assert_eq!(device, deserialized);
     }
+
+    #[test]
+    fn test_device_without_relay_serializes_as_before() {
+        let device = Device::new("phone".to_string(), "abc123".to_string());
+        assert_eq!(
+            serde_json::to_string(&device).unwrap(),
+            r#"{"name":"phone","iroh_endpoint_id":"abc123"}"#
+        );
+    }
+
+    #[test]
+    fn test_device_metadata_round_trip() {
+        let mut device = Device::new("server".to_string(), "abc123".to_string());
+        device.metadata = Some(DeviceMetadata::this_device(Some(DeviceRole::OftenOn)));
+
+        let json = serde_json::to_string(&device).unwrap();
+        assert!(json.contains(r#""role":"often-on""#));
+        let deserialized: Device = serde_json::from_str(&json).unwrap();
+        assert_eq!(device, deserialized);
+        assert!(deserialized.is_often_on());
+    }
+
+    #[test]
+    fn test_unknown_role_is_kept() {
+        let json = r#"{"name":"tv","iroh_endpoint_id":"abc123","metadata":{"role":"kiosk"}}"#;
+        let device: Device = serde_json::from_str(json).unwrap();
+        assert_eq!(device.role(), Some(&DeviceRole::Other("kiosk".to_string())));
+        assert_eq!(serde_json::to_string(&device).unwrap(), json);
+    }
 }
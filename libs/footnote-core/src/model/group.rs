@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// A named set of contacts, referred to by name in `share_with` or a share
+/// rule. Groups are local to the user, they are mirrored from the device
+/// leader like contacts but never sent to a share peer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ContactGroup {
+    pub name: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+impl ContactGroup {
+    pub fn new(name: &str, members: Vec<String>) -> Result<Self> {
+        let group = Self {
+            name: name.trim().to_string(),
+            members,
+        };
+        group.validate()?;
+        Ok(group)
+    }
+
+    /// the name becomes a filename in .footnote/groups
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(!self.name.is_empty(), "group name is empty");
+        anyhow::ensure!(
+            !self.name.starts_with('.') && !self.name.contains(['/', '\\']),
+            "group name {} is not a valid name",
+            self.name
+        );
+        anyhow::ensure!(
+            !self.name.contains(char::is_whitespace),
+            "group name {} cannot contain whitespace",
+            self.name
+        );
+        Ok(())
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Failed to read group file: {}", path.as_ref().display()))?;
+        let group: Self = serde_json::from_str(&content).context("Failed to parse group JSON")?;
+        group.validate()?;
+        Ok(group)
+    }
+
+    pub fn to_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize group")?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+}
+
+/// every name that refers to nickname in a share_with list: the nickname
+/// itself and the name of each group it is a member of
+pub fn share_names_for(nickname: &str, groups: &[ContactGroup]) -> Vec<String> {
+    let mut names = vec![nickname.to_string()];
+    for group in groups {
+        if group.members.iter().any(|m| m == nickname) {
+            names.push(group.name.clone());
+        }
+    }
+    names
+}
+
+/// expand group names in a share_with list to the nicknames they contain.
+/// names that are not groups are passed through as nicknames.
+pub fn expand_share_names(share_with: &[String], groups: &[ContactGroup]) -> Vec<String> {
+    let mut nicknames = Vec::new();
+    for name in share_with {
+        let members = match groups.iter().find(|g| &g.name == name) {
+            Some(group) => group.members.clone(),
+            None => vec![name.clone()],
+        };
+        for member in members {
+            if !nicknames.contains(&member) {
+                nicknames.push(member);
+            }
+        }
+    }
+    nicknames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups() -> Vec<ContactGroup> {
+        vec![
+            ContactGroup::new("family", vec!["mom".to_string(), "dad".to_string()]).unwrap(),
+            ContactGroup::new("book-club", vec!["mom".to_string(), "bob".to_string()]).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_share_names_for_member() {
+        assert_eq!(
+            share_names_for("mom", &groups()),
+            vec!["mom", "family", "book-club"]
+        );
+        assert_eq!(share_names_for("bob", &groups()), vec!["bob", "book-club"]);
+        assert_eq!(share_names_for("carol", &groups()), vec!["carol"]);
+    }
+
+    #[test]
+    fn test_expand_share_names() {
+        let share_with = vec!["family".to_string(), "bob".to_string(), "mom".to_string()];
+        assert_eq!(
+            expand_share_names(&share_with, &groups()),
+            vec!["mom", "dad", "bob"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_group_names() {
+        assert!(ContactGroup::new("", vec![]).is_err());
+        assert!(ContactGroup::new("../family", vec![]).is_err());
+        assert!(ContactGroup::new(".hidden", vec![]).is_err());
+        assert!(ContactGroup::new("book club", vec![]).is_err());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod contact;
 pub mod device;
+pub mod group;
 pub mod note;
+pub mod settings;
 pub mod user;
 pub mod vault;
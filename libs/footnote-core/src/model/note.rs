@@ -1,7 +1,10 @@
-use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::model::device::Device;
+use crate::util::hybrid_timestamp::{DeviceClock, HybridTimestamp};
+use crate::util::version_vector::VersionVector;
 use anyhow::{Context, Result};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
@@ -17,19 +20,38 @@ pub struct Note {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Frontmatter {
     pub uuid: Uuid,
-    pub modified: LamportTimestamp,
+    pub modified: HybridTimestamp,
+    #[serde(default, skip_serializing_if = "VersionVector::is_empty")]
+    pub versions: VersionVector,
     #[serde(default)]
     pub share_with: Vec<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub reply_to: Option<Uuid>,
+    /// id key of the contact who wrote the note, for a note a member of a
+    /// shared folder sent us. None for our own notes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// signature of the author's claim by one of their devices, see
+    /// `Note::sign_author`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_signature: Option<String>,
+    /// an append-only log, edits made on two devices at once are merged
+    /// line by line
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub log: bool,
+    /// the contacts it's shared with can edit it too, edits from everyone
+    /// are merged
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub collaborative: bool,
     #[serde(flatten)]
     extra: serde_yaml::Value,
 }
 
 impl Note {
-    pub fn new() -> Self {
+    /// an empty note, versioned as made with `clock`
+    pub fn new(clock: &DeviceClock) -> Self {
         Note {
-            frontmatter: Self::create_frontmatter(),
+            frontmatter: Self::create_frontmatter(clock),
             content: String::new(),
             footnotes: IndexMap::new(),
             loaded_from: None,
@@ -54,7 +76,8 @@ impl Note {
                         content,
                         e
                     );
-                    (Self::create_frontmatter(), 0)
+                    // stamped by the device that saves it
+                    (Self::create_frontmatter(&DeviceClock::default()), 0)
                 } else {
                     anyhow::bail!("failed to parse frontmatter");
                 }
@@ -146,17 +169,32 @@ impl Note {
         // Add footnotes at the bottom if there are any
         if !self.footnotes.is_empty() {
             result.push_str("\n\n");
-            for (id, text) in &self.footnotes {
-                result.push_str(&format!("[{}]: {}\n", id, text));
-            }
+            result.push_str(&self.footnotes_text());
         }
 
         Ok(result)
     }
 
-    pub fn to_file(&mut self, path: impl AsRef<Path>) -> Result<()> {
+    /// the footnote lines at the bottom of the note
+    pub fn footnotes_text(&self) -> String {
+        self.footnotes
+            .iter()
+            .map(|(id, text)| format!("[{}]: {}\n", id, text))
+            .collect()
+    }
+
+    pub fn set_footnotes_text(&mut self, text: &str) {
+        self.footnotes = text
+            .lines()
+            .filter(|line| line.starts_with('['))
+            .filter_map(Self::parse_footnote_line)
+            .collect();
+    }
+
+    /// Save the note as an edit made with `clock`.
+    pub fn to_file(&mut self, path: impl AsRef<Path>, clock: &DeviceClock) -> Result<()> {
         let path = path.as_ref();
-        self.frontmatter.modified = LamportTimestamp::new(Some(self.frontmatter.modified));
+        self.frontmatter.record_edit(clock);
 
         if let Some(old_path) = &self.loaded_from {
             if old_path != path && old_path.exists() {
@@ -179,10 +217,10 @@ impl Note {
         Ok(())
     }
 
-    pub fn create(path: &Path, content: &str) -> Result<Self> {
+    pub fn create(path: &Path, content: &str, clock: &DeviceClock) -> Result<Self> {
         let (body, footnotes) = Self::parse_body_and_footnotes(&content);
 
-        let frontmatter = Note::create_frontmatter();
+        let frontmatter = Note::create_frontmatter(clock);
         let mut note = Note {
             frontmatter,
             content: body,
@@ -190,26 +228,30 @@ impl Note {
             loaded_from: Some(path.to_path_buf()),
         };
 
-        note.to_file(path)?;
+        note.to_file(path, clock)?;
         Ok(note)
     }
 
-    fn create_frontmatter() -> Frontmatter {
+    fn create_frontmatter(clock: &DeviceClock) -> Frontmatter {
         Frontmatter {
             uuid: Uuid::new_v4(),
-            modified: LamportTimestamp::now(),
+            modified: clock.tick(None),
+            versions: VersionVector::default(),
             share_with: Vec::new(),
             reply_to: None,
+            author: None,
+            author_signature: None,
+            log: false,
+            collaborative: false,
             extra: serde_yaml::Value::Mapping(serde_yaml::Mapping::new()),
         }
     }
 
-    pub fn update(&mut self, path: &Path, content: &str) -> Result<()> {
+    pub fn update(&mut self, path: &Path, content: &str, clock: &DeviceClock) -> Result<()> {
         let (body, footnotes) = Self::parse_body_and_footnotes(&content);
         self.content = body;
         self.footnotes = footnotes;
-        self.frontmatter.modified = LamportTimestamp::new(Some(self.frontmatter.modified));
-        self.to_file(path)?;
+        self.to_file(path, clock)?;
         Ok(())
     }
 
@@ -218,18 +260,134 @@ impl Note {
         path: &Path,
         body: &str,
         footnotes: IndexMap<String, String>,
+        clock: &DeviceClock,
     ) -> Result<()> {
         self.content = body.to_string();
         self.footnotes = footnotes;
-        self.frontmatter.modified = LamportTimestamp::new(Some(self.frontmatter.modified));
-        self.to_file(path)?;
+        self.to_file(path, clock)?;
         Ok(())
     }
+
+    /// what an author's device signs to claim the note
+    fn authorship(&self, author: &str) -> Vec<u8> {
+        format!("footnote author {} {}", self.frontmatter.uuid, author).into_bytes()
+    }
+
+    /// Name `author` as the note's author, signed by one of their devices.
+    /// The claim covers the note, not its content, so members of a shared
+    /// folder can edit it and pass it on without changing who wrote it.
+    pub fn sign_author(&mut self, author: &str, device_key: &iroh::SecretKey) {
+        let signature = device_key.sign(&self.authorship(author));
+        self.frontmatter.author = Some(author.to_string());
+        self.frontmatter.author_signature = Some(hex::encode(signature.to_bytes()));
+    }
+
+    /// whether one of `devices` signed the author the note names
+    pub fn author_signed_by(&self, devices: &[Device]) -> bool {
+        let (Some(author), Some(signature)) =
+            (&self.frontmatter.author, &self.frontmatter.author_signature)
+        else {
+            return false;
+        };
+        let Some(signature) = hex::decode(signature)
+            .ok()
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .map(|bytes| iroh::Signature::from_bytes(&bytes))
+        else {
+            return false;
+        };
+        let message = self.authorship(author);
+        devices.iter().any(|device| {
+            device
+                .iroh_endpoint_id
+                .parse::<iroh::PublicKey>()
+                .is_ok_and(|key| key.verify(&message, &signature).is_ok())
+        })
+    }
+
+    pub fn is_log(&self) -> bool {
+        self.frontmatter.log
+    }
+
+    /// Add a line to the end of the note and save it.
+    pub fn append(&mut self, path: &Path, entry: &str, clock: &DeviceClock) -> Result<()> {
+        if !self.content.is_empty() {
+            self.content.push('\n');
+        }
+        self.content.push_str(entry.trim());
+        self.to_file(path, clock)
+    }
+
+    /// Take in the lines and footnotes of `other`, a version of this log
+    /// edited without seeing this one, as an edit on top of both made with
+    /// `clock`.
+    pub fn merge_log(&mut self, other: &Note, clock: &DeviceClock) {
+        self.content = merge_lines(&self.content, &other.content);
+        for (id, text) in &other.footnotes {
+            self.footnotes
+                .entry(id.clone())
+                .or_insert_with(|| text.clone());
+        }
+        self.merge_versions(other, clock);
+    }
+
+    /// Count this version as made after seeing `other` too, as an edit made
+    /// with `clock`.
+    pub fn merge_versions(&mut self, other: &Note, clock: &DeviceClock) {
+        self.frontmatter.modified = self.frontmatter.modified.max(other.frontmatter.modified);
+        self.frontmatter.versions.merge(&other.frontmatter.versions);
+        self.frontmatter.record_edit(clock);
+    }
+}
+
+/// Every line of ours and theirs, in order. A line only they have goes after
+/// the line it follows in theirs, and after the lines only we added there, so
+/// entries appended on both sides end up one side's after the other's. A line
+/// written twice counts twice.
+fn merge_lines(ours: &str, theirs: &str) -> String {
+    fn keyed(text: &str) -> Vec<(&str, usize)> {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        text.lines()
+            .map(|line| {
+                let n = seen.entry(line).or_default();
+                *n += 1;
+                (line, *n)
+            })
+            .collect()
+    }
+    let theirs = keyed(theirs);
+    let mut merged = keyed(ours);
+    let mut cursor = 0;
+    for line in &theirs {
+        if let Some(i) = merged.iter().position(|l| l == line) {
+            cursor = i + 1;
+            continue;
+        }
+        while cursor < merged.len() && !theirs.contains(&merged[cursor]) {
+            cursor += 1;
+        }
+        merged.insert(cursor, *line);
+        cursor += 1;
+    }
+    merged
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Frontmatter {
+    /// Stamp an edit made with `clock`.
+    fn record_edit(&mut self, clock: &DeviceClock) {
+        self.modified = clock.tick(Some(self.modified));
+        self.versions.increment(clock.device);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::model::vault::Vault;
 
     #[test]
     fn test_parse_note_with_frontmatter() {
@@ -336,9 +494,14 @@ Text with refs.
 
         let frontmatter = Frontmatter {
             uuid: Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap(),
-            modified: LamportTimestamp(1705316400),
+            modified: HybridTimestamp::from_secs(1705316400),
+            versions: VersionVector::default(),
             share_with: vec!["alice".to_string()],
             reply_to: None,
+            author: None,
+            author_signature: None,
+            log: false,
+            collaborative: false,
             extra: serde_yaml::Value::Mapping(frontmatter_map),
         };
 
@@ -484,6 +647,72 @@ Haidt [3]
             }
         }
     }
+
+    #[test]
+    fn test_edits_count_in_version_vector() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let vault = Vault::create_primary(dir.path(), "alice", "desktop").unwrap();
+        let path = dir.path().join("note.md");
+
+        let clock = vault.clock();
+
+        let created = Note::create(&path, "first", &clock).unwrap();
+        let mut edited = Note::from_path(&path, false).unwrap();
+        edited.update(&path, "second", &clock).unwrap();
+
+        let reread = Note::from_path(&path, false).unwrap();
+        assert_eq!(reread.frontmatter.modified.device, vault.version_device());
+        assert_eq!(
+            reread
+                .frontmatter
+                .versions
+                .compare(&created.frontmatter.versions),
+            Some(std::cmp::Ordering::Greater)
+        );
+        let mut twice = VersionVector::default();
+        twice.increment(clock.device);
+        twice.increment(clock.device);
+        assert_eq!(reread.frontmatter.versions, twice);
+    }
+
+    #[test]
+    fn test_merge_lines_keeps_appends_from_both() {
+        let base = "# runs\n- mon 5k";
+        let ours = format!("{}\n- tue 8k\n- wed rest", base);
+        let theirs = format!("{}\n- tue intervals", base);
+        assert_eq!(
+            merge_lines(&ours, &theirs),
+            "# runs\n- mon 5k\n- tue 8k\n- wed rest\n- tue intervals"
+        );
+        assert_eq!(merge_lines(&ours, &ours), ours);
+        assert_eq!(merge_lines(&ours, base), ours);
+        assert_eq!(merge_lines("- 5k", "- 5k\n- 5k"), "- 5k\n- 5k");
+    }
+
+    #[test]
+    fn test_author_signed_by_one_of_their_devices() {
+        let phone = iroh::SecretKey::generate(&mut rand::rng());
+        let laptop = iroh::SecretKey::generate(&mut rand::rng());
+        let devices = vec![Device::new("phone".to_string(), phone.public().to_string())];
+        let mut note = Note::from_string(
+            "---\nuuid: 550e8400-e29b-41d4-a716-446655440000\nmodified: 1705316400\n---\nhi",
+            false,
+        )
+        .unwrap();
+        assert!(!note.author_signed_by(&devices));
+
+        note.sign_author("carol", &phone);
+        assert!(note.author_signed_by(&devices));
+        // edits by other members keep the claim
+        note.content.push_str("\nand more");
+        let reparsed = Note::from_string(&note.to_string().unwrap(), false).unwrap();
+        assert!(reparsed.author_signed_by(&devices));
+
+        note.frontmatter.author = Some("bob".to_string());
+        assert!(!note.author_signed_by(&devices));
+        note.sign_author("carol", &laptop);
+        assert!(!note.author_signed_by(&devices));
+    }
 }
 
 #[test]
@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+
+/// Vault local settings, stored in .footnote/settings.json. Every field must
+/// have a default so vaults without the file, or with a file written by an
+/// older version, keep loading.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct VaultSettings {
+    #[serde(default)]
+    pub share_rules: Vec<ShareRule>,
+    #[serde(default)]
+    pub shared_folders: Vec<SharedFolder>,
+    /// limits on what any contact can share with us
+    #[serde(default)]
+    pub default_quota: ShareQuota,
+    /// limits for a contact by nickname, replacing the default
+    #[serde(default)]
+    pub contact_quotas: HashMap<String, ShareQuota>,
+    /// seconds between pushes to a device, by device name, replacing the
+    /// default interval
+    #[serde(default)]
+    pub device_sync_intervals: HashMap<String, u64>,
+    /// seconds between pushes to a contact, by nickname, replacing the
+    /// default interval
+    #[serde(default)]
+    pub contact_sync_intervals: HashMap<String, u64>,
+    /// don't announce this device or look for others on the local network
+    #[serde(default)]
+    pub disable_local_discovery: bool,
+    /// never use relay servers, or publish our address to the public
+    /// discovery service. devices are then only reachable over the local
+    /// network.
+    #[serde(default)]
+    pub disable_public_relays: bool,
+    /// relay servers to use in place of the public ones, e.g. one run for a
+    /// team. The first is announced to other devices in our user record.
+    #[serde(default)]
+    pub relay_urls: Vec<String>,
+}
+
+/// Limits on what a contact can store in footnotes/<nickname>/, enforced when
+/// receiving a share.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ShareQuota {
+    pub max_note_bytes: u64,
+    pub max_total_bytes: u64,
+    pub max_files: usize,
+}
+
+impl Default for ShareQuota {
+    fn default() -> Self {
+        Self {
+            max_note_bytes: 10 * 1024 * 1024,
+            max_total_bytes: 512 * 1024 * 1024,
+            max_files: 10_000,
+        }
+    }
+}
+
+/// Share every note under a folder, or matching a glob, with a list of
+/// contacts. Rules are evaluated alongside `share_with` in the note
+/// frontmatter, a note is shared with the union of both.
+///
+/// pattern examples:
+/// - `recipes` or `recipes/`: everything under the recipes folder
+/// - `recipes/*.md`: notes directly in recipes, not in subfolders
+/// - `**/family-*.md`: any note starting with family- anywhere in the vault
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ShareRule {
+    pub pattern: String,
+    pub share_with: Vec<String>,
+}
+
+impl ShareRule {
+    pub fn new(pattern: &str, share_with: Vec<String>) -> Result<Self> {
+        let rule = Self {
+            pattern: pattern.trim().to_string(),
+            share_with,
+        };
+        rule.validate()?;
+        Ok(rule)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(!self.pattern.is_empty(), "share rule pattern is empty");
+        let pattern = Path::new(&self.pattern);
+        for component in pattern.components() {
+            match component {
+                Component::ParentDir => anyhow::bail!("share rule cannot refer to parent dir"),
+                Component::Prefix(_) | Component::RootDir => {
+                    anyhow::bail!("share rule must be relative to the vault")
+                }
+                Component::Normal(_) | Component::CurDir => {}
+            }
+        }
+        if self.is_glob() {
+            Glob::new(&self.pattern)
+                .with_context(|| format!("invalid share rule glob: {}", self.pattern))?;
+        }
+        Ok(())
+    }
+
+    fn is_glob(&self) -> bool {
+        self.pattern.contains(['*', '?', '[', '{'])
+    }
+
+    /// relative_path is relative to the vault root. notes other people have
+    /// shared with us live under footnotes/ and are never matched, a rule only
+    /// ever shares our own notes.
+    pub fn matches(&self, relative_path: &Path) -> bool {
+        if relative_path.starts_with("footnotes") {
+            return false;
+        }
+
+        if self.is_glob() {
+            return match GlobBuilder::new(&self.pattern)
+                .literal_separator(true)
+                .build()
+            {
+                Ok(glob) => glob.compile_matcher().is_match(relative_path),
+                Err(e) => {
+                    tracing::warn!("ignoring invalid share rule {}: {}", self.pattern, e);
+                    false
+                }
+            };
+        }
+
+        relative_path.starts_with(Path::new(self.pattern.trim_end_matches('/')))
+    }
+}
+
+/// A folder several contacts write to. Every note in it, whoever wrote it,
+/// is shared with each member, and notes a member sends for it are written
+/// into it instead of footnotes/<nickname>/, keeping their author. The id is
+/// the same for every member, the path and the nicknames are each member's
+/// own.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SharedFolder {
+    pub id: Uuid,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+impl SharedFolder {
+    pub fn new(id: Uuid, path: &str, members: Vec<String>) -> Result<Self> {
+        let folder = Self {
+            id,
+            path: PathBuf::from(path.trim().trim_end_matches('/')),
+            members,
+        };
+        folder.validate()?;
+        Ok(folder)
+    }
+
+    /// the path is a folder of our own notes, relative to the vault
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            !self.path.as_os_str().is_empty(),
+            "shared folder path is empty"
+        );
+        for component in self.path.components() {
+            match component {
+                Component::Normal(name) => anyhow::ensure!(
+                    !name.to_string_lossy().starts_with('.'),
+                    "shared folder cannot be hidden"
+                ),
+                _ => anyhow::bail!(
+                    "shared folder {} must be a folder in the vault",
+                    self.path.display()
+                ),
+            }
+        }
+        anyhow::ensure!(
+            !self.path.starts_with("footnotes"),
+            "shared folder cannot be under footnotes/"
+        );
+        Ok(())
+    }
+
+    /// relative_path is relative to the vault root
+    pub fn contains(&self, relative_path: &Path) -> bool {
+        relative_path.starts_with(&self.path)
+    }
+
+    /// share_names is a nickname and the groups it is in, see
+    /// `group::share_names_for`
+    pub fn has_member(&self, share_names: &[String]) -> bool {
+        self.members.iter().any(|m| share_names.contains(m))
+    }
+}
+
+impl VaultSettings {
+    fn settings_path(vault_path: &Path) -> PathBuf {
+        vault_path.join(".footnote").join("settings.json")
+    }
+
+    pub fn read(vault_path: &Path) -> Result<Self> {
+        let path = Self::settings_path(vault_path);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read settings: {}", path.display()))?;
+        serde_json::from_str(&json).context("Failed to parse vault settings")
+    }
+
+    pub fn write(&self, vault_path: &Path) -> Result<()> {
+        let path = Self::settings_path(vault_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// all names a note at relative_path is shared with through share rules
+    pub fn rule_share_with(&self, relative_path: &Path) -> Vec<String> {
+        let mut names = Vec::new();
+        for rule in self.share_rules.iter().filter(|r| r.matches(relative_path)) {
+            for name in &rule.share_with {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn quota_for(&self, nickname: &str) -> ShareQuota {
+        self.contact_quotas
+            .get(nickname)
+            .copied()
+            .unwrap_or(self.default_quota)
+    }
+
+    pub fn is_shared_by_rule(&self, relative_path: &Path, nickname: &str) -> bool {
+        self.share_rules
+            .iter()
+            .any(|r| r.share_with.iter().any(|n| n == nickname) && r.matches(relative_path))
+    }
+
+    /// the shared folder a note at relative_path is in, if any
+    pub fn shared_folder_for(&self, relative_path: &Path) -> Option<&SharedFolder> {
+        self.shared_folders
+            .iter()
+            .find(|f| f.contains(relative_path))
+    }
+
+    /// the shared folders the contact is a member of, see `SharedFolder::has_member`
+    pub fn shared_folders_with(&self, share_names: &[String]) -> Vec<&SharedFolder> {
+        self.shared_folders
+            .iter()
+            .filter(|f| f.has_member(share_names))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, share_with: &[&str]) -> ShareRule {
+        ShareRule::new(pattern, share_with.iter().map(|s| s.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_prefix_rule_matches_folder() {
+        let r = rule("recipes/", &["mom"]);
+        assert!(r.matches(Path::new("recipes/soup.md")));
+        assert!(r.matches(Path::new("recipes/dessert/pie.md")));
+        assert!(!r.matches(Path::new("recipes2/soup.md")));
+        assert!(!r.matches(Path::new("soup.md")));
+    }
+
+    #[test]
+    fn test_prefix_rule_without_trailing_slash() {
+        let r = rule("recipes", &["mom"]);
+        assert!(r.matches(Path::new("recipes/soup.md")));
+        assert!(!r.matches(Path::new("recipes.md")));
+    }
+
+    #[test]
+    fn test_glob_rule() {
+        let r = rule("recipes/*.md", &["mom"]);
+        assert!(r.matches(Path::new("recipes/soup.md")));
+        assert!(!r.matches(Path::new("recipes/dessert/pie.md")));
+
+        let r = rule("**/family-*.md", &["mom"]);
+        assert!(r.matches(Path::new("family-reunion.md")));
+        assert!(r.matches(Path::new("events/family-reunion.md")));
+        assert!(!r.matches(Path::new("events/reunion.md")));
+    }
+
+    #[test]
+    fn test_rules_never_match_footnotes_dir() {
+        let r = rule("**", &["bob"]);
+        assert!(r.matches(Path::new("notes.md")));
+        assert!(!r.matches(Path::new("footnotes/mom/recipes.md")));
+    }
+
+    #[test]
+    fn test_rule_share_with_is_union_of_rules() {
+        let settings = VaultSettings {
+            share_rules: vec![
+                rule("recipes", &["mom", "dad"]),
+                rule("**/*.md", &["mom", "bob"]),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.rule_share_with(Path::new("recipes/soup.md")),
+            vec!["mom", "dad", "bob"]
+        );
+        assert!(settings.is_shared_by_rule(Path::new("recipes/soup.md"), "dad"));
+        assert!(!settings.is_shared_by_rule(Path::new("todo.md"), "dad"));
+    }
+
+    #[test]
+    fn test_invalid_rules_rejected() {
+        assert!(ShareRule::new("", vec![]).is_err());
+        assert!(ShareRule::new("../outside", vec![]).is_err());
+        assert!(ShareRule::new("/etc", vec![]).is_err());
+        assert!(ShareRule::new("recipes/[", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_shared_folder_paths() {
+        let folder = SharedFolder::new(Uuid::new_v4(), "trips/", vec!["mom".to_string()]).unwrap();
+        assert!(folder.contains(Path::new("trips/rome.md")));
+        assert!(!folder.contains(Path::new("trips2/rome.md")));
+        assert!(folder.has_member(&["mom".to_string()]));
+
+        for path in ["", "../trips", "/trips", ".footnote", "footnotes/mom"] {
+            assert!(SharedFolder::new(Uuid::new_v4(), path, vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_missing_fields_default() {
+        let settings: VaultSettings = serde_json::from_str("{}").unwrap();
+        assert!(settings.share_rules.is_empty());
+        assert_eq!(settings.quota_for("mom"), ShareQuota::default());
+        assert!(!settings.disable_local_discovery);
+        assert!(!settings.disable_public_relays);
+
+        let settings: VaultSettings =
+            serde_json::from_str(r#"{"contact_quotas": {"mom": {"max_files": 5}}}"#).unwrap();
+        let quota = settings.quota_for("mom");
+        assert_eq!(quota.max_files, 5);
+        assert_eq!(quota.max_note_bytes, ShareQuota::default().max_note_bytes);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::model::contact::Contact;
-use crate::model::device::Device;
+use crate::model::device::{Device, DeviceMetadata};
 use crate::util::lamport_timestamp::LamportTimestamp;
 use crate::util::sync_status_record;
 use anyhow::Result;
@@ -31,10 +31,8 @@ impl LocalUser {
         let (id_signing_key, _) = local_user.id_key_read()?;
         let (device_signing_key, device_name) = local_user.device_key_read()?;
 
-        let local_device = Device {
-            iroh_endpoint_id: device_signing_key.public().to_string(),
-            name: device_name,
-        };
+        let mut local_device = Device::new(device_name, device_signing_key.public().to_string());
+        local_device.metadata = Some(DeviceMetadata::this_device(None));
 
         let id_public_key_str = hex::encode(id_signing_key.verifying_key().to_bytes());
 
@@ -120,7 +118,12 @@ impl LocalUser {
         Ok((device_key.public(), device_name))
     }
 
-    pub fn bless_remote_device(&self, device_name: &str, iroh_endpoint: &str) -> Result<Contact> {
+    pub fn bless_remote_device(
+        &self,
+        device_name: &str,
+        iroh_endpoint: &str,
+        relay_url: Option<&str>,
+    ) -> Result<Contact> {
         let local_user_file = self.vault_path.join(".footnote").join("user.json");
         let current_user_record = match Contact::from_file(&local_user_file) {
             Ok(contact) => contact,
@@ -132,6 +135,8 @@ impl LocalUser {
         user_record.devices.push(Device {
             name: device_name.to_string(),
             iroh_endpoint_id: iroh_endpoint.to_string(),
+            relay_url: relay_url.map(str::to_string),
+            metadata: None,
         });
         user_record.updated_at = LamportTimestamp(user_record.updated_at.as_i64());
         let (signing_key, _) = self.id_key_read()?;
@@ -212,4 +217,70 @@ impl LocalUser {
         user_record.to_file(&local_user_file)?;
         Ok(user_record)
     }
+
+    pub fn device_relay_update(
+        &self,
+        iroh_endpoint: &str,
+        relay_url: Option<&str>,
+    ) -> Result<Contact> {
+        let local_user_file = self.vault_path.join(".footnote").join("user.json");
+        let current_user_record = match Contact::from_file(&local_user_file) {
+            Ok(contact) => contact,
+            Err(_) => anyhow::bail!("no user file exists to update device in"),
+        };
+        current_user_record.verify()?;
+
+        let mut user_record = current_user_record.clone();
+
+        let device = user_record
+            .devices
+            .iter_mut()
+            .find(|d| d.iroh_endpoint_id == iroh_endpoint)
+            .ok_or_else(|| anyhow::anyhow!("device not found"))?;
+
+        if device.relay_url.as_deref() == relay_url {
+            return Ok(current_user_record);
+        }
+        device.relay_url = relay_url.map(str::to_string);
+
+        user_record.updated_at = LamportTimestamp(user_record.updated_at.as_i64());
+        let (signing_key, _) = self.id_key_read()?;
+        user_record.sign(&signing_key)?;
+        user_record.is_valid_successor_of(&current_user_record)?;
+        user_record.to_file(&local_user_file)?;
+        Ok(user_record)
+    }
+
+    pub fn device_metadata_update(
+        &self,
+        iroh_endpoint: &str,
+        metadata: DeviceMetadata,
+    ) -> Result<Contact> {
+        let local_user_file = self.vault_path.join(".footnote").join("user.json");
+        let current_user_record = match Contact::from_file(&local_user_file) {
+            Ok(contact) => contact,
+            Err(_) => anyhow::bail!("no user file exists to update device in"),
+        };
+        current_user_record.verify()?;
+
+        let mut user_record = current_user_record.clone();
+
+        let device = user_record
+            .devices
+            .iter_mut()
+            .find(|d| d.iroh_endpoint_id == iroh_endpoint)
+            .ok_or_else(|| anyhow::anyhow!("device not found"))?;
+
+        if device.metadata.as_ref() == Some(&metadata) {
+            return Ok(current_user_record);
+        }
+        device.metadata = Some(metadata);
+
+        user_record.updated_at = LamportTimestamp(user_record.updated_at.as_i64());
+        let (signing_key, _) = self.id_key_read()?;
+        user_record.sign(&signing_key)?;
+        user_record.is_valid_successor_of(&current_user_record)?;
+        user_record.to_file(&local_user_file)?;
+        Ok(user_record)
+    }
 }
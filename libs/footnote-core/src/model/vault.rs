@@ -1,5 +1,14 @@
-use crate::model::device::Device;
+use crate::model::device::{Device, DeviceMetadata, DeviceRole};
+use crate::model::group::{self, ContactGroup};
+use crate::model::settings::{ShareQuota, ShareRule, SharedFolder, VaultSettings};
 use crate::model::{contact::Contact, note::Note, user::LocalUser};
+use crate::service::ALPN_PING;
+use crate::util::endpoint;
+use crate::util::hybrid_timestamp::{self, DeviceClock, HybridTimestamp};
+use crate::util::manifest::{self, SharePreviewEntry};
+use crate::util::presence::{self, DevicePresence};
+use crate::util::quota::{self, StorageUsage};
+use crate::util::vault_lock::VaultLock;
 use anyhow::Result;
 use core::fmt;
 use iroh::Endpoint;
@@ -40,6 +49,8 @@ impl fmt::Display for VaultState {
 ///    id_key               : private key that signs device record, primary only
 ///    device_key           : private key specific to this device
 ///    user.json            : signed record of the local user's devices
+///    settings.json        : vault local settings, such as share rules
+///    groups/              : named sets of contacts, usable in share_with
 impl Vault {
     /// Create a vault handle
     pub fn new(path: &Path) -> Result<Self> {
@@ -154,6 +165,8 @@ impl Vault {
         fs::create_dir_all(&footnote_dir)?;
         let contacts_dir = footnote_dir.join("contacts");
         fs::create_dir_all(&contacts_dir)?;
+        let groups_dir = footnote_dir.join("groups");
+        fs::create_dir_all(&groups_dir)?;
         let footnotes_dir = self.path.join("footnotes");
         fs::create_dir_all(&footnotes_dir)?;
         Ok(())
@@ -202,15 +215,8 @@ impl Vault {
         let Ok((secret_key, _)) = self.device_secret_key() else {
             anyhow::bail!("could not get secret key");
         };
-        let Ok(endpoint) = Endpoint::builder()
-            .secret_key(secret_key.clone())
-            .alpns(vec![alpn.to_vec()])
-            .bind()
-            .await
-        else {
-            anyhow::bail!("could not get secret key");
-        };
-        Ok(endpoint)
+        // every endpoint answers pings alongside its own protocol
+        endpoint::endpoint_bind(self, secret_key, vec![alpn.to_vec(), ALPN_PING.to_vec()]).await
     }
 
     pub fn can_device_read_note(
@@ -230,12 +236,16 @@ impl Vault {
         };
 
         let note = Note::from_path(note_path, false)?;
-
-        if note.frontmatter.share_with.contains(&contact.nickname) {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        let share_names = group::share_names_for(&contact.nickname, &self.group_read()?);
+        let relative_path = note_path.strip_prefix(&self.path).unwrap_or(note_path);
+        Ok(!manifest::share_reasons(
+            &contact.nickname,
+            &share_names,
+            &note.frontmatter.share_with,
+            relative_path,
+            &self.settings_read()?,
+        )
+        .is_empty())
     }
 
     pub fn owned_device_endpoint_to_name(
@@ -312,6 +322,13 @@ impl Vault {
         )
     }
 
+    /// the notes the contact receives on the next share, and why each one is
+    /// shared with them
+    pub fn share_preview(&self, nickname: &str) -> Result<Vec<SharePreviewEntry>> {
+        self.contact_read_devices(nickname)?;
+        manifest::share_preview(&self.path, nickname, &self.group_read()?)
+    }
+
     pub fn contact_read(&self) -> anyhow::Result<Vec<Contact>> {
         let contacts_dir = self.path.join(".footnote").join("contacts");
 
@@ -336,6 +353,7 @@ impl Vault {
     }
 
     pub fn contact_update(&self, nickname: &str, new_contact: &mut Contact) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
         let contact_file_path = self
             .path
             .join(".footnote")
@@ -356,6 +374,10 @@ impl Vault {
     }
 
     pub fn contact_import(&self, nickname: &str, contact_json: &str) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        if self.group_file_path(nickname).exists() {
+            anyhow::bail!("{} is already the name of a group", nickname);
+        }
         let mut contact = Contact::from_json(contact_json)?;
         contact.verify()?; // currently called in from_json but doesn't hurt to do it here too
         contact.nickname = nickname.to_string();
@@ -369,6 +391,7 @@ impl Vault {
     }
 
     pub fn contacts_replace(&self, incoming: &[Contact]) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
         let contacts_dir = self.path.join(".footnote").join("contacts");
         fs::create_dir_all(&contacts_dir)?;
 
@@ -412,6 +435,121 @@ impl Vault {
         Ok(())
     }
 
+    pub fn group_read(&self) -> anyhow::Result<Vec<ContactGroup>> {
+        let groups_dir = self.path.join(".footnote").join("groups");
+
+        if !groups_dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut groups = fs::read_dir(groups_dir)?
+            .filter_map(|entry| {
+                let entry = match entry {
+                    Ok(e) => e,
+                    Err(e) => return Some(Err(e.into())),
+                };
+
+                if entry.path().extension()?.to_str()? == "json" {
+                    Some(ContactGroup::from_file(entry.path()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(groups)
+    }
+
+    fn group_file_path(&self, name: &str) -> PathBuf {
+        self.path
+            .join(".footnote")
+            .join("groups")
+            .join(format!("{}.json", name))
+    }
+
+    fn group_find(&self, name: &str) -> anyhow::Result<ContactGroup> {
+        let group_file = self.group_file_path(name);
+        if !group_file.exists() {
+            anyhow::bail!("no group named {}", name);
+        }
+        ContactGroup::from_file(group_file)
+    }
+
+    pub fn group_create(&self, name: &str, members: Vec<String>) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let group = ContactGroup::new(name, Vec::new())?;
+        let contacts = self.contact_read()?;
+        if contacts.iter().any(|c| c.nickname == group.name) {
+            anyhow::bail!("{} is already the nickname of a contact", group.name);
+        }
+        if self.group_file_path(&group.name).exists() {
+            anyhow::bail!("group {} already exists", group.name);
+        }
+
+        fs::create_dir_all(self.path.join(".footnote").join("groups"))?;
+        group.to_file(self.group_file_path(&group.name))?;
+        for member in members {
+            self.group_member_add(&group.name, &member)?;
+        }
+        Ok(())
+    }
+
+    pub fn group_member_add(&self, name: &str, nickname: &str) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let mut group = self.group_find(name)?;
+        if !self.contact_read()?.iter().any(|c| c.nickname == nickname) {
+            anyhow::bail!("no contact with nickname {}", nickname);
+        }
+        if !group.members.iter().any(|m| m == nickname) {
+            group.members.push(nickname.to_string());
+        }
+        group.to_file(self.group_file_path(name))
+    }
+
+    pub fn group_member_delete(&self, name: &str, nickname: &str) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let mut group = self.group_find(name)?;
+        group.members.retain(|m| m != nickname);
+        group.to_file(self.group_file_path(name))
+    }
+
+    pub fn group_delete(&self, name: &str) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let group = self.group_find(name)?;
+        fs::remove_file(self.group_file_path(&group.name))?;
+        Ok(())
+    }
+
+    /// groups are mirrored from the device leader the same way contacts are,
+    /// the incoming list replaces ours
+    pub fn groups_replace(&self, incoming: &[ContactGroup]) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let groups_dir = self.path.join(".footnote").join("groups");
+        fs::create_dir_all(&groups_dir)?;
+
+        for entry in fs::read_dir(&groups_dir)?.filter_map(|e| e.ok()) {
+            if entry.path().extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            fs::remove_file(entry.path())?;
+        }
+
+        for group in incoming {
+            if let Err(e) = group.validate() {
+                tracing::warn!(
+                    "skipping invalid group '{}' during mirror: {}",
+                    group.name,
+                    e
+                );
+                continue;
+            }
+            group.to_file(self.group_file_path(&group.name))?;
+            tracing::info!("wrote group '{}' from mirror", group.name);
+        }
+
+        Ok(())
+    }
+
     /// return a list of devices owned by this vault
     pub fn device_read(&self) -> anyhow::Result<Vec<Device>> {
         // if user.json exists, return those
@@ -433,6 +571,7 @@ impl Vault {
     }
 
     pub fn device_delete(&self, iroh_endpoint: &str) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
         let local_user = LocalUser::new(&self.path)?;
         local_user.device_delete_from_contact_record(&iroh_endpoint)?;
         Ok(())
@@ -471,6 +610,30 @@ impl Vault {
         Ok((secret_key.public(), device_name))
     }
 
+    /// the version for an edit or delete made on this device, after
+    /// `previous` when there is one
+    pub fn version_after(&self, previous: Option<HybridTimestamp>) -> HybridTimestamp {
+        self.clock().tick(previous)
+    }
+
+    /// the clock edits made on this device are stamped with
+    pub fn clock(&self) -> DeviceClock {
+        DeviceClock {
+            device: self.version_device(),
+            observed: hybrid_timestamp::clock_read(&self.path).unwrap_or_else(|e| {
+                tracing::warn!("failed to read clock: {}", e);
+                None
+            }),
+        }
+    }
+
+    /// this device in note versions
+    pub fn version_device(&self) -> u64 {
+        self.device_public_key()
+            .map(|(endpoint_id, _)| HybridTimestamp::device_id(&endpoint_id))
+            .unwrap_or(0)
+    }
+
     pub fn device_key_update(&self, device_name: &str) -> anyhow::Result<()> {
         let footnotes_dir = self.path.join(".footnote");
         let device_key_file = footnotes_dir.join("device_key");
@@ -485,10 +648,43 @@ impl Vault {
     }
 
     pub fn device_update(&self, device_id: &str, name: &str) -> anyhow::Result<Contact> {
+        let _lock = VaultLock::acquire(&self.path)?;
         let local_user = LocalUser::new(&self.path)?;
         local_user.device_name_update(device_id, name)
     }
 
+    /// Set the role of one of our devices in the signed user record, with
+    /// what is known about it: everything for this device, the version it
+    /// last announced for another. Only the leader signs the record.
+    pub fn device_role_set(&self, device_name: &str, role: Option<DeviceRole>) -> Result<Contact> {
+        anyhow::ensure!(
+            self.is_device_leader()?,
+            "only the device leader can describe devices"
+        );
+        let endpoint_id = self.owned_device_name_to_endpoint(device_name)?;
+        let (this_device, _) = self.device_public_key()?;
+        let metadata = if endpoint_id == this_device.to_string() {
+            DeviceMetadata::this_device(role)
+        } else {
+            let known = self
+                .device_read()?
+                .into_iter()
+                .find(|d| d.iroh_endpoint_id == endpoint_id)
+                .and_then(|d| d.metadata)
+                .unwrap_or_default();
+            let seen_version = self
+                .device_presence(&endpoint_id)?
+                .and_then(|p| p.app_version);
+            DeviceMetadata {
+                role,
+                app_version: seen_version.or(known.app_version),
+                ..known
+            }
+        };
+        let _lock = VaultLock::acquire(&self.path)?;
+        LocalUser::new(&self.path)?.device_metadata_update(&endpoint_id, metadata)
+    }
+
     pub fn user_read(&self) -> anyhow::Result<Option<Contact>> {
         let user_record = self.path.join(".footnote").join("user.json");
 
@@ -501,20 +697,161 @@ impl Vault {
     }
 
     pub fn user_write(&self, user: &Contact) -> anyhow::Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
         let user_record_path = self.path.join(".footnote").join("user.json");
         user.to_file(user_record_path)?;
         Ok(())
     }
 
     pub fn user_update(&self, username: &str) -> anyhow::Result<Contact> {
+        let _lock = VaultLock::acquire(&self.path)?;
         // the Vault concept and LocalUser are muddled. it's not really clear if
         // both are needed
         let local_user = LocalUser::new(&self.path)?;
         local_user.username_update(username)
     }
 
+    /// when each device or contact endpoint was last reached, by endpoint id
+    pub fn presence_read(&self) -> Result<HashMap<String, DevicePresence>> {
+        presence::presence_read(&self.path)
+    }
+
+    pub fn device_presence(&self, endpoint_id: &str) -> Result<Option<DevicePresence>> {
+        Ok(self.presence_read()?.remove(endpoint_id))
+    }
+
+    pub fn settings_read(&self) -> Result<VaultSettings> {
+        VaultSettings::read(&self.path)
+    }
+
+    pub fn settings_write(&self, settings: &VaultSettings) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        settings.write(&self.path)
+    }
+
+    /// Use the given relays in place of the public ones, or the public ones
+    /// again when empty. On the leader, our device record announces the
+    /// first relay to other devices and contacts; another device announces
+    /// the relay in its join url when it joins.
+    pub fn relays_set(&self, relay_urls: Vec<String>) -> Result<()> {
+        for url in &relay_urls {
+            endpoint::relay_url_parse(url)?;
+        }
+        let _lock = VaultLock::acquire(&self.path)?;
+        let mut settings = self.settings_read()?;
+        settings.relay_urls = relay_urls;
+        self.settings_write(&settings)?;
+
+        if self.is_device_leader()? {
+            let (endpoint_id, _) = self.device_public_key()?;
+            LocalUser::new(&self.path)?.device_relay_update(
+                &endpoint_id.to_string(),
+                settings.relay_urls.first().map(String::as_str),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// add a share rule, or replace the contacts of an existing rule with the
+    /// same pattern
+    pub fn share_rule_add(&self, pattern: &str, share_with: Vec<String>) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let rule = ShareRule::new(pattern, share_with)?;
+        let mut settings = self.settings_read()?;
+        match settings
+            .share_rules
+            .iter_mut()
+            .find(|r| r.pattern == rule.pattern)
+        {
+            Some(existing) => existing.share_with = rule.share_with,
+            None => settings.share_rules.push(rule),
+        }
+        self.settings_write(&settings)
+    }
+
+    pub fn share_rule_delete(&self, pattern: &str) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let mut settings = self.settings_read()?;
+        let before = settings.share_rules.len();
+        settings.share_rules.retain(|r| r.pattern != pattern.trim());
+        if settings.share_rules.len() == before {
+            anyhow::bail!("no share rule with pattern {}", pattern);
+        }
+        self.settings_write(&settings)
+    }
+
+    /// Start a folder the given contacts write to along with us. Returns its
+    /// id, which each member joins with.
+    pub fn shared_folder_create(&self, path: &str, members: Vec<String>) -> Result<Uuid> {
+        let id = Uuid::new_v4();
+        self.shared_folder_join(id, path, members)?;
+        Ok(id)
+    }
+
+    /// Write to the shared folder with the given id, started by one of the
+    /// members, at path in our vault.
+    pub fn shared_folder_join(&self, id: Uuid, path: &str, members: Vec<String>) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let folder = SharedFolder::new(id, path, members)?;
+        let contacts = self.contact_read()?;
+        let groups = self.group_read()?;
+        for member in &folder.members {
+            if !contacts.iter().any(|c| &c.nickname == member)
+                && !groups.iter().any(|g| &g.name == member)
+            {
+                anyhow::bail!("no contact or group named {}", member);
+            }
+        }
+        let mut settings = self.settings_read()?;
+        if let Some(existing) = settings
+            .shared_folders
+            .iter()
+            .find(|f| f.id == id || f.contains(&folder.path) || folder.contains(&f.path))
+        {
+            anyhow::bail!("{} is already a shared folder", existing.path.display());
+        }
+        fs::create_dir_all(self.path.join(&folder.path))?;
+        settings.shared_folders.push(folder);
+        self.settings_write(&settings)
+    }
+
+    /// Stop writing to a shared folder. Its notes stay where they are, and
+    /// are no longer shared with its members.
+    pub fn shared_folder_leave(&self, path: &str) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        let mut settings = self.settings_read()?;
+        let before = settings.shared_folders.len();
+        let path = Path::new(path.trim().trim_end_matches('/'));
+        settings.shared_folders.retain(|f| f.path != path);
+        if settings.shared_folders.len() == before {
+            anyhow::bail!("no shared folder at {}", path.display());
+        }
+        self.settings_write(&settings)
+    }
+
+    /// replace the storage limits for a contact's shared notes
+    pub fn contact_quota_set(&self, nickname: &str, quota: ShareQuota) -> Result<()> {
+        let _lock = VaultLock::acquire(&self.path)?;
+        self.contact_read_devices(nickname)?;
+        let mut settings = self.settings_read()?;
+        settings.contact_quotas.insert(nickname.to_string(), quota);
+        self.settings_write(&settings)
+    }
+
+    /// how much space each contact's shared notes use, and their limits
+    pub fn contact_storage_report(&self) -> Result<Vec<(String, StorageUsage, ShareQuota)>> {
+        let settings = self.settings_read()?;
+        let mut report = Vec::new();
+        for contact in self.contact_read()? {
+            let usage = quota::storage_usage(&self.path.join("footnotes").join(&contact.nickname))?;
+            let contact_quota = settings.quota_for(&contact.nickname);
+            report.push((contact.nickname, usage, contact_quota));
+        }
+        Ok(report)
+    }
+
     pub fn note_create(&self, path: &Path, content: &str) -> anyhow::Result<()> {
-        Note::create(path, content)?;
+        Note::create(path, content, &self.clock())?;
         Ok(())
     }
 
@@ -534,7 +871,7 @@ impl Vault {
         for entry in WalkDir::new(self.base_path())
             .follow_links(false)
             .into_iter()
-            .filter_entry(|e| !is_hidden(e))
+            .filter_entry(|e| e.depth() == 0 || !is_hidden(e))
             .filter_map(|e| e.ok())
         {
             let path = entry.path();
@@ -598,10 +935,11 @@ impl Vault {
         }
 
         if fix {
+            let clock = self.clock();
             for rewrite in needs_new_uuid {
                 if let Ok(mut note) = Note::from_path(&rewrite, false) {
                     note.frontmatter.uuid = Uuid::new_v4();
-                    if let Err(_) = note.to_file(&rewrite) {
+                    if let Err(_) = note.to_file(&rewrite, &clock) {
                         ret.push((
                             rewrite.to_string_lossy().to_string(),
                             format!(
@@ -615,7 +953,7 @@ impl Vault {
 
             for note_without_metdata in needs_frontmatter {
                 if let Ok(mut note) = Note::from_path(&note_without_metdata, true) {
-                    if let Err(_) = note.to_file(&note_without_metdata) {
+                    if let Err(_) = note.to_file(&note_without_metdata, &clock) {
                         ret.push((
                             note_without_metdata.to_string_lossy().to_string(),
                             format!(
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use iroh::Endpoint;
+use iroh::EndpointAddr;
 use n0_error::StdResultExt;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -11,6 +11,7 @@ use crate::model::{
     user::LocalUser,
     vault::{Vault, VaultState},
 };
+use crate::util::endpoint;
 
 const ALPN_VAULT_JOIN: &[u8] = b"footnote/vault-join";
 
@@ -41,13 +42,15 @@ impl JoinService {
         let secret_key = iroh::SecretKey::generate(&mut rand::rng());
 
         tracing::info!("creating endpoint");
-        let endpoint = Endpoint::builder()
-            .secret_key(secret_key.clone())
-            .alpns(vec![ALPN_VAULT_JOIN.to_vec()])
-            .bind()
-            .await?;
+        let endpoint =
+            endpoint::endpoint_bind(vault, secret_key.clone(), vec![ALPN_VAULT_JOIN.to_vec()])
+                .await?;
         let endpoint_id = secret_key.public();
-        let join_url = format!("footnote+pair://{}", endpoint_id);
+        // a device on its own relay can't be found through public discovery
+        let join_url = match vault.settings_read()?.relay_urls.first() {
+            Some(relay_url) => format!("footnote+pair://{}?relay={}", endpoint_id, relay_url),
+            None => format!("footnote+pair://{}", endpoint_id),
+        };
 
         let (tx, rx) = mpsc::channel(32);
         let _ = tx.send(JoinEvent::Listening { join_url }).await;
@@ -123,22 +126,26 @@ impl JoinService {
             connection_string
         );
 
-        let endpoint_id = parse_connection_string(connection_string)?;
+        let (endpoint_id, relay_url) = parse_connection_string(connection_string)?;
         let (secret_key, _) = vault.device_secret_key()?;
 
         tracing::info!("build endpoint");
-        let endpoint = Endpoint::builder()
-            .secret_key(secret_key.clone())
-            .bind()
-            .await?;
+        let endpoint = endpoint::endpoint_bind(vault, secret_key.clone(), Vec::new()).await?;
         tracing::info!("connect");
-        let conn = endpoint.connect(endpoint_id, ALPN_VAULT_JOIN).await?;
+        let mut addr = EndpointAddr::new(endpoint_id);
+        if let Some(relay_url) = &relay_url {
+            addr = addr.with_relay_url(endpoint::relay_url_parse(relay_url)?);
+        }
+        let conn = endpoint.connect(addr, ALPN_VAULT_JOIN).await?;
         tracing::info!("open bi direction stream");
         let (mut send, mut recv) = conn.open_bi().await.anyerr()?;
 
         let local_user = LocalUser::new(&vault.path)?;
-        let updated_contact_record =
-            local_user.bless_remote_device(device_name, &endpoint_id.to_string())?;
+        let updated_contact_record = local_user.bless_remote_device(
+            device_name,
+            &endpoint_id.to_string(),
+            relay_url.as_deref(),
+        )?;
         let message = UserRecordMessage {
             contact_json: serde_json::to_string(&updated_contact_record)?,
         };
@@ -166,18 +173,49 @@ impl JoinService {
     }
 }
 
-fn parse_connection_string(conn_str: &str) -> Result<iroh::PublicKey> {
+/// footnote+pair://<endpoint id>, with ?relay=<url> when the listening device
+/// uses its own relay
+fn parse_connection_string(conn_str: &str) -> Result<(iroh::PublicKey, Option<String>)> {
     let conn_str = conn_str.trim();
 
     if !conn_str.starts_with("footnote+pair://") {
         anyhow::bail!("Invalid connection string. Expected format: footnote+pair://endpoint-id");
     }
 
-    let endpoint_str = conn_str.strip_prefix("footnote+pair://").unwrap();
+    let address = conn_str.strip_prefix("footnote+pair://").unwrap();
+    let (endpoint_str, relay_url) = match address.split_once("?relay=") {
+        Some((endpoint_str, relay_url)) => (endpoint_str, Some(relay_url.to_string())),
+        None => (address, None),
+    };
 
     let endpoint_id: iroh::PublicKey = endpoint_str
         .parse()
         .map_err(|_| anyhow::anyhow!("Invalid endpoint ID"))?;
 
-    Ok(endpoint_id)
+    Ok((endpoint_id, relay_url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_connection_string_with_relay() {
+        let endpoint_id = iroh::SecretKey::generate(&mut rand::rng()).public();
+
+        let (parsed, relay_url) =
+            parse_connection_string(&format!("footnote+pair://{}", endpoint_id)).unwrap();
+        assert_eq!(parsed, endpoint_id);
+        assert_eq!(relay_url, None);
+
+        let (parsed, relay_url) = parse_connection_string(&format!(
+            "footnote+pair://{}?relay=https://relay.example.com/",
+            endpoint_id
+        ))
+        .unwrap();
+        assert_eq!(parsed, endpoint_id);
+        assert_eq!(relay_url.as_deref(), Some("https://relay.example.com/"));
+
+        assert!(parse_connection_string("footnote+pair://nope").is_err());
+    }
 }
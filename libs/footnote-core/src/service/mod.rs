@@ -1,4 +1,7 @@
 pub mod join_service;
+pub mod peer_scheduler;
+pub mod ping_service;
+pub mod push_service;
 pub mod sync_service;
 
 // protocol negotation is a more robust solution to wire changes. bumping the
@@ -37,5 +40,31 @@ pub mod sync_service;
 // receiver requests files from manifest
 // sender validates file is from manifest, sends
 // receiver deletes files in the tombstone, if deleted_time < modified_time
+//
+// v3:
+// sender sends its app version, user record, serialized contact array,
+// serialized group array, delivery ledgers by contact (mirror only),
+// manifest, tombstone, automerge documents of its collaborative notes,
+// shared folders the receiver writes to with it, none on a mirror
+// receiver answers with its app version
+// receiver writes user record, if successor, contact and group arrays, if
+// coming from device_leader
+// receiver merges delivery ledgers into its own
+// receiver compares manifest to local files
+// receiver requests files from manifest
+// sender validates file is from manifest, sends
+// receiver deletes files in the tombstone, if deleted_time < modified_time
+// receiver sends back its merged documents after the end of file requests,
+// then the notes it rejected for the sender's quota
+// both sides note when the other was last seen, and its version
+// note versions are hybrid timestamps with version vectors
+
+// bundles hold the sender's records and are only imported with the same ALPN,
+// so any change to the records needs a bump too
+pub const ALPN_SYNC: &[u8] = b"footnote/sync/3";
+
+/// reachability check, answered by every sync listener. see `PingService`
+pub const ALPN_PING: &[u8] = b"footnote/ping/2";
 
-pub const ALPN_SYNC: &[u8] = b"footnote/sync/2";
+/// announced to peers at the start of a sync or ping, see `DevicePresence`
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+/// time between pushes to a peer that is reachable, unless overridden
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+/// first retry after a failure, doubling on every failure after that
+const RETRY_BASE: Duration = Duration::from_secs(30);
+const RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+/// shortest interval a peer can be given, an interval of 0 would push in a
+/// tight loop
+pub const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerKey {
+    /// one of our devices, by endpoint id
+    Device(String),
+    /// a contact, by nickname
+    Contact(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerState {
+    pub last_success: Option<Instant>,
+    pub consecutive_failures: u32,
+    pub next_attempt: Option<Instant>,
+    /// change generation the peer last received, devices only
+    pub pushed_generation: Option<u64>,
+}
+
+impl PeerState {
+    /// the peer failed recently and its retry time hasn't come yet
+    pub fn in_backoff(&self, now: Instant) -> bool {
+        self.consecutive_failures > 0 && self.next_attempt.is_some_and(|t| t > now)
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|t| t <= now)
+    }
+}
+
+/// Tracks when each peer should next be pushed to. A reachable peer is pushed
+/// to every interval, a peer that fails is retried with exponential backoff
+/// and jitter so an offline contact doesn't cost a connection timeout on
+/// every change.
+#[derive(Debug, Default)]
+pub struct PeerScheduler {
+    peers: HashMap<PeerKey, PeerState>,
+    intervals: HashMap<PeerKey, Duration>,
+}
+
+impl PeerScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// peers we have never tried are due right away
+    pub fn state(&self, key: &PeerKey) -> PeerState {
+        self.peers.get(key).cloned().unwrap_or_default()
+    }
+
+    /// intervals shorter than MIN_INTERVAL are raised to it
+    pub fn set_interval(&mut self, key: PeerKey, interval: Duration) {
+        self.intervals.insert(key, interval.max(MIN_INTERVAL));
+    }
+
+    pub fn clear_intervals(&mut self) {
+        self.intervals.clear();
+    }
+
+    fn interval(&self, key: &PeerKey) -> Duration {
+        self.intervals.get(key).copied().unwrap_or(DEFAULT_INTERVAL)
+    }
+
+    pub fn record_success(&mut self, key: &PeerKey, now: Instant, generation: Option<u64>) {
+        let interval = self.interval(key);
+        let state = self.peers.entry(key.clone()).or_default();
+        state.last_success = Some(now);
+        state.consecutive_failures = 0;
+        state.next_attempt = Some(now + interval);
+        if generation.is_some() {
+            state.pushed_generation = generation;
+        }
+    }
+
+    pub fn record_failure(&mut self, key: &PeerKey, now: Instant) {
+        let state = self.peers.entry(key.clone()).or_default();
+        state.consecutive_failures += 1;
+        let delay = retry_delay(state.consecutive_failures, rand::random::<f64>());
+        state.next_attempt = Some(now + delay);
+    }
+
+    /// the peer just connected to us so it is online, if it was failing try
+    /// it now. a peer that is doing fine keeps its schedule, otherwise two
+    /// devices would answer each other's pushes forever.
+    pub fn wake(&mut self, key: &PeerKey) {
+        if let Some(state) = self.peers.get_mut(key) {
+            if state.consecutive_failures > 0 {
+                state.next_attempt = None;
+            }
+        }
+    }
+
+    /// forget peers that are no longer devices or contacts
+    pub fn retain(&mut self, keys: &[PeerKey]) {
+        self.peers.retain(|k, _| keys.contains(k));
+    }
+
+    /// the earliest time a known peer is due
+    pub fn next_due(&self) -> Option<Instant> {
+        self.peers.values().filter_map(|s| s.next_attempt).min()
+    }
+}
+
+/// RETRY_BASE doubled for each failure after the first, capped at RETRY_MAX.
+/// jitter in [0, 1) scales the delay to between half and all of it, so peers
+/// that failed together don't retry together.
+fn retry_delay(consecutive_failures: u32, jitter: f64) -> Duration {
+    let exponent = consecutive_failures.saturating_sub(1).min(16);
+    let delay = RETRY_BASE.saturating_mul(1 << exponent).min(RETRY_MAX);
+    delay.mul_f64(0.5 + 0.5 * jitter.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device() -> PeerKey {
+        PeerKey::Device("endpoint".to_string())
+    }
+
+    #[test]
+    fn test_retry_delay_doubles_and_caps() {
+        assert_eq!(retry_delay(1, 1.0), RETRY_BASE);
+        assert_eq!(retry_delay(2, 1.0), RETRY_BASE * 2);
+        assert_eq!(retry_delay(3, 1.0), RETRY_BASE * 4);
+        assert_eq!(retry_delay(30, 1.0), RETRY_MAX);
+        assert_eq!(retry_delay(1, 0.0), RETRY_BASE / 2);
+    }
+
+    #[test]
+    fn test_interval_has_a_minimum() {
+        let mut scheduler = PeerScheduler::new();
+        scheduler.set_interval(device(), Duration::ZERO);
+        assert_eq!(scheduler.interval(&device()), MIN_INTERVAL);
+        scheduler.set_interval(device(), Duration::from_secs(900));
+        assert_eq!(scheduler.interval(&device()), Duration::from_secs(900));
+    }
+
+    #[test]
+    fn test_new_peer_is_due() {
+        let scheduler = PeerScheduler::new();
+        let now = Instant::now();
+        assert!(scheduler.state(&device()).is_due(now));
+        assert!(!scheduler.state(&device()).in_backoff(now));
+    }
+
+    #[test]
+    fn test_failure_backs_off_until_woken() {
+        let mut scheduler = PeerScheduler::new();
+        let now = Instant::now();
+        scheduler.record_failure(&device(), now);
+        assert!(scheduler.state(&device()).in_backoff(now));
+        assert!(!scheduler.state(&device()).is_due(now));
+        assert!(scheduler.state(&device()).is_due(now + RETRY_BASE));
+
+        scheduler.wake(&device());
+        assert!(scheduler.state(&device()).is_due(now));
+    }
+
+    #[test]
+    fn test_wake_leaves_healthy_peer_alone() {
+        let mut scheduler = PeerScheduler::new();
+        let now = Instant::now();
+        scheduler.record_success(&device(), now, Some(1));
+        scheduler.wake(&device());
+        assert!(!scheduler.state(&device()).is_due(now));
+    }
+
+    #[test]
+    fn test_success_resets_failures_and_uses_override() {
+        let mut scheduler = PeerScheduler::new();
+        let now = Instant::now();
+        scheduler.set_interval(device(), Duration::from_secs(60));
+        scheduler.record_failure(&device(), now);
+        scheduler.record_success(&device(), now, Some(3));
+
+        let state = scheduler.state(&device());
+        assert_eq!(state.consecutive_failures, 0);
+        assert_eq!(state.pushed_generation, Some(3));
+        assert_eq!(state.next_attempt, Some(now + Duration::from_secs(60)));
+        assert_eq!(scheduler.next_due(), Some(now + Duration::from_secs(60)));
+    }
+}
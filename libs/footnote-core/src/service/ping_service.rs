@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use iroh::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::model::vault::Vault;
+use crate::service::ALPN_PING;
+use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::network;
+use crate::util::presence;
+use crate::util::transport::{SyncConnection, Transport};
+
+/// give up on a peer that hasn't answered by now
+const PING_TIMEOUT: Duration = Duration::from_secs(15);
+
+const PING_KNOWN: u32 = 0;
+const PING_UNKNOWN: u32 = 1;
+
+/// How the connection to a peer was carried.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum PathKind {
+    Direct,
+    Relay,
+    /// holepunching is still in progress, both are in use
+    Mixed,
+    /// the transport can't tell
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum PingOutcome {
+    /// the peer answered and knows us as a device or contact
+    Reachable { rtt_ms: u64, path: PathKind },
+    /// the peer answered, but would refuse a sync from us
+    Rejected { rtt_ms: u64, path: PathKind },
+    /// offline, or not reachable from here
+    Unreachable { error: String },
+}
+
+/// The last reachability check of a peer endpoint, stored in
+/// .footnote/status/<endpoint>/ping.json next to its sync status.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PingReport {
+    pub endpoint_id: String,
+    pub checked_at: LamportTimestamp,
+    pub outcome: PingOutcome,
+    /// the version the peer announced, if it answered
+    #[serde(default)]
+    pub app_version: Option<String>,
+}
+
+impl PingReport {
+    fn report_path(vault_path: &Path, endpoint_id: &str) -> PathBuf {
+        vault_path
+            .join(".footnote")
+            .join("status")
+            .join(endpoint_id)
+            .join("ping.json")
+    }
+
+    pub fn read(vault_path: &Path, endpoint_id: &str) -> Result<Option<Self>> {
+        let path = Self::report_path(vault_path, endpoint_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read ping report: {}", path.display()))?;
+        Ok(Some(serde_json::from_str(&json)?))
+    }
+
+    pub fn write(&self, vault_path: &Path) -> Result<()> {
+        let path = Self::report_path(vault_path, &self.endpoint_id);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// one line for the CLI and the sync activity panel
+    pub fn summary(&self) -> String {
+        let version = match &self.app_version {
+            Some(version) => format!(", v{}", version),
+            None => String::new(),
+        };
+        match &self.outcome {
+            PingOutcome::Reachable { rtt_ms, path } => {
+                format!("reachable, {} ms, {}{}", rtt_ms, path.label(), version)
+            }
+            PingOutcome::Rejected { rtt_ms, path } => {
+                format!("rejected us, {} ms, {}{}", rtt_ms, path.label(), version)
+            }
+            PingOutcome::Unreachable { error } => format!("unreachable: {}", error),
+        }
+    }
+}
+
+impl PathKind {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PathKind::Direct => "direct",
+            PathKind::Relay => "relayed",
+            PathKind::Mixed => "direct and relayed",
+            PathKind::Unknown => "unknown path",
+        }
+    }
+}
+
+/// A lightweight check of whether a peer can be reached, how, and whether it
+/// would accept a sync from us, without syncing anything.
+///
+/// ping protocol, on ALPN_PING:
+///
+/// v1:
+/// pinger opens a stream and sends a u64 nonce
+/// listener echoes the nonce and a u32, 0 if the pinger is one of its
+/// devices or contacts, 1 if not
+///
+/// v2:
+/// pinger sends the nonce and its app version
+/// listener echoes the nonce, the u32 and its app version
+pub struct PingService;
+
+impl PingService {
+    /// Ping every endpoint of one of our devices by name, or of a contact by
+    /// nickname, and store each report.
+    pub async fn ping_peer(
+        vault: &Vault,
+        transport: impl Transport,
+        name: &str,
+    ) -> Result<Vec<(String, PingReport)>> {
+        let devices = if let Ok(endpoint_id) = vault.owned_device_name_to_endpoint(name) {
+            vec![(name.to_string(), endpoint_id)]
+        } else if let Some(contact) = vault
+            .contact_read()?
+            .into_iter()
+            .find(|c| c.nickname == name)
+        {
+            contact
+                .devices
+                .into_iter()
+                .map(|d| (d.name, d.iroh_endpoint_id))
+                .collect()
+        } else {
+            anyhow::bail!("no device or contact named {}", name);
+        };
+
+        let mut reports = Vec::new();
+        for (device_name, endpoint_id) in devices {
+            let report = Self::diagnose(vault, transport.clone(), endpoint_id.parse()?).await;
+            reports.push((device_name, report));
+        }
+        Ok(reports)
+    }
+
+    /// ping and store the report, for the sync activity panel
+    pub async fn diagnose(
+        vault: &Vault,
+        transport: impl Transport,
+        remote: PublicKey,
+    ) -> PingReport {
+        let report = Self::ping(transport, remote).await;
+        if let Err(e) = report.write(&vault.path) {
+            tracing::warn!("could not record ping report: {}", e);
+        }
+        if !matches!(report.outcome, PingOutcome::Unreachable { .. }) {
+            let app_version = report.app_version.as_deref();
+            if let Err(e) = presence::presence_record(&vault.path, &remote, app_version).await {
+                tracing::warn!("could not record presence: {}", e);
+            }
+        }
+        report
+    }
+
+    pub async fn ping(transport: impl Transport, remote: PublicKey) -> PingReport {
+        let mut app_version = None;
+        let outcome = match timeout(PING_TIMEOUT, Self::exchange(&transport, remote)).await {
+            Ok(Ok((known, rtt, version))) => {
+                app_version = Some(version);
+                let rtt_ms = rtt.as_millis() as u64;
+                let path = Self::path_kind(&transport, remote);
+                if known {
+                    PingOutcome::Reachable { rtt_ms, path }
+                } else {
+                    PingOutcome::Rejected { rtt_ms, path }
+                }
+            }
+            Ok(Err(e)) => PingOutcome::Unreachable {
+                error: format!("{:#}", e),
+            },
+            Err(_) => PingOutcome::Unreachable {
+                error: format!("no answer in {}s", PING_TIMEOUT.as_secs()),
+            },
+        };
+        PingReport {
+            endpoint_id: remote.to_string(),
+            checked_at: LamportTimestamp::now(),
+            outcome,
+            app_version,
+        }
+    }
+
+    /// whether the peer knows us, the time for the nonce to come back and
+    /// the peer's app version
+    async fn exchange(
+        transport: &impl Transport,
+        remote: PublicKey,
+    ) -> Result<(bool, Duration, String)> {
+        let conn = transport
+            .connect(remote, ALPN_PING)
+            .await
+            .context("could not connect")?;
+        let (mut send, mut recv) = conn.open_bi().await?;
+        let nonce: u64 = rand::random();
+        let sent_at = Instant::now();
+        network::send_u64(&mut send, nonce).await?;
+        network::send_app_version(&mut send).await?;
+        let echoed = network::receive_u64(&mut recv).await?;
+        let rtt = sent_at.elapsed();
+        let status = network::receive_u32(&mut recv).await?;
+        let version = network::receive_app_version(&mut recv).await?;
+        anyhow::ensure!(echoed == nonce, "ping answered with the wrong nonce");
+        conn.close(0, b"done");
+        Ok((status == PING_KNOWN, rtt, version))
+    }
+
+    fn path_kind(transport: &impl Transport, remote: PublicKey) -> PathKind {
+        let Some(addr) = transport.remote_addr(remote) else {
+            return PathKind::Unknown;
+        };
+        let direct = addr.ip_addrs().next().is_some();
+        let relayed = addr.relay_urls().next().is_some();
+        match (direct, relayed) {
+            (true, true) => PathKind::Mixed,
+            (true, false) => PathKind::Direct,
+            (false, true) => PathKind::Relay,
+            (false, false) => PathKind::Unknown,
+        }
+    }
+
+    /// the listening side of a ping
+    pub(crate) async fn answer(vault: &Vault, connection: impl SyncConnection) -> Result<()> {
+        let remote_id = connection.remote_id();
+        let known = vault.owned_device_endpoint_to_name(&remote_id).is_ok()
+            || vault.find_contact_by_endpoint(&remote_id).is_ok();
+        let (mut send, mut recv) = connection.accept_bi().await?;
+        let nonce = network::receive_u64(&mut recv).await?;
+        let remote_version = network::receive_app_version(&mut recv).await?;
+        network::send_u64(&mut send, nonce).await?;
+        network::send_u32(&mut send, if known { PING_KNOWN } else { PING_UNKNOWN }).await?;
+        network::send_app_version(&mut send).await?;
+        send.flush().await?;
+        // strangers are answered, but not remembered
+        if known {
+            if let Err(e) =
+                presence::presence_record(&vault.path, &remote_id, Some(&remote_version)).await
+            {
+                tracing::warn!("could not record presence: {}", e);
+            }
+        }
+        // the pinger closes once it has the answer
+        let _ = timeout(PING_TIMEOUT, connection.closed()).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+use crate::model::vault::Vault;
+use crate::service::peer_scheduler::{PeerKey, PeerScheduler, DEFAULT_INTERVAL};
+use crate::service::ping_service::PingService;
+use crate::service::sync_service::{ShareOutcome, SyncService};
+use crate::util::change_signal::ChangeSignal;
+use iroh::Endpoint;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{sleep_until, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// wait this long after the last change before pushing
+const CHANGE_DEBOUNCE: Duration = Duration::from_secs(2);
+/// but never hold a push back longer than this while changes keep coming
+const CHANGE_MAX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub enum PushEvent {
+    Mirrored {
+        device_name: String,
+    },
+    Shared {
+        nickname: String,
+    },
+    Failed {
+        peer: String,
+        error: String,
+    },
+    /// the device or contact records changed and were read again
+    PeersReloaded {
+        devices: usize,
+        contacts: usize,
+    },
+}
+
+/// Pushes our notes to our devices and contacts shortly after they change.
+/// When each peer is tried is up to the `PeerScheduler`: reachable peers are
+/// also pushed to on their interval, in case a change was missed, and peers
+/// that fail are retried with backoff.
+pub struct PushService {
+    vault: Vault,
+    endpoint: Endpoint,
+    changes: ChangeSignal,
+    peer_changes: ChangeSignal,
+    connects: Option<Receiver<iroh::PublicKey>>,
+    events: Option<Sender<PushEvent>>,
+}
+
+impl PushService {
+    pub fn new(vault: Vault, endpoint: Endpoint, changes: ChangeSignal) -> Self {
+        Self {
+            vault,
+            endpoint,
+            changes,
+            peer_changes: ChangeSignal::new(),
+            connects: None,
+            events: None,
+        }
+    }
+
+    /// raised when devices or contacts change. new peers are pushed to right
+    /// away, peers that are up to date are left alone.
+    pub fn peer_changes(mut self, peer_changes: ChangeSignal) -> Self {
+        self.peer_changes = peer_changes;
+        self
+    }
+
+    /// endpoints that connected to us, from `SyncService::listen_notifying`.
+    /// a failing peer that connects is retried right away.
+    pub fn connects(mut self, connects: Receiver<iroh::PublicKey>) -> Self {
+        self.connects = Some(connects);
+        self
+    }
+
+    pub fn events(mut self, events: Sender<PushEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub async fn run(mut self, cancel: CancellationToken) {
+        tracing::info!("start push loop");
+        let mut scheduler = PeerScheduler::new();
+
+        loop {
+            self.push_round(&mut scheduler).await;
+
+            let next_due = scheduler
+                .next_due()
+                .unwrap_or_else(|| Instant::now() + DEFAULT_INTERVAL);
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = self.changes.changed() => {
+                    if !Self::coalesce(&self.changes, &cancel).await {
+                        break;
+                    }
+                }
+                _ = self.peer_changes.changed() => {
+                    if !Self::coalesce(&self.peer_changes, &cancel).await {
+                        break;
+                    }
+                    self.emit(PushEvent::PeersReloaded {
+                        devices: self.vault.device_read().map(|d| d.len()).unwrap_or(0),
+                        contacts: self.vault.contact_read().map(|c| c.len()).unwrap_or(0),
+                    })
+                    .await;
+                }
+                Some(remote_id) = recv_connect(&mut self.connects) => {
+                    if let Some(key) = self.peer_key_for_endpoint(&remote_id) {
+                        tracing::debug!("{:?} connected to us", key);
+                        scheduler.wake(&key);
+                    }
+                }
+                _ = sleep_until(next_due) => {}
+            }
+        }
+        tracing::info!("push loop cancelled");
+    }
+
+    /// push to every peer that missed a change or is due, skipping peers
+    /// waiting out a backoff
+    async fn push_round(&self, scheduler: &mut PeerScheduler) {
+        let settings = self.vault.settings_read().unwrap_or_default();
+        let devices = self.vault.device_read().unwrap_or_default();
+        let contacts = self.vault.contact_read().unwrap_or_default();
+
+        scheduler.clear_intervals();
+        let mut keys = Vec::new();
+        for device in &devices {
+            let key = PeerKey::Device(device.iroh_endpoint_id.clone());
+            if let Some(secs) = settings.device_sync_intervals.get(&device.name) {
+                scheduler.set_interval(key.clone(), Duration::from_secs(*secs));
+            }
+            keys.push(key);
+        }
+        for contact in &contacts {
+            let key = PeerKey::Contact(contact.nickname.clone());
+            if let Some(secs) = settings.contact_sync_intervals.get(&contact.nickname) {
+                scheduler.set_interval(key.clone(), Duration::from_secs(*secs));
+            }
+            keys.push(key);
+        }
+        scheduler.retain(&keys);
+
+        let now = Instant::now();
+        let generation = self.changes.generation();
+        let this_device = self.endpoint.id().to_string();
+        for device in &devices {
+            if device.iroh_endpoint_id == this_device {
+                continue;
+            }
+            let key = PeerKey::Device(device.iroh_endpoint_id.clone());
+            let state = scheduler.state(&key);
+            if state.in_backoff(now) {
+                tracing::debug!("{} is backing off, skipping", device.name);
+                continue;
+            }
+            let up_to_date = state.pushed_generation.is_some_and(|g| g >= generation);
+            if up_to_date && !state.is_due(now) {
+                tracing::debug!("{} is up to date, skipping", device.name);
+                continue;
+            }
+            tracing::info!("attempting to push changes to {}", device.name);
+            match SyncService::mirror_to_device(&self.vault, self.endpoint.clone(), &device.name)
+                .await
+            {
+                Ok(_) => {
+                    scheduler.record_success(&key, Instant::now(), Some(generation));
+                    self.emit(PushEvent::Mirrored {
+                        device_name: device.name.clone(),
+                    })
+                    .await;
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to sync to {}: {}", device.name, e);
+                    scheduler.record_failure(&key, Instant::now());
+                    self.diagnose([&device.iroh_endpoint_id]);
+                    self.emit(PushEvent::Failed {
+                        peer: device.name.clone(),
+                        error: e.to_string(),
+                    })
+                    .await;
+                }
+            }
+        }
+
+        // a contact with nothing changed since the last share waits for its
+        // interval, and then the delivery ledger still skips it without
+        // connecting if it is up to date
+        for contact in &contacts {
+            let key = PeerKey::Contact(contact.nickname.clone());
+            let state = scheduler.state(&key);
+            if state.in_backoff(now) {
+                tracing::debug!("{} is backing off, skipping", contact.nickname);
+                continue;
+            }
+            let up_to_date = state.pushed_generation.is_some_and(|g| g >= generation);
+            if up_to_date && !state.is_due(now) {
+                tracing::debug!("{} is up to date, skipping", contact.nickname);
+                continue;
+            }
+            tracing::info!("attempting to share with {}", contact.nickname);
+            match SyncService::share_to_contact(
+                &self.vault,
+                self.endpoint.clone(),
+                &contact.nickname,
+            )
+            .await
+            {
+                Ok(outcome) => {
+                    scheduler.record_success(&key, Instant::now(), Some(generation));
+                    if outcome == ShareOutcome::Delivered {
+                        self.emit(PushEvent::Shared {
+                            nickname: contact.nickname.clone(),
+                        })
+                        .await;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to share with {}: {}", contact.nickname, e);
+                    scheduler.record_failure(&key, Instant::now());
+                    self.diagnose(contact.devices.iter().map(|d| &d.iroh_endpoint_id));
+                    self.emit(PushEvent::Failed {
+                        peer: contact.nickname.clone(),
+                        error: e.to_string(),
+                    })
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// ping the endpoints of a peer that failed in the background, so the
+    /// activity panel can tell an offline peer from one that rejected us
+    fn diagnose<'a>(&self, endpoint_ids: impl IntoIterator<Item = &'a String>) {
+        for endpoint_id in endpoint_ids {
+            let Ok(remote) = endpoint_id.parse::<iroh::PublicKey>() else {
+                continue;
+            };
+            let vault = self.vault.clone();
+            let endpoint = self.endpoint.clone();
+            tokio::spawn(async move {
+                PingService::diagnose(&vault, endpoint, remote).await;
+            });
+        }
+    }
+
+    fn peer_key_for_endpoint(&self, remote_id: &iroh::PublicKey) -> Option<PeerKey> {
+        if self.vault.owned_device_endpoint_to_name(remote_id).is_ok() {
+            return Some(PeerKey::Device(remote_id.to_string()));
+        }
+        self.vault
+            .find_contact_by_endpoint(remote_id)
+            .ok()
+            .map(|c| PeerKey::Contact(c.nickname))
+    }
+
+    async fn emit(&self, event: PushEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event).await;
+        }
+    }
+
+    /// wait for a burst of changes to settle. false if cancelled.
+    async fn coalesce(changes: &ChangeSignal, cancel: &CancellationToken) -> bool {
+        let deadline = Instant::now() + CHANGE_MAX_DELAY;
+        loop {
+            let wake = (Instant::now() + CHANGE_DEBOUNCE).min(deadline);
+            tokio::select! {
+                _ = cancel.cancelled() => return false,
+                _ = sleep_until(wake) => return true,
+                _ = changes.changed() => continue,
+            }
+        }
+    }
+}
+
+/// without a receiver this never resolves, leaving its select arm idle
+async fn recv_connect(connects: &mut Option<Receiver<iroh::PublicKey>>) -> Option<iroh::PublicKey> {
+    match connects {
+        Some(connects) => connects.recv().await,
+        None => std::future::pending().await,
+    }
+}
@@ -1,85 +1,390 @@
-use crate::service::ALPN_SYNC;
+use crate::service::ping_service::PingService;
+use crate::service::{ALPN_PING, ALPN_SYNC};
+use crate::util::bundle::{Bundle, BundleHeader, BundleScope};
+use crate::util::collab;
+use crate::util::manifest::Manifest;
+use crate::util::peer_address;
+use crate::util::presence;
 use crate::util::sync_status_record::SyncType;
-use crate::util::tombstone::tombstones_read;
+use crate::util::tombstone::{tombstones_read, Tombstone};
 use crate::{
     model::vault::Vault,
-    util::{manifest, transfer},
+    util::{
+        delivery_ledger::DeliveryLedger,
+        manifest,
+        transfer::{self, LeaderRecords},
+        transport::{Connecting, MemoryNetwork, SyncConnection, Transport},
+    },
 };
 use anyhow::{Context, Result};
-use iroh::Endpoint;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 
+/// inbound syncs handled at the same time
+const MAX_INBOUND_SYNCS: usize = 8;
+/// how long syncs in progress get to finish once listening is cancelled
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+/// closes a second connection from an endpoint we are already syncing with
+const BUSY_ERROR_CODE: u32 = 1;
+
 pub struct SyncService;
 
+/// Endpoints with a sync in progress. Each gets one at a time, a peer that
+/// reconnects mid sync would otherwise receive into the same files twice.
+#[derive(Clone, Default)]
+struct InFlight {
+    endpoints: Arc<Mutex<HashSet<iroh::PublicKey>>>,
+}
+
+impl InFlight {
+    /// None if the endpoint already has a sync in progress
+    fn claim(&self, endpoint: iroh::PublicKey) -> Option<InFlightClaim> {
+        let mut endpoints = self.endpoints.lock().unwrap_or_else(|e| e.into_inner());
+        if !endpoints.insert(endpoint) {
+            return None;
+        }
+        Some(InFlightClaim {
+            endpoints: self.endpoints.clone(),
+            endpoint,
+        })
+    }
+}
+
+/// releases the endpoint when the sync ends, however it ends
+struct InFlightClaim {
+    endpoints: Arc<Mutex<HashSet<iroh::PublicKey>>>,
+    endpoint: iroh::PublicKey,
+}
+
+impl Drop for InFlightClaim {
+    fn drop(&mut self) {
+        self.endpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.endpoint);
+    }
+}
+
+/// What became of a sync another device started with us.
+#[derive(Debug, Clone)]
+pub enum InboundEvent {
+    /// a mirror from one of our devices, by device name, or a share from a
+    /// contact, by nickname
+    Received {
+        peer: String,
+    },
+    /// the endpoint is none of our devices or contacts, or is already syncing
+    Rejected {
+        endpoint_id: String,
+        reason: String,
+    },
+    Failed {
+        peer: String,
+        error: String,
+    },
+}
+
+/// Where the listener reports to, see `listen_reporting`.
+#[derive(Clone, Default)]
+struct Listeners {
+    connects: Option<Sender<iroh::PublicKey>>,
+    events: Option<Sender<InboundEvent>>,
+}
+
+impl Listeners {
+    async fn emit(&self, event: InboundEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event).await;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShareOutcome {
+    Delivered,
+    /// the delivery ledger shows the contact already has every shared note
+    UpToDate,
+}
+
 impl SyncService {
-    pub async fn listen(vault: Vault, endpoint: Endpoint, cancel: CancellationToken) -> Result<()> {
+    pub async fn listen(
+        vault: Vault,
+        transport: impl Transport,
+        cancel: CancellationToken,
+    ) -> Result<()> {
+        Self::listen_inner(vault, transport, cancel, Listeners::default()).await
+    }
+
+    /// listen, and send the endpoint of every peer that connects to us. a
+    /// peer that connects is online, the push loop uses this to retry it
+    /// right away.
+    pub async fn listen_notifying(
+        vault: Vault,
+        transport: impl Transport,
+        cancel: CancellationToken,
+        connects: Sender<iroh::PublicKey>,
+    ) -> Result<()> {
+        let listeners = Listeners {
+            connects: Some(connects),
+            events: None,
+        };
+        Self::listen_inner(vault, transport, cancel, listeners).await
+    }
+
+    /// like `listen_notifying`, and send what became of every sync a peer
+    /// started with us
+    pub async fn listen_reporting(
+        vault: Vault,
+        transport: impl Transport,
+        cancel: CancellationToken,
+        connects: Sender<iroh::PublicKey>,
+        events: Sender<InboundEvent>,
+    ) -> Result<()> {
+        let listeners = Listeners {
+            connects: Some(connects),
+            events: Some(events),
+        };
+        Self::listen_inner(vault, transport, cancel, listeners).await
+    }
+
+    async fn listen_inner<T: Transport>(
+        vault: Vault,
+        transport: T,
+        cancel: CancellationToken,
+        listeners: Listeners,
+    ) -> Result<()> {
         let (secret_key, _) = vault.device_secret_key()?;
 
         tracing::info!("Listening on endpoint: {}", secret_key.public());
 
+        let permits = Arc::new(Semaphore::new(MAX_INBOUND_SYNCS));
+        let in_flight = InFlight::default();
+        let mut tasks = JoinSet::new();
         loop {
-            tokio::select! {
-                _ = cancel.cancelled() => {
-                    tracing::info!("Listening cancelled on endpoint: {}", secret_key.public());
-                    break;
+            while let Some(result) = tasks.try_join_next() {
+                if let Err(e) = result {
+                    tracing::error!("inbound sync task failed: {}", e);
                 }
+            }
 
-                maybe_incoming = endpoint.accept() => {
-                    if let Some(incoming) = maybe_incoming {
-                        tracing::info!("Woke up endpoint: {}", secret_key.public());
-                        match async {
-                            let connection = incoming.await?;
-                            let remote_id = connection.remote_id();
-                            tracing::info!( "succesfully connection from {}", remote_id);
-
-                            if let Ok(contact) = vault.find_contact_by_endpoint(&remote_id) {
-                                tracing::info!( "found contact {} from endpoint {}", contact.nickname, remote_id);
-                                transfer::receive_share(&vault, &contact.nickname, connection.clone()).await?;
-                                tracing::info!( "succesfully recieved shared files from {}", contact.nickname);
-                                return Ok(());
-                            }
-
-                            if let Ok(device_name) = vault.owned_device_endpoint_to_name(&remote_id) {
-                                tracing::info!("found our own device {} from endpoint {}", device_name, remote_id);
-                                transfer::receive_mirror(&vault, connection).await?;
-                                tracing::info!("succesfully handled replicate request from {} on {}", device_name, remote_id);
-                                return Ok(());
-                            }
-                            anyhow::bail!("failed to handle incoming connection")
-                        }.await {
-                            Ok(_) => {
-                                tracing::info!("Handling incoming connection");
-                            }
-                            Err(e) => {
-                                tracing::info!("failed to handle request {}", e.to_string());
-                            }
-                        }
-                    }
+            // wait for a free slot before accepting, further connections
+            // queue up in the endpoint
+            let permit = tokio::select! {
+                _ = cancel.cancelled() => break,
+                permit = permits.clone().acquire_owned() => permit?,
+            };
+
+            tokio::select! {
+                _ = cancel.cancelled() => break,
+
+                maybe_incoming = transport.accept() => {
+                    let Some(incoming) = maybe_incoming else {
+                        tracing::info!("endpoint closed");
+                        break;
+                    };
+                    tracing::info!("Woke up endpoint: {}", secret_key.public());
+                    tasks.spawn(Self::handle_incoming(
+                        vault.clone(),
+                        transport.clone(),
+                        incoming,
+                        listeners.clone(),
+                        in_flight.clone(),
+                        permit,
+                    ));
                 }
             }
         }
+        tracing::info!("Listening cancelled on endpoint: {}", secret_key.public());
+
+        // let syncs in progress finish, within reason
+        if tokio::time::timeout(SHUTDOWN_GRACE, async {
+            while tasks.join_next().await.is_some() {}
+        })
+        .await
+        .is_err()
+        {
+            tracing::warn!("aborting {} inbound syncs", tasks.len());
+            tasks.shutdown().await;
+        }
         Ok(())
     }
 
-    pub async fn share_to_contact(vault: &Vault, endpoint: Endpoint, nickname: &str) -> Result<()> {
-        let devices = vault.contact_read_devices(nickname)?;
-        let manifest = manifest::create_manifest_for_share(&vault.path, nickname)
-            .context("Failed to create manifest for sharing")?;
+    async fn handle_incoming<T: Transport>(
+        vault: Vault,
+        transport: T,
+        incoming: Connecting<T::Connection>,
+        listeners: Listeners,
+        in_flight: InFlight,
+        _permit: OwnedSemaphorePermit,
+    ) {
+        match async {
+            let connection = incoming.await?;
+            let remote_id = connection.remote_id();
+            if connection.alpn() == ALPN_PING {
+                return PingService::answer(&vault, connection).await;
+            }
+            tracing::info!("succesfully connection from {}", remote_id);
+            if let Some(connects) = &listeners.connects {
+                let _ = connects.try_send(remote_id);
+            }
+
+            let Some(_claim) = in_flight.claim(remote_id) else {
+                tracing::info!("sync from {} already in progress, refusing", remote_id);
+                connection.close(BUSY_ERROR_CODE, b"sync already in progress");
+                listeners
+                    .emit(InboundEvent::Rejected {
+                        endpoint_id: remote_id.to_string(),
+                        reason: "sync already in progress".to_string(),
+                    })
+                    .await;
+                return Ok(());
+            };
+            let Some(peer) = Self::peer_name(&vault, &remote_id) else {
+                listeners
+                    .emit(InboundEvent::Rejected {
+                        endpoint_id: remote_id.to_string(),
+                        reason: "not one of our devices or contacts".to_string(),
+                    })
+                    .await;
+                anyhow::bail!("no device or contact has endpoint {}", remote_id);
+            };
+            let remote_version = match Self::receive(&vault, connection).await {
+                Ok(remote_version) => {
+                    listeners
+                        .emit(InboundEvent::Received { peer: peer.clone() })
+                        .await;
+                    remote_version
+                }
+                Err(e) => {
+                    listeners
+                        .emit(InboundEvent::Failed {
+                            peer,
+                            error: e.to_string(),
+                        })
+                        .await;
+                    return Err(e);
+                }
+            };
+            if let Err(e) =
+                presence::presence_record(&vault.path, &remote_id, Some(&remote_version)).await
+            {
+                tracing::warn!("could not record presence: {}", e);
+            }
+            if let Some(addr) = transport.remote_addr(remote_id) {
+                if let Err(e) = peer_address::peer_address_record(&vault.path, &addr).await {
+                    tracing::warn!("could not record peer address: {}", e);
+                }
+            }
+            anyhow::Ok(())
+        }
+        .await
+        {
+            Ok(_) => {
+                tracing::info!("Handling incoming connection");
+            }
+            Err(e) => {
+                tracing::info!("failed to handle request {}", e.to_string());
+            }
+        }
+    }
+
+    /// the nickname of the contact, or name of our device, at the endpoint
+    fn peer_name(vault: &Vault, remote_id: &iroh::PublicKey) -> Option<String> {
+        if let Ok(contact) = vault.find_contact_by_endpoint(remote_id) {
+            return Some(contact.nickname);
+        }
+        vault.owned_device_endpoint_to_name(remote_id).ok()
+    }
+
+    /// a sync from a contact is a share, from one of our devices a mirror.
+    /// returns the app version the sender announced
+    async fn receive(vault: &Vault, connection: impl SyncConnection) -> Result<String> {
+        let remote_id = connection.remote_id();
+        if let Ok(contact) = vault.find_contact_by_endpoint(&remote_id) {
+            tracing::info!(
+                "found contact {} from endpoint {}",
+                contact.nickname,
+                remote_id
+            );
+            let remote_version =
+                transfer::receive_share(vault, &contact.nickname, connection).await?;
+            tracing::info!(
+                "succesfully recieved shared files from {}",
+                contact.nickname
+            );
+            return Ok(remote_version);
+        }
+
+        if let Ok(device_name) = vault.owned_device_endpoint_to_name(&remote_id) {
+            tracing::info!(
+                "found our own device {} from endpoint {}",
+                device_name,
+                remote_id
+            );
+            let remote_version = transfer::receive_mirror(vault, connection).await?;
+            tracing::info!(
+                "succesfully handled replicate request from {} on {}",
+                device_name,
+                remote_id
+            );
+            return Ok(remote_version);
+        }
+        anyhow::bail!("failed to handle incoming connection")
+    }
+
+    pub async fn share_to_contact(
+        vault: &Vault,
+        transport: impl Transport,
+        nickname: &str,
+    ) -> Result<ShareOutcome> {
+        let mut devices = vault.contact_read_devices(nickname)?;
+        // the often-on device is the likeliest to answer, and passes the
+        // share on to the contact's other devices
+        devices.sort_by_key(|d| !d.is_often_on());
+        let manifest =
+            manifest::create_manifest_for_share(&vault.path, nickname, &vault.group_read()?)
+                .context("Failed to create manifest for sharing")?;
+
+        collab::absorb(vault).await?;
+        let ledger = DeliveryLedger::read(&vault.path, nickname)?;
+        let withdrawals = Self::share_withdrawals(vault, &ledger, &manifest)?;
+        // any of our devices may share, the mirrored delivery ledger tells us
+        // if another one already delivered, or delivered something newer than
+        // what this device has. an edit to a collaborative note, ours or
+        // theirs, still goes out as a change to its document, absorbed above,
+        // when every note was delivered.
+        if ledger.is_up_to_date(&manifest, &withdrawals)
+            && !collab::has_unsent(vault, nickname, &manifest)?
+        {
+            tracing::info!("{} already has every shared note", nickname);
+            return Ok(ShareOutcome::UpToDate);
+        }
+        if ledger.is_behind(&manifest) {
+            anyhow::bail!(
+                "a newer version was already delivered to {}, waiting for mirror",
+                nickname
+            );
+        }
         for device in devices {
             if let Ok(device_endpoint) = device.iroh_endpoint_id.parse::<iroh::PublicKey>() {
                 match transfer::sync_to_target(
                     vault,
-                    endpoint.clone(),
+                    transport.clone(),
                     SyncType::Share,
                     manifest.clone(),
-                    Vec::new(),
-                    Vec::new(),
+                    withdrawals.clone(),
+                    LeaderRecords::default(),
                     device_endpoint,
                     ALPN_SYNC,
                 )
                 .await
                 {
-                    Ok(_) => return Ok(()),
+                    Ok(_) => return Ok(ShareOutcome::Delivered),
                     Err(e) => {
                         tracing::warn!(
                             "username {} device {} could not be contacted, error {}, trying next device",
@@ -103,34 +408,176 @@ impl SyncService {
 
     pub async fn mirror_to_device(
         vault: &Vault,
-        endpoint: Endpoint,
+        transport: impl Transport,
         device_name: &str,
     ) -> Result<()> {
         let endpoint_str = vault.owned_device_name_to_endpoint(device_name)?;
         let endpoint_id = endpoint_str.parse::<iroh::PublicKey>()?;
+        let (manifest, tombstones, leader_records) = Self::mirror_records(vault)?;
+
+        transfer::sync_to_target(
+            vault,
+            transport,
+            SyncType::Mirror,
+            manifest,
+            tombstones,
+            leader_records,
+            endpoint_id,
+            ALPN_SYNC,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// what a mirror sends: every note, tombstone, and the leader's records
+    fn mirror_records(vault: &Vault) -> Result<(Manifest, Vec<Tombstone>, LeaderRecords)> {
         let tombstones = tombstones_read(&vault.path)?;
 
         let manifest =
             manifest::create_manifest_full(&vault.path).context("Failed to create manifest")?;
 
-        let contacts = if vault.is_device_leader()? {
-            vault.contact_read()?
+        let leader_records = if vault.is_device_leader()? {
+            LeaderRecords {
+                contacts: vault.contact_read()?,
+                groups: vault.group_read()?,
+            }
         } else {
-            Vec::new()
+            LeaderRecords::default()
         };
+        Ok((manifest, tombstones, leader_records))
+    }
 
-        transfer::sync_to_target(
+    fn share_withdrawals(
+        vault: &Vault,
+        ledger: &DeliveryLedger,
+        manifest: &Manifest,
+    ) -> Result<Vec<Tombstone>> {
+        Ok(ledger.withdrawals(
+            manifest,
+            &manifest::create_manifest_full(&vault.path)?,
+            &tombstones_read(&vault.path)?,
+        ))
+    }
+
+    /// The mirror `mirror_to_device` would send, as a signed bundle for the
+    /// device to import.
+    pub async fn mirror_bundle(vault: &Vault, device_name: &str) -> Result<Vec<u8>> {
+        let endpoint_str = vault.owned_device_name_to_endpoint(device_name)?;
+        let endpoint_id = endpoint_str.parse::<iroh::PublicKey>()?;
+        let (manifest, tombstones, leader_records) = Self::mirror_records(vault)?;
+        transfer::export_bundle(
             vault,
-            endpoint,
+            BundleScope::Device(endpoint_str),
             SyncType::Mirror,
             manifest,
             tombstones,
-            contacts,
+            leader_records,
             endpoint_id,
-            ALPN_SYNC,
         )
-        .await?;
+        .await
+    }
 
-        Ok(())
+    /// The share `share_to_contact` would send, as a signed bundle any of the
+    /// contact's devices can import. Unlike a share over the network it
+    /// always holds every shared note, and the delivery ledger is left alone
+    /// since we can't tell when, or if, it arrives.
+    pub async fn share_bundle(vault: &Vault, nickname: &str) -> Result<Vec<u8>> {
+        let Some(contact) = vault
+            .contact_read()?
+            .into_iter()
+            .find(|c| c.nickname == nickname)
+        else {
+            anyhow::bail!("no contact named {}", nickname);
+        };
+        let Some(reader) = contact
+            .devices
+            .iter()
+            .find_map(|d| d.iroh_endpoint_id.parse::<iroh::PublicKey>().ok())
+        else {
+            anyhow::bail!("{} has no devices", nickname);
+        };
+        let manifest =
+            manifest::create_manifest_for_share(&vault.path, nickname, &vault.group_read()?)
+                .context("Failed to create manifest for sharing")?;
+        let ledger = DeliveryLedger::read(&vault.path, nickname)?;
+        let withdrawals = Self::share_withdrawals(vault, &ledger, &manifest)?;
+        transfer::export_bundle(
+            vault,
+            BundleScope::Contact(contact.id_public_key),
+            SyncType::Share,
+            manifest,
+            withdrawals,
+            LeaderRecords::default(),
+            reader,
+        )
+        .await
+    }
+
+    /// Apply a bundle exported by another device. It is replayed to the same
+    /// receive code, with the same checks, as a live sync from that device.
+    pub async fn import_bundle(vault: &Vault, bytes: &[u8]) -> Result<BundleHeader> {
+        let bundle = Bundle::from_bytes(bytes).await?;
+        if bundle.header.protocol.as_bytes() != ALPN_SYNC {
+            anyhow::bail!(
+                "bundle was exported for sync protocol {}, this device speaks {}",
+                bundle.header.protocol,
+                String::from_utf8_lossy(ALPN_SYNC)
+            );
+        }
+        let (secret_key, _) = vault.device_secret_key()?;
+        let ours = secret_key.public();
+        match &bundle.header.scope {
+            BundleScope::Device(endpoint_id) => {
+                if *endpoint_id != ours.to_string() {
+                    anyhow::bail!("bundle is for device {}, not this one", endpoint_id);
+                }
+            }
+            BundleScope::Contact(id_public_key) => {
+                let Some(user_record) = vault.user_read()? else {
+                    anyhow::bail!("cannot import a bundle without a user record");
+                };
+                if user_record.id_public_key != *id_public_key {
+                    anyhow::bail!("bundle is for another user");
+                }
+            }
+        }
+        let from = bundle
+            .header
+            .from
+            .parse::<iroh::PublicKey>()
+            .context("bundle is not from a valid endpoint id")?;
+
+        let network = MemoryNetwork::new();
+        let receiver = network.bind(ours);
+        let sender = network.bind(from);
+        let receiving = async {
+            let Some(connecting) = receiver.accept().await else {
+                anyhow::bail!("bundle replay did not connect");
+            };
+            Self::receive(vault, connecting.await?).await
+        };
+        let (received, replayed) = tokio::join!(receiving, bundle.replay(sender, ours, ALPN_SYNC));
+        received?;
+        replayed?;
+        Ok(bundle.header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_sync_per_endpoint() {
+        let in_flight = InFlight::default();
+        let endpoint = iroh::SecretKey::generate(&mut rand::rng()).public();
+
+        let claim = in_flight.claim(endpoint);
+        assert!(claim.is_some());
+        assert!(in_flight.claim(endpoint).is_none());
+
+        drop(claim);
+        assert!(in_flight.claim(endpoint).is_some());
     }
 }
@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use iroh::{PublicKey, SecretKey, Signature};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::network;
+use crate::util::transport::{SyncConnection, Transport};
+
+const MAGIC: &[u8; 8] = b"fnbundle";
+const BUNDLE_VERSION: u32 = 2;
+
+/// Who a bundle is for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum BundleScope {
+    /// a mirror to one of our devices, by endpoint id
+    Device(String),
+    /// a share with a contact, by their id public key. any of their devices
+    /// may import it.
+    Contact(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleHeader {
+    pub scope: BundleScope,
+    /// endpoint id of the device that exported and signed the bundle
+    pub from: String,
+    pub created_at: LamportTimestamp,
+    /// ALPN of the sync the records were written for, a bundle can only be
+    /// replayed to a receiver that speaks the same one
+    pub protocol: String,
+}
+
+/// A sync written to a file, for devices that can't reach each other over
+/// the network. It holds what the sender writes before the first file
+/// request, and every note the receiver could ask for. Importing replays it
+/// to the same receive code a live sync uses.
+///
+/// The bundle is signed with the exporting device's key, standing in for the
+/// endpoint authentication iroh does on a live connection.
+pub struct Bundle {
+    pub header: BundleHeader,
+    records: Vec<u8>,
+    files: BTreeMap<Uuid, Vec<u8>>,
+}
+
+impl Bundle {
+    pub fn new(header: BundleHeader, records: Vec<u8>, files: BTreeMap<Uuid, Vec<u8>>) -> Self {
+        Self {
+            header,
+            records,
+            files,
+        }
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.files.len()
+    }
+
+    pub async fn to_bytes(&self, secret_key: &SecretKey) -> Result<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        network::send_u32(&mut bytes, BUNDLE_VERSION).await?;
+        network::send_bytes(&mut bytes, &serde_json::to_vec(&self.header)?).await?;
+        network::send_bytes(&mut bytes, &self.records).await?;
+        network::send_u32(&mut bytes, self.files.len() as u32).await?;
+        for (uuid, contents) in &self.files {
+            network::send_file_request(&mut bytes, uuid).await?;
+            network::send_file_contents(&mut bytes, contents).await?;
+        }
+        let signature = secret_key.sign(&bytes);
+        bytes.extend_from_slice(&signature.to_bytes());
+        Ok(bytes)
+    }
+
+    /// only the header is read before the signature is checked
+    pub async fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < MAGIC.len() + Signature::LENGTH || !bytes.starts_with(MAGIC) {
+            anyhow::bail!("not a footnote bundle");
+        }
+        let (signed, signature) = bytes.split_at(bytes.len() - Signature::LENGTH);
+        let mut reader = &signed[MAGIC.len()..];
+
+        let version = network::receive_u32(&mut reader).await?;
+        if version != BUNDLE_VERSION {
+            anyhow::bail!("unsupported bundle version {}", version);
+        }
+        let header: BundleHeader =
+            serde_json::from_slice(&network::receive_bytes(&mut reader).await?)
+                .context("Failed to parse bundle header")?;
+        let from: PublicKey = header
+            .from
+            .parse()
+            .context("bundle is not from a valid endpoint id")?;
+        let signature = Signature::from_bytes(signature.try_into()?);
+        if from.verify(signed, &signature).is_err() {
+            anyhow::bail!("bundle signature does not match {}", header.from);
+        }
+
+        let records = network::receive_bytes(&mut reader).await?;
+        let count = network::receive_u32(&mut reader).await?;
+        let mut files = BTreeMap::new();
+        for _ in 0..count {
+            let Some(uuid) = network::receive_file_request(&mut reader).await? else {
+                anyhow::bail!("bundle holds a note without a uuid");
+            };
+            files.insert(uuid, network::receive_file_contents(&mut reader).await?);
+        }
+        Ok(Self {
+            header,
+            records,
+            files,
+        })
+    }
+
+    /// Play the sending side of the sync to `to`: the records, then each note
+    /// the receiver asks for.
+    pub async fn replay(
+        &self,
+        transport: impl Transport,
+        to: PublicKey,
+        alpn: &[u8],
+    ) -> Result<()> {
+        let conn = transport.connect(to, alpn).await?;
+        let (mut send, mut recv) = conn.open_bi().await?;
+        send.write_all(&self.records).await?;
+        // the receiver announces its version, nobody is there to hear it
+        network::receive_app_version(&mut recv).await?;
+        while let Some(uuid) = network::receive_file_request(&mut recv).await? {
+            let Some(contents) = self.files.get(&uuid) else {
+                anyhow::bail!("bundle does not hold requested note {}", uuid);
+            };
+            network::send_file_contents(&mut send, contents).await?;
+        }
+        // nobody is there to merge the receiver's documents, or to try the
+        // notes it rejected again, either
+        network::receive_docs(&mut recv).await?;
+        network::receive_bytes(&mut recv).await?;
+        conn.close(0, b"done");
+        conn.closed().await;
+        Ok(())
+    }
+}
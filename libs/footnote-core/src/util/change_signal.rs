@@ -0,0 +1,161 @@
+use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Counts changes to the notes in a vault and wakes the task waiting on them.
+/// Any number of producers, the GUI on save or a `VaultWatcher`, and a single
+/// consumer, the push loop. Changes made while nobody is waiting are kept as
+/// one pending wake up, so a burst of saves is one wake up.
+#[derive(Clone, Default)]
+pub struct ChangeSignal {
+    inner: Arc<ChangeSignalInner>,
+}
+
+#[derive(Default)]
+struct ChangeSignalInner {
+    generation: AtomicU64,
+    notify: Notify,
+}
+
+impl ChangeSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn notify_changed(&self) {
+        self.inner.generation.fetch_add(1, Ordering::SeqCst);
+        self.inner.notify.notify_one();
+    }
+
+    /// increases on every change, a peer that was sent generation n has every
+    /// change up to n
+    pub fn generation(&self) -> u64 {
+        self.inner.generation.load(Ordering::SeqCst)
+    }
+
+    pub async fn changed(&self) {
+        self.inner.notify.notified().await;
+    }
+}
+
+/// Watches the notes in a vault for changes made by any process, an external
+/// editor or the CLI, and raises the change signal. Dropping the watcher stops
+/// it.
+pub struct VaultWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl VaultWatcher {
+    pub fn start(vault_path: &Path, signal: ChangeSignal) -> Result<Self> {
+        Self::start_filtered(vault_path, signal, is_note_path)
+    }
+
+    /// watch the device, contact and group records instead of the notes.
+    /// these are rewritten on every mirror, so the signal must only be used
+    /// to pick up new peers, never to push.
+    pub fn start_peer_records(vault_path: &Path, signal: ChangeSignal) -> Result<Self> {
+        Self::start_filtered(vault_path, signal, is_peer_record_path)
+    }
+
+    fn start_filtered(
+        vault_path: &Path,
+        signal: ChangeSignal,
+        filter: fn(&Path, &Path) -> bool,
+    ) -> Result<Self> {
+        let root = vault_path.to_path_buf();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) => {
+                    if matches!(event.kind, EventKind::Access(_)) {
+                        return;
+                    }
+                    if event.paths.iter().any(|p| filter(&root, p)) {
+                        tracing::debug!("vault change: {:?}", event.paths);
+                        signal.notify_changed();
+                    }
+                }
+                Err(e) => tracing::warn!("vault watcher error: {}", e),
+            })?;
+        watcher.watch(vault_path, RecursiveMode::Recursive)?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// only notes count as changes. the files under .footnote are rewritten on
+/// every sync, reacting to them would have devices push back and forth forever.
+fn is_note_path(vault_path: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(vault_path) else {
+        return false;
+    };
+    let hidden = relative.components().any(|c| match c {
+        Component::Normal(name) => name.to_string_lossy().starts_with('.'),
+        _ => true,
+    });
+    !hidden && relative.extension().and_then(|s| s.to_str()) == Some("md")
+}
+
+fn is_peer_record_path(vault_path: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(vault_path.join(".footnote")) else {
+        return false;
+    };
+    let is_json = relative.extension().and_then(|s| s.to_str()) == Some("json");
+    is_json
+        && (relative == Path::new("user.json")
+            || relative.starts_with("contacts")
+            || relative.starts_with("groups"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_note_path() {
+        let vault = Path::new("/vault");
+        assert!(is_note_path(vault, Path::new("/vault/recipes/soup.md")));
+        assert!(is_note_path(
+            vault,
+            Path::new("/vault/footnotes/mom/soup.md")
+        ));
+        assert!(!is_note_path(vault, Path::new("/vault/recipes/soup.tmp")));
+        assert!(!is_note_path(
+            vault,
+            Path::new("/vault/.footnote/contacts/mom.json")
+        ));
+        assert!(!is_note_path(vault, Path::new("/vault/.hidden/soup.md")));
+        assert!(!is_note_path(vault, Path::new("/elsewhere/soup.md")));
+    }
+
+    #[test]
+    fn test_is_peer_record_path() {
+        let vault = Path::new("/vault");
+        assert!(is_peer_record_path(
+            vault,
+            Path::new("/vault/.footnote/user.json")
+        ));
+        assert!(is_peer_record_path(
+            vault,
+            Path::new("/vault/.footnote/contacts/mom.json")
+        ));
+        assert!(!is_peer_record_path(
+            vault,
+            Path::new("/vault/.footnote/status/abc/inbound/status.json")
+        ));
+        assert!(!is_peer_record_path(vault, Path::new("/vault/user.json")));
+    }
+
+    #[tokio::test]
+    async fn test_changes_coalesce_into_one_wake_up() {
+        let signal = ChangeSignal::new();
+        signal.notify_changed();
+        signal.notify_changed();
+        assert_eq!(signal.generation(), 2);
+
+        signal.changed().await;
+        let second = tokio::time::timeout(std::time::Duration::from_millis(10), signal.changed());
+        assert!(second.await.is_err());
+    }
+}
@@ -0,0 +1,512 @@
+use anyhow::{Context, Result};
+use automerge::transaction::Transactable;
+use automerge::{ActorId, AutoCommit, ObjId, ObjType, ReadDoc, ROOT};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+use crate::model::note::Note;
+use crate::model::vault::Vault;
+use crate::util::manifest::Manifest;
+use crate::util::sync_lock;
+use crate::util::vault_lock::VaultLock;
+
+/// documents are read, merged and saved under one lock, every change this
+/// device makes is numbered in sequence for its actor
+static COLLAB_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+/// walks in this process take turns saving the note index
+static INDEX_WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// The body and footnotes of a collaborative note as an Automerge document,
+/// stored in .footnote/collab/<uuid>.automerge on every device that has the
+/// note. Edits made on disk are folded in before a sync, documents from the
+/// peer are merged, and the merged text is written back to the note.
+struct CollabDoc(AutoCommit);
+
+impl CollabDoc {
+    /// A document holding the note as it is. Only the device leader starts
+    /// one, for its own notes: documents started on two devices would each
+    /// have their own body, and merging them would keep only one.
+    fn start(note: &Note, actor: ActorId) -> Result<Self> {
+        let mut doc = AutoCommit::new().with_actor(actor);
+        let body = doc.put_object(ROOT, "body", ObjType::Text)?;
+        doc.update_text(&body, &note.content)?;
+        let footnotes = doc.put_object(ROOT, "footnotes", ObjType::Text)?;
+        doc.update_text(&footnotes, note.footnotes_text())?;
+        Ok(Self(doc))
+    }
+
+    fn load(bytes: &[u8], actor: ActorId) -> Result<Self> {
+        let doc = Self(AutoCommit::load(bytes)?.with_actor(actor));
+        // a document without both texts can't be rendered
+        doc.text("body")?;
+        doc.text("footnotes")?;
+        Ok(doc)
+    }
+
+    fn text_id(&self, key: &str) -> Result<ObjId> {
+        match self.0.get(ROOT, key)? {
+            Some((_, id)) => Ok(id),
+            None => anyhow::bail!("collaborative document has no {}", key),
+        }
+    }
+
+    fn text(&self, key: &str) -> Result<String> {
+        Ok(self.0.text(self.text_id(key)?)?)
+    }
+
+    /// take in edits made to the note on disk, true if there were any
+    fn absorb(&mut self, note: &Note) -> Result<bool> {
+        let mut changed = false;
+        for (key, text) in [
+            ("body", note.content.clone()),
+            ("footnotes", note.footnotes_text()),
+        ] {
+            if self.text(key)? != text {
+                let id = self.text_id(key)?;
+                self.0.update_text(&id, text)?;
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// write the merged body and footnotes into the note, true if it changed
+    fn render_into(&self, note: &mut Note) -> Result<bool> {
+        let body = self.text("body")?;
+        let footnotes = self.text("footnotes")?;
+        if note.content == body && note.footnotes_text() == footnotes {
+            return Ok(false);
+        }
+        note.content = body;
+        note.set_footnotes_text(&footnotes);
+        Ok(true)
+    }
+
+    fn heads(&mut self) -> Vec<String> {
+        let mut heads: Vec<String> = self.0.get_heads().iter().map(|h| h.to_string()).collect();
+        heads.sort();
+        heads
+    }
+}
+
+fn collab_dir(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("collab")
+}
+
+fn doc_path(vault_path: &Path, uuid: &Uuid) -> PathBuf {
+    collab_dir(vault_path).join(format!("{}.automerge", uuid))
+}
+
+fn exchanged_path(vault_path: &Path) -> PathBuf {
+    collab_dir(vault_path).join("exchanged.json")
+}
+
+fn index_path(vault_path: &Path) -> PathBuf {
+    collab_dir(vault_path).join("index.json")
+}
+
+fn device_actor(vault: &Vault) -> Result<ActorId> {
+    let (endpoint_id, _) = vault.device_public_key()?;
+    Ok(ActorId::from(endpoint_id.as_bytes()))
+}
+
+fn doc_read(vault: &Vault, uuid: &Uuid) -> Result<Option<CollabDoc>> {
+    let path = doc_path(&vault.path, uuid);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(&path)
+        .with_context(|| format!("Failed to read collaborative document: {}", path.display()))?;
+    Ok(Some(CollabDoc::load(&bytes, device_actor(vault)?)?))
+}
+
+fn doc_write(vault_path: &Path, uuid: &Uuid, doc: &mut CollabDoc) -> Result<()> {
+    let path = doc_path(vault_path, uuid);
+    fs::create_dir_all(collab_dir(vault_path))?;
+    let tmp = path.with_extension("automerge.tmp");
+    fs::write(&tmp, doc.0.save())?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// What the last walk found in a note file. A file with the same size and
+/// modification time isn't parsed again, so a walk only reads the notes
+/// that changed since.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct IndexedNote {
+    modified: SystemTime,
+    len: u64,
+    /// None for a .md file that isn't a note
+    uuid: Option<Uuid>,
+    collaborative: bool,
+}
+
+fn index_read(vault_path: &Path) -> HashMap<PathBuf, IndexedNote> {
+    // the index is only a cache, a missing or damaged one is rebuilt
+    fs::read_to_string(index_path(vault_path))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn index_write(vault_path: &Path, index: &HashMap<PathBuf, IndexedNote>) -> Result<()> {
+    let _guard = INDEX_WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    fs::create_dir_all(collab_dir(vault_path))?;
+    let path = index_path(vault_path);
+    // walks in other processes don't take the vault lock either
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp, serde_json::to_string(index)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// every note in the vault, ours and the ones contacts share with us, by
+/// uuid with its path relative to the vault and whether it's collaborative
+fn indexed_notes(vault_path: &Path) -> Result<HashMap<Uuid, (PathBuf, bool)>> {
+    let previous = index_read(vault_path);
+    let mut index = HashMap::new();
+    for entry in WalkDir::new(vault_path)
+        .follow_links(false)
+        .into_iter()
+        // the same notes create_manifest_full finds
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_str().unwrap_or("").starts_with('.'))
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !entry.file_type().is_file() || path.extension().and_then(|s| s.to_str()) != Some("md") {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let relative = path
+            .strip_prefix(vault_path)
+            .context("Failed to get relative path")?
+            .to_path_buf();
+        let modified = metadata.modified()?;
+        let len = metadata.len();
+        let indexed = match previous.get(&relative) {
+            Some(known) if known.modified == modified && known.len == len => known.clone(),
+            _ => {
+                let note = Note::from_path(path, false).ok();
+                IndexedNote {
+                    modified,
+                    len,
+                    uuid: note.as_ref().map(|n| n.frontmatter.uuid),
+                    collaborative: note.is_some_and(|n| n.frontmatter.collaborative),
+                }
+            }
+        };
+        index.insert(relative, indexed);
+    }
+    if index != previous {
+        index_write(vault_path, &index)?;
+    }
+
+    Ok(index
+        .into_iter()
+        .filter_map(|(relative, indexed)| Some((indexed.uuid?, (relative, indexed.collaborative))))
+        .collect())
+}
+
+/// every collaborative note in the vault, by uuid with its path relative to
+/// the vault
+fn collaborative_notes(vault_path: &Path) -> Result<HashMap<Uuid, PathBuf>> {
+    Ok(indexed_notes(vault_path)?
+        .into_iter()
+        .filter(|(_, (_, collaborative))| *collaborative)
+        .map(|(uuid, (relative, _))| (uuid, relative))
+        .collect())
+}
+
+/// notes shared with us by a contact are under footnotes/<nickname>/
+fn is_own(relative: &Path) -> bool {
+    !relative.starts_with("footnotes")
+}
+
+/// Fold edits made on disk to collaborative notes into their documents. The
+/// device leader starts a document for each of its own collaborative notes
+/// that doesn't have one yet.
+pub async fn absorb(vault: &Vault) -> Result<()> {
+    let collaborative = collaborative_notes(&vault.path)?;
+    if collaborative.is_empty() {
+        return Ok(());
+    }
+    let lock = COLLAB_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(&vault.path).await?;
+    let is_leader = vault.is_device_leader().unwrap_or(false);
+    for (uuid, relative) in collaborative {
+        let Ok(note) = Note::from_path(vault.path.join(&relative), false) else {
+            continue;
+        };
+        let mut doc = match doc_read(vault, &uuid)? {
+            Some(mut doc) => {
+                if !doc.absorb(&note)? {
+                    continue;
+                }
+                doc
+            }
+            None if is_leader && is_own(&relative) => {
+                CollabDoc::start(&note, device_actor(vault)?)?
+            }
+            None => continue,
+        };
+        doc_write(&vault.path, &uuid, &mut doc)?;
+    }
+    Ok(())
+}
+
+/// The notes whose documents a sync sends: the collaborative notes in the
+/// manifest, and for a share with a contact, their collaborative notes we
+/// have, which we may have edited.
+pub fn exchanged_notes(
+    vault: &Vault,
+    manifest: &Manifest,
+    share_with: Option<&str>,
+) -> Result<HashSet<Uuid>> {
+    let shared_by_contact = share_with.map(|nickname| Path::new("footnotes").join(nickname));
+    Ok(collaborative_notes(&vault.path)?
+        .into_iter()
+        .filter(|(uuid, relative)| {
+            manifest.contains_key(uuid)
+                || shared_by_contact
+                    .as_ref()
+                    .is_some_and(|dir| relative.starts_with(dir))
+        })
+        .map(|(uuid, _)| uuid)
+        .collect())
+}
+
+/// The notes whose documents a device mirror sends back: every
+/// collaborative note in the vault.
+pub fn mirrored_notes(vault: &Vault) -> Result<HashSet<Uuid>> {
+    Ok(collaborative_notes(&vault.path)?.into_keys().collect())
+}
+
+/// the saved documents of the given notes, those that have one
+pub fn docs_read(vault: &Vault, uuids: &HashSet<Uuid>) -> Result<Vec<(Uuid, Vec<u8>)>> {
+    let mut docs = Vec::new();
+    for uuid in uuids {
+        let path = doc_path(&vault.path, uuid);
+        if path.exists() {
+            docs.push((*uuid, fs::read(&path)?));
+        }
+    }
+    docs.sort_by_key(|(uuid, _)| *uuid);
+    Ok(docs)
+}
+
+/// Merge documents from a peer into ours, for the notes in `accepted` that
+/// are collaborative here or that we don't have yet. Returns the notes whose
+/// document changed.
+pub async fn merge(
+    vault: &Vault,
+    docs: Vec<(Uuid, Vec<u8>)>,
+    accepted: &HashSet<Uuid>,
+) -> Result<Vec<Uuid>> {
+    let notes = indexed_notes(&vault.path)?;
+    let lock = COLLAB_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(&vault.path).await?;
+    let actor = device_actor(vault)?;
+    let mut merged = Vec::new();
+    for (uuid, bytes) in docs {
+        if !accepted.contains(&uuid)
+            || notes
+                .get(&uuid)
+                .is_some_and(|(_, collaborative)| !collaborative)
+        {
+            tracing::warn!("ignoring collaborative document for {}", uuid);
+            continue;
+        }
+        let mut incoming = match CollabDoc::load(&bytes, actor.clone()) {
+            Ok(doc) => doc,
+            Err(e) => {
+                tracing::warn!("invalid collaborative document for {}: {}", uuid, e);
+                continue;
+            }
+        };
+        let mut doc = match doc_read(vault, &uuid)? {
+            Some(mut doc) => {
+                let before = doc.heads();
+                doc.0.merge(&mut incoming.0)?;
+                if doc.heads() == before {
+                    continue;
+                }
+                doc
+            }
+            None => incoming,
+        };
+        doc_write(&vault.path, &uuid, &mut doc)?;
+        merged.push(uuid);
+    }
+    Ok(merged)
+}
+
+/// Write the body and footnotes of each note from its document. Our own
+/// notes are saved as an edit made here, so the merge reaches our other
+/// devices and contacts. A note a contact shares with us keeps their version.
+pub async fn render(vault: &Vault, uuids: &[Uuid]) -> Result<()> {
+    if uuids.is_empty() {
+        return Ok(());
+    }
+    let mut collaborative = collaborative_notes(&vault.path)?;
+    let lock = COLLAB_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _note_guard = sync_lock::note_lock().await;
+    let _vault_lock = VaultLock::acquire_async(&vault.path).await?;
+    for uuid in uuids {
+        let (Some(relative), Some(doc)) = (collaborative.remove(uuid), doc_read(vault, uuid)?)
+        else {
+            continue;
+        };
+        let path = vault.path.join(&relative);
+        let Ok(mut note) = Note::from_path(&path, false) else {
+            continue;
+        };
+        if !doc.render_into(&mut note)? {
+            continue;
+        }
+        if is_own(&relative) {
+            note.to_file(&path, &vault.clock())?;
+        } else {
+            sync_lock::write_atomic(&path, note.to_string()?.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// A received collaborative note with the body and footnotes of our
+/// document, which was merged with the sender's before the notes arrive.
+/// Anything else is returned as it came.
+pub fn with_doc_content(vault: &Vault, contents: Vec<u8>) -> Result<Vec<u8>> {
+    let Ok(mut note) = Note::from_string(&String::from_utf8_lossy(&contents), false) else {
+        return Ok(contents);
+    };
+    if !note.frontmatter.collaborative {
+        return Ok(contents);
+    }
+    let Some(doc) = doc_read(vault, &note.frontmatter.uuid)? else {
+        return Ok(contents);
+    };
+    if !doc.render_into(&mut note)? {
+        return Ok(contents);
+    }
+    Ok(note.to_string()?.into_bytes())
+}
+
+/// whether a document we'd send the contact, with the notes in `manifest`
+/// shared with them, changed since we last exchanged documents with them
+pub fn has_unsent(vault: &Vault, nickname: &str, manifest: &Manifest) -> Result<bool> {
+    let exchanged = exchanged_read(&vault.path)?;
+    let known = exchanged.get(nickname);
+    for uuid in exchanged_notes(vault, manifest, Some(nickname))? {
+        let Some(mut doc) = doc_read(vault, &uuid)? else {
+            continue;
+        };
+        if known.and_then(|k| k.get(&uuid)) != Some(&doc.heads()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn exchanged_read(vault_path: &Path) -> Result<HashMap<String, HashMap<Uuid, Vec<String>>>> {
+    let path = exchanged_path(vault_path);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read exchanged documents: {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse exchanged documents")
+}
+
+/// note that the contact now has our documents of these notes
+pub async fn record_exchanged(vault: &Vault, nickname: &str, uuids: &HashSet<Uuid>) -> Result<()> {
+    let lock = COLLAB_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(&vault.path).await?;
+    let mut exchanged = exchanged_read(&vault.path)?;
+    let known = exchanged.entry(nickname.to_string()).or_default();
+    for uuid in uuids {
+        if let Some(mut doc) = doc_read(vault, uuid)? {
+            known.insert(*uuid, doc.heads());
+        }
+    }
+
+    let path = exchanged_path(&vault.path);
+    fs::create_dir_all(collab_dir(&vault.path))?;
+    let json = serde_json::to_string_pretty(&exchanged)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hybrid_timestamp::DeviceClock;
+
+    #[test]
+    fn test_concurrent_edits_merge() {
+        let laptop = ActorId::from([1u8; 32].as_slice());
+        let phone = ActorId::from([2u8; 32].as_slice());
+        let mut note = Note::new(&DeviceClock::default());
+        note.content = "milk\neggs".to_string();
+        note.set_footnotes_text("[1]: from the market\n");
+
+        let mut on_laptop = CollabDoc::start(&note, laptop.clone()).unwrap();
+        let mut on_phone = CollabDoc::load(&on_laptop.0.save(), phone).unwrap();
+
+        let mut edited = note.clone();
+        edited.content = "milk\nbread\neggs".to_string();
+        assert!(on_laptop.absorb(&edited).unwrap());
+        edited = note.clone();
+        edited.content = "milk\neggs\nbutter".to_string();
+        edited.set_footnotes_text("[1]: from the market\n[2]: salted\n");
+        assert!(on_phone.absorb(&edited).unwrap());
+        assert!(!on_phone.absorb(&edited).unwrap());
+
+        on_laptop.0.merge(&mut on_phone.0).unwrap();
+        on_phone.0.merge(&mut on_laptop.0).unwrap();
+        assert_eq!(on_laptop.heads(), on_phone.heads());
+
+        let mut rendered = note.clone();
+        assert!(on_phone.render_into(&mut rendered).unwrap());
+        assert_eq!(rendered.content, "milk\nbread\neggs\nbutter");
+        assert_eq!(rendered.footnotes.len(), 2);
+    }
+
+    #[test]
+    fn test_index_follows_edits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let clock = DeviceClock::default();
+        let path = dir.path().join("list.md");
+        let note = Note::create(&path, "milk", &clock).unwrap();
+        fs::write(dir.path().join("readme.md"), "not a note").unwrap();
+        assert!(collaborative_notes(dir.path()).unwrap().is_empty());
+
+        let index = index_read(dir.path());
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[Path::new("readme.md")].uuid, None);
+
+        let mut edited = Note::from_path(&path, false).unwrap();
+        edited.frontmatter.collaborative = true;
+        edited.to_file(&path, &clock).unwrap();
+        let collaborative = collaborative_notes(dir.path()).unwrap();
+        assert_eq!(
+            collaborative[&note.frontmatter.uuid],
+            PathBuf::from("list.md")
+        );
+        assert!(index_read(dir.path())[Path::new("list.md")].collaborative);
+    }
+}
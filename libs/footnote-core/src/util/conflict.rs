@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::util::hybrid_timestamp::HybridTimestamp;
+use crate::util::lamport_timestamp::LamportTimestamp;
+
+/// A version of a note that lost to an edit made on another of our devices
+/// alongside it: a local version a mirror replaced or deleted before it had
+/// been exchanged, or an older incoming one the mirror didn't write. The
+/// version is copied to .footnote/conflicts/, so an edit made on two devices
+/// at once is never lost without a trace.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ConflictRecord {
+    pub uuid: Uuid,
+    /// where the note was, relative to the vault
+    pub path: PathBuf,
+    /// the version left at the path, None if the mirror deleted the note
+    pub kept: Option<HybridTimestamp>,
+    /// the version that was copied
+    pub replaced: HybridTimestamp,
+    /// the copy, relative to the vault
+    pub copy: PathBuf,
+    /// the device the mirror came from
+    pub endpoint_id: String,
+    pub recorded_at: LamportTimestamp,
+}
+
+fn conflicts_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("conflicts.json")
+}
+
+fn sync_base_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("sync_base.json")
+}
+
+pub fn conflicts_read(vault_path: &Path) -> Result<Vec<ConflictRecord>> {
+    let path = conflicts_path(vault_path);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read conflicts: {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse conflicts")
+}
+
+/// For every note, the last version received from another of our devices.
+/// A local version newer than this has not been mirrored anywhere yet.
+pub fn sync_base_read(vault_path: &Path) -> Result<HashMap<Uuid, HybridTimestamp>> {
+    let path = sync_base_path(vault_path);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read sync base: {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse sync base")
+}
+
+/// the caller holds the note write lock
+pub(crate) fn sync_base_set(
+    vault_path: &Path,
+    uuid: Uuid,
+    modified: Option<HybridTimestamp>,
+) -> Result<()> {
+    let mut base = sync_base_read(vault_path)?;
+    match modified {
+        Some(modified) => base.insert(uuid, modified),
+        None => base.remove(&uuid),
+    };
+    write_json(&sync_base_path(vault_path), &base)
+}
+
+/// Copy a local version about to be replaced or deleted by a mirror, unless
+/// it came from another device or the incoming contents are the same. The
+/// caller holds the note write lock.
+pub(crate) fn preserve_unsynced(
+    vault_path: &Path,
+    path: &Path,
+    uuid: Uuid,
+    replaced: HybridTimestamp,
+    incoming: Option<(&[u8], HybridTimestamp)>,
+    endpoint_id: &str,
+) -> Result<()> {
+    let base = sync_base_read(vault_path)?;
+    if base.get(&uuid).is_some_and(|synced| replaced <= *synced) {
+        return Ok(());
+    }
+    let contents = fs::read(path)?;
+    if incoming.is_some_and(|(incoming, _)| incoming == contents.as_slice()) {
+        return Ok(());
+    }
+    keep_copy(
+        vault_path,
+        path,
+        uuid,
+        &contents,
+        replaced,
+        incoming.map(|(_, modified)| modified),
+        endpoint_id,
+    )
+}
+
+/// Copy a version from a mirror that is older than the local one it was
+/// edited alongside, which stays. The caller holds the note write lock.
+pub(crate) fn preserve_incoming(
+    vault_path: &Path,
+    path: &Path,
+    uuid: Uuid,
+    kept: HybridTimestamp,
+    incoming: (&[u8], HybridTimestamp),
+    endpoint_id: &str,
+) -> Result<()> {
+    let (contents, replaced) = incoming;
+    if fs::read(path).is_ok_and(|local| local == contents) {
+        return Ok(());
+    }
+    keep_copy(
+        vault_path,
+        path,
+        uuid,
+        contents,
+        replaced,
+        Some(kept),
+        endpoint_id,
+    )
+}
+
+fn keep_copy(
+    vault_path: &Path,
+    path: &Path,
+    uuid: Uuid,
+    contents: &[u8],
+    replaced: HybridTimestamp,
+    kept: Option<HybridTimestamp>,
+    endpoint_id: &str,
+) -> Result<()> {
+    let copy = PathBuf::from(".footnote")
+        .join("conflicts")
+        .join(format!("{}-{}.md", uuid, replaced));
+    let copy_path = vault_path.join(&copy);
+    if copy_path.exists() {
+        // this version was kept already
+        return Ok(());
+    }
+    fs::create_dir_all(vault_path.join(".footnote").join("conflicts"))?;
+    fs::write(&copy_path, contents)?;
+    tracing::warn!(
+        "kept concurrent version {} of {} in {}",
+        replaced,
+        path.display(),
+        copy.display()
+    );
+
+    let mut records = conflicts_read(vault_path)?;
+    records.push(ConflictRecord {
+        uuid,
+        path: vault_relative(vault_path, path),
+        kept,
+        replaced,
+        copy,
+        endpoint_id: endpoint_id.to_string(),
+        recorded_at: LamportTimestamp::now(),
+    });
+    write_json(&conflicts_path(vault_path), &records)
+}
+
+/// received notes are written to canonical paths, the vault path may not be
+fn vault_relative(vault_path: &Path, path: &Path) -> PathBuf {
+    if let Ok(relative) = path.strip_prefix(vault_path) {
+        return relative.to_path_buf();
+    }
+    vault_path
+        .canonicalize()
+        .ok()
+        .and_then(|canonical| path.strip_prefix(canonical).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(value)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
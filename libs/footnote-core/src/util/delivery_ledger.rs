@@ -0,0 +1,509 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::util::hybrid_timestamp::HybridTimestamp;
+use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::manifest::{Manifest, ManifestEntry};
+use crate::util::tombstone::Tombstone;
+use crate::util::vault_lock::VaultLock;
+
+static DELIVERY_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+/// The newest version of a note a contact is known to have, and which of
+/// their devices it went to. withdrawn is set once the note is no longer
+/// shared with the contact and a share has removed it from their copy.
+///
+/// named is set when the note's share_with named the contact, or a group
+/// they are in. Only those notes are withdrawn when they stop being shared:
+/// share rules are kept per device, and another of our devices may still
+/// share a note by a rule this one doesn't have.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Delivery {
+    pub path: PathBuf,
+    pub modified: HybridTimestamp,
+    pub delivered_at: LamportTimestamp,
+    pub endpoint_id: String,
+    pub device_name: String,
+    #[serde(default)]
+    pub withdrawn: bool,
+    #[serde(default)]
+    pub named: bool,
+}
+
+impl Delivery {
+    /// deliveries are ordered by note version, then by when they happened
+    fn is_newer_than(&self, other: &Delivery) -> bool {
+        (self.modified, self.delivered_at) > (other.modified, other.delivered_at)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeliveryStatus {
+    Delivered,
+    Pending,
+}
+
+/// Per contact record of shared notes delivered to them, stored in
+/// .footnote/delivery/<nickname>.json. Unlike the recent files in
+/// `SyncStatusRecord` every note shared with the contact is kept.
+///
+/// The ledger is mirrored between our own devices so whichever device is
+/// online can tell what the contact already has, and does not push again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeliveryLedger {
+    #[serde(default)]
+    pub notes: HashMap<Uuid, Delivery>,
+}
+
+impl DeliveryLedger {
+    fn ledger_path(vault_path: &Path, nickname: &str) -> PathBuf {
+        vault_path
+            .join(".footnote")
+            .join("delivery")
+            .join(format!("{}.json", nickname))
+    }
+
+    pub fn read(vault_path: &Path, nickname: &str) -> Result<Self> {
+        let path = Self::ledger_path(vault_path, nickname);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let json = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read delivery ledger: {}", path.display()))?;
+        serde_json::from_str(&json).context("Failed to parse delivery ledger")
+    }
+
+    fn write(&self, vault_path: &Path, nickname: &str) -> Result<()> {
+        let path = Self::ledger_path(vault_path, nickname);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// delivered if the contact has the version modified at, or newer
+    pub fn status(&self, uuid: &Uuid, modified: HybridTimestamp) -> DeliveryStatus {
+        match self.notes.get(uuid) {
+            Some(delivery) if !delivery.withdrawn && delivery.modified >= modified => {
+                DeliveryStatus::Delivered
+            }
+            _ => DeliveryStatus::Pending,
+        }
+    }
+
+    /// the contact has every note in the share manifest, at that version, and
+    /// there is nothing to withdraw, see `withdrawals`
+    pub fn is_up_to_date(&self, manifest: &Manifest, withdrawals: &[Tombstone]) -> bool {
+        withdrawals.is_empty()
+            && manifest
+                .values()
+                .all(|e| self.status(&e.uuid, e.modified) == DeliveryStatus::Delivered)
+    }
+
+    /// one of our other devices delivered a newer version of a note than the
+    /// one in the share manifest, so this device hasn't been mirrored to yet
+    pub fn is_behind(&self, manifest: &Manifest) -> bool {
+        manifest.values().any(|e| {
+            self.notes
+                .get(&e.uuid)
+                .is_some_and(|d| d.modified > e.modified)
+        })
+    }
+
+    /// Tombstones for notes the contact was given that we deleted, or whose
+    /// share_with no longer names them, so a share removes their copy. A note
+    /// they got by a share rule is left alone, see `Delivery`. Each is dated
+    /// by our version of the note, or when we deleted it, and only removes
+    /// older copies.
+    pub fn withdrawals(
+        &self,
+        manifest: &Manifest,
+        vault_manifest: &Manifest,
+        tombstones: &[Tombstone],
+    ) -> Vec<Tombstone> {
+        self.notes
+            .iter()
+            .filter(|(uuid, d)| !d.withdrawn && !manifest.contains_key(uuid))
+            .filter_map(|(uuid, d)| {
+                let deleted = tombstones
+                    .iter()
+                    .find(|t| &t.uuid == uuid)
+                    .map(|t| t.deleted_at);
+                let unshared = vault_manifest
+                    .get(uuid)
+                    .filter(|_| d.named)
+                    .map(|e| e.modified);
+                // a note missing without a tombstone hasn't been mirrored here
+                let ours = deleted.or(unshared)?;
+                Some(Tombstone {
+                    uuid: *uuid,
+                    deleted_at: ours.max(d.modified),
+                })
+            })
+            .collect()
+    }
+
+    /// an older version never replaces a newer one already delivered
+    pub fn record(
+        &mut self,
+        entry: &ManifestEntry,
+        endpoint_id: &str,
+        device_name: &str,
+        delivered_at: LamportTimestamp,
+        named: bool,
+    ) {
+        if let Some(existing) = self.notes.get(&entry.uuid) {
+            if existing.modified > entry.modified {
+                return;
+            }
+        }
+        self.notes.insert(
+            entry.uuid,
+            Delivery {
+                path: entry.path.clone(),
+                modified: entry.modified,
+                delivered_at,
+                endpoint_id: endpoint_id.to_string(),
+                device_name: device_name.to_string(),
+                withdrawn: false,
+                named,
+            },
+        );
+    }
+
+    /// after a successful share the withdrawals sent with it were removed
+    /// from the contact's copy
+    pub fn record_withdrawn(&mut self, withdrawals: &[Tombstone], withdrawn_at: LamportTimestamp) {
+        for withdrawal in withdrawals {
+            if let Some(delivery) = self.notes.get_mut(&withdrawal.uuid) {
+                delivery.withdrawn = true;
+                delivery.delivered_at = withdrawn_at;
+            }
+        }
+    }
+
+    /// merge a ledger mirrored from another of our devices, keeping the newest
+    /// delivery of each note
+    pub fn merge(&mut self, other: DeliveryLedger) {
+        for (uuid, delivery) in other.notes {
+            match self.notes.get(&uuid) {
+                Some(existing) if !delivery.is_newer_than(existing) => {}
+                _ => {
+                    self.notes.insert(uuid, delivery);
+                }
+            }
+        }
+    }
+}
+
+/// every ledger in the vault by contact nickname, sent on mirror
+pub fn delivery_ledgers_read(vault_path: &Path) -> Result<HashMap<String, DeliveryLedger>> {
+    let mut ledgers = HashMap::new();
+    let dir = vault_path.join(".footnote").join("delivery");
+    if !dir.exists() {
+        return Ok(ledgers);
+    }
+    for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().and_then(|s| s.to_str()) != Some("json") {
+            continue;
+        }
+        let Some(nickname) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        ledgers.insert(
+            nickname.to_string(),
+            DeliveryLedger::read(vault_path, nickname)?,
+        );
+    }
+    Ok(ledgers)
+}
+
+/// merge a contact's ledger mirrored from another of our devices
+pub async fn delivery_merge(
+    vault_path: &Path,
+    nickname: &str,
+    incoming: DeliveryLedger,
+) -> Result<()> {
+    let lock = DELIVERY_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    let mut ledger = DeliveryLedger::read(vault_path, nickname)?;
+    ledger.merge(incoming);
+    ledger.write(vault_path, nickname)
+}
+
+/// What a successful share gave one of the contact's devices.
+pub struct SharedNotes<'a> {
+    /// it has every version in the manifest
+    pub manifest: &'a Manifest,
+    /// except these, it was not allowed to read them
+    pub withheld: &'a [Uuid],
+    /// notes whose share_with names the contact, or a group they are in
+    pub named: &'a HashSet<Uuid>,
+    /// removed from its copy
+    pub withdrawals: &'a [Tombstone],
+}
+
+/// record a successful share to a contact's device
+pub async fn delivery_record(
+    vault_path: &Path,
+    nickname: &str,
+    endpoint_id: &str,
+    device_name: &str,
+    shared: SharedNotes<'_>,
+) -> Result<()> {
+    let lock = DELIVERY_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    let mut ledger = DeliveryLedger::read(vault_path, nickname)?;
+    let delivered_at = LamportTimestamp::now();
+    for entry in shared
+        .manifest
+        .values()
+        .filter(|e| !shared.withheld.contains(&e.uuid))
+    {
+        let named = shared.named.contains(&entry.uuid);
+        ledger.record(entry, endpoint_id, device_name, delivered_at, named);
+    }
+    ledger.record_withdrawn(shared.withdrawals, delivered_at);
+    ledger.write(vault_path, nickname)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::version_vector::VersionVector;
+
+    fn entry(uuid: Uuid, modified: i64) -> ManifestEntry {
+        ManifestEntry {
+            uuid,
+            path: PathBuf::from("recipes.md"),
+            modified: HybridTimestamp::from_secs(modified),
+            versions: VersionVector::default(),
+        }
+    }
+
+    #[test]
+    fn test_status_pending_until_latest_delivered() {
+        let uuid = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        assert_eq!(
+            ledger.status(&uuid, HybridTimestamp::from_secs(10)),
+            DeliveryStatus::Pending
+        );
+
+        ledger.record(
+            &entry(uuid, 10),
+            "endpoint",
+            "laptop",
+            LamportTimestamp(20),
+            true,
+        );
+        assert_eq!(
+            ledger.status(&uuid, HybridTimestamp::from_secs(10)),
+            DeliveryStatus::Delivered
+        );
+        assert_eq!(
+            ledger.status(&uuid, HybridTimestamp::from_secs(11)),
+            DeliveryStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_record_never_goes_backwards() {
+        let uuid = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        ledger.record(
+            &entry(uuid, 10),
+            "laptop-endpoint",
+            "laptop",
+            LamportTimestamp(20),
+            true,
+        );
+        ledger.record(
+            &entry(uuid, 5),
+            "phone-endpoint",
+            "phone",
+            LamportTimestamp(30),
+            true,
+        );
+
+        let delivery = &ledger.notes[&uuid];
+        assert_eq!(delivery.modified, HybridTimestamp::from_secs(10));
+        assert_eq!(delivery.device_name, "laptop");
+    }
+
+    fn manifest(entries: &[ManifestEntry]) -> Manifest {
+        entries.iter().map(|e| (e.uuid, e.clone())).collect()
+    }
+
+    #[test]
+    fn test_up_to_date_and_withdrawn() {
+        let kept = Uuid::new_v4();
+        let unshared = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        ledger.record(
+            &entry(kept, 10),
+            "endpoint",
+            "laptop",
+            LamportTimestamp(20),
+            true,
+        );
+        ledger.record(
+            &entry(unshared, 10),
+            "endpoint",
+            "laptop",
+            LamportTimestamp(20),
+            true,
+        );
+
+        let current = manifest(&[entry(kept, 10)]);
+        let vault = manifest(&[entry(kept, 10), entry(unshared, 10)]);
+        let withdrawals = ledger.withdrawals(&current, &vault, &[]);
+        assert!(!ledger.is_up_to_date(&current, &withdrawals));
+
+        ledger.record_withdrawn(&withdrawals, LamportTimestamp(30));
+        assert!(ledger.withdrawals(&current, &vault, &[]).is_empty());
+        assert!(ledger.is_up_to_date(&current, &[]));
+
+        // sharing it again makes it pending
+        let reshared = manifest(&[entry(kept, 10), entry(unshared, 10)]);
+        assert!(!ledger.is_up_to_date(&reshared, &[]));
+    }
+
+    #[test]
+    fn test_behind_when_newer_version_delivered() {
+        let uuid = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        ledger.record(
+            &entry(uuid, 10),
+            "endpoint",
+            "laptop",
+            LamportTimestamp(20),
+            true,
+        );
+
+        assert!(!ledger.is_behind(&manifest(&[entry(uuid, 10)])));
+        assert!(ledger.is_behind(&manifest(&[entry(uuid, 9)])));
+    }
+
+    #[test]
+    fn test_merge_keeps_newest_delivery() {
+        let uuid = Uuid::new_v4();
+        let mut phone = DeliveryLedger::default();
+        phone.record(
+            &entry(uuid, 10),
+            "endpoint",
+            "phone",
+            LamportTimestamp(20),
+            true,
+        );
+
+        let mut desktop = DeliveryLedger::default();
+        desktop.record(
+            &entry(uuid, 10),
+            "endpoint",
+            "desktop",
+            LamportTimestamp(20),
+            true,
+        );
+        let withdrawal = Tombstone {
+            uuid,
+            deleted_at: HybridTimestamp::from_secs(10),
+        };
+        desktop.record_withdrawn(&[withdrawal], LamportTimestamp(30));
+
+        phone.merge(desktop.clone());
+        assert!(phone.notes[&uuid].withdrawn);
+
+        // an older ledger never undoes a newer delivery
+        desktop.merge(DeliveryLedger::default());
+        let mut stale = DeliveryLedger::default();
+        stale.record(
+            &entry(uuid, 10),
+            "endpoint",
+            "phone",
+            LamportTimestamp(20),
+            true,
+        );
+        desktop.merge(stale);
+        assert!(desktop.notes[&uuid].withdrawn);
+    }
+
+    #[test]
+    fn test_withdrawals_dated_by_our_version() {
+        let unshared = Uuid::new_v4();
+        let deleted = Uuid::new_v4();
+        let still_shared = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        for uuid in [unshared, deleted, still_shared] {
+            ledger.record(
+                &entry(uuid, 10),
+                "endpoint",
+                "phone",
+                LamportTimestamp(20),
+                true,
+            );
+        }
+
+        let manifest = Manifest::from([(still_shared, entry(still_shared, 10))]);
+        let vault_manifest = Manifest::from([
+            (still_shared, entry(still_shared, 10)),
+            (unshared, entry(unshared, 15)),
+        ]);
+        let tombstones = [Tombstone {
+            uuid: deleted,
+            deleted_at: HybridTimestamp::from_secs(12),
+        }];
+        let withdrawals: HashMap<_, _> = ledger
+            .withdrawals(&manifest, &vault_manifest, &tombstones)
+            .into_iter()
+            .map(|t| (t.uuid, t.deleted_at))
+            .collect();
+        assert_eq!(withdrawals.len(), 2);
+        assert_eq!(withdrawals[&unshared], HybridTimestamp::from_secs(15));
+        assert_eq!(withdrawals[&deleted], HybridTimestamp::from_secs(12));
+    }
+
+    #[test]
+    fn test_notes_shared_by_rule_are_not_withdrawn() {
+        // delivered by another of our devices, by a share rule this one
+        // doesn't have
+        let by_rule = Uuid::new_v4();
+        let mut ledger = DeliveryLedger::default();
+        ledger.record(
+            &entry(by_rule, 10),
+            "endpoint",
+            "phone",
+            LamportTimestamp(20),
+            false,
+        );
+
+        let vault_manifest = manifest(&[entry(by_rule, 10)]);
+        let withdrawals = ledger.withdrawals(&Manifest::new(), &vault_manifest, &[]);
+        assert!(withdrawals.is_empty());
+        assert!(ledger.is_up_to_date(&Manifest::new(), &withdrawals));
+
+        // deleting it still removes their copy
+        let tombstones = [Tombstone {
+            uuid: by_rule,
+            deleted_at: HybridTimestamp::from_secs(12),
+        }];
+        assert_eq!(
+            ledger
+                .withdrawals(&Manifest::new(), &Manifest::new(), &tombstones)
+                .len(),
+            1
+        );
+    }
+}
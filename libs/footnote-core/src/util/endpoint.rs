@@ -0,0 +1,193 @@
+use anyhow::{Context, Result};
+use iroh::discovery::mdns::MdnsDiscovery;
+use iroh::discovery::static_provider::StaticProvider;
+use iroh::{Endpoint, EndpointAddr, RelayMap, RelayMode, RelayUrl, SecretKey};
+
+use crate::model::settings::VaultSettings;
+use crate::model::vault::Vault;
+use crate::util::peer_address;
+
+/// Every iroh endpoint footnote binds is built here, so the sync listener,
+/// pushes and pairing all honor the vault's network settings.
+pub async fn endpoint_bind(
+    vault: &Vault,
+    secret_key: SecretKey,
+    alpns: Vec<Vec<u8>>,
+) -> Result<Endpoint> {
+    let network = NetworkPlan::for_settings(&vault.settings_read()?)?;
+    let mut builder = if network.public_discovery {
+        Endpoint::builder().relay_mode(network.relay_mode)
+    } else {
+        Endpoint::empty_builder(network.relay_mode)
+    };
+    if network.local_discovery {
+        builder = builder.discovery(MdnsDiscovery::builder());
+    }
+    builder = builder.discovery(known_addresses(vault)?);
+
+    builder
+        .secret_key(secret_key)
+        .alpns(alpns)
+        .bind()
+        .await
+        .context("could not bind endpoint")
+}
+
+/// How `endpoint_bind` reaches other devices with the vault's settings.
+#[derive(Debug, PartialEq)]
+struct NetworkPlan {
+    relay_mode: RelayMode,
+    /// publish our address to, and look others up in, the public discovery
+    /// service
+    public_discovery: bool,
+    /// mDNS on the local network
+    local_discovery: bool,
+}
+
+impl NetworkPlan {
+    fn for_settings(settings: &VaultSettings) -> Result<Self> {
+        let relay_mode = match relay_map(settings)? {
+            Some(relay_map) => RelayMode::Custom(relay_map),
+            None if settings.disable_public_relays => RelayMode::Disabled,
+            None => RelayMode::Default,
+        };
+        Ok(Self {
+            relay_mode,
+            // the default discovery publishes through public servers too
+            public_discovery: !settings.disable_public_relays,
+            local_discovery: !settings.disable_local_discovery,
+        })
+    }
+}
+
+/// the vault's own relays, None to use the defaults
+pub fn relay_map(settings: &VaultSettings) -> Result<Option<RelayMap>> {
+    if settings.relay_urls.is_empty() {
+        return Ok(None);
+    }
+    let urls = settings
+        .relay_urls
+        .iter()
+        .map(|url| relay_url_parse(url))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(RelayMap::from_iter(urls)))
+}
+
+pub fn relay_url_parse(url: &str) -> Result<RelayUrl> {
+    url.parse()
+        .with_context(|| format!("invalid relay url: {}", url))
+}
+
+/// Where each of our devices and contacts' devices was last reached, and
+/// the relay it announced in its record, so they can be dialed without a
+/// discovery lookup.
+fn known_addresses(vault: &Vault) -> Result<StaticProvider> {
+    let provider = StaticProvider::new();
+    for addr in peer_address::peer_addresses_fresh(&vault.path)? {
+        provider.add_endpoint_info(addr);
+    }
+    let mut devices = vault
+        .contact_read()?
+        .into_iter()
+        .flat_map(|c| c.devices)
+        .collect::<Vec<_>>();
+    if let Some(user_record) = vault.user_read()? {
+        devices.extend(user_record.devices);
+    }
+    for device in devices {
+        let Some(relay_url) = device.relay_url else {
+            continue;
+        };
+        let (Ok(endpoint_id), Ok(relay_url)) =
+            (device.iroh_endpoint_id.parse(), relay_url_parse(&relay_url))
+        else {
+            tracing::warn!("ignoring bad relay for device {}", device.name);
+            continue;
+        };
+        provider.add_endpoint_info(EndpointAddr::new(endpoint_id).with_relay_url(relay_url));
+    }
+    Ok(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relay_map_from_settings() {
+        let settings = VaultSettings::default();
+        assert!(relay_map(&settings).unwrap().is_none());
+
+        let settings = VaultSettings {
+            relay_urls: vec!["https://relay.example.com".to_string()],
+            ..Default::default()
+        };
+        let relay_map = relay_map(&settings).unwrap().unwrap();
+        assert_eq!(relay_map.len(), 1);
+
+        let settings = VaultSettings {
+            relay_urls: vec!["not a url".to_string()],
+            ..Default::default()
+        };
+        assert!(super::relay_map(&settings).is_err());
+    }
+
+    #[test]
+    fn test_network_plan_for_each_setting() {
+        let plan = |disable_public_relays, disable_local_discovery| {
+            NetworkPlan::for_settings(&VaultSettings {
+                disable_public_relays,
+                disable_local_discovery,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        assert_eq!(
+            plan(false, false),
+            NetworkPlan {
+                relay_mode: RelayMode::Default,
+                public_discovery: true,
+                local_discovery: true,
+            }
+        );
+        assert_eq!(
+            plan(true, false),
+            NetworkPlan {
+                relay_mode: RelayMode::Disabled,
+                public_discovery: false,
+                local_discovery: true,
+            }
+        );
+        assert_eq!(
+            plan(false, true),
+            NetworkPlan {
+                relay_mode: RelayMode::Default,
+                public_discovery: true,
+                local_discovery: false,
+            }
+        );
+        assert_eq!(
+            plan(true, true),
+            NetworkPlan {
+                relay_mode: RelayMode::Disabled,
+                public_discovery: false,
+                local_discovery: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_network_plan_uses_own_relays() {
+        let settings = VaultSettings {
+            relay_urls: vec!["https://relay.example.com".to_string()],
+            disable_public_relays: true,
+            ..Default::default()
+        };
+        let plan = NetworkPlan::for_settings(&settings).unwrap();
+        assert_eq!(
+            plan.relay_mode,
+            RelayMode::Custom(relay_map(&settings).unwrap().unwrap())
+        );
+        assert!(!plan.public_discovery);
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::util::lamport_timestamp::LamportTimestamp;
+
+/// a version from further ahead of our wall clock than this comes from a
+/// device with a broken clock, following it would hold every later edit here
+/// to that time
+const MAX_DRIFT_SECS: i64 = 60 * 60;
+
+/// Hybrid logical clock timestamp for note versions.
+///
+/// Whole seconds of wall clock, a counter for edits made within the same
+/// second or while this device's clock is behind the version being edited,
+/// and the device that made the edit. Ordered by all three in turn, so two
+/// edits on different devices never compare equal and every device picks the
+/// same winner.
+///
+/// Versions written before the clock existed are plain integers. They read
+/// as counter 0 of device 0 and are written back as integers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HybridTimestamp {
+    pub physical: i64,
+    pub counter: u32,
+    pub device: u64,
+}
+
+impl HybridTimestamp {
+    pub fn from_secs(physical: i64) -> Self {
+        Self {
+            physical,
+            counter: 0,
+            device: 0,
+        }
+    }
+
+    /// Timestamp for an edit made on `device`. Always after `previous`, the
+    /// version of the note that was edited, and on the wall clock when this
+    /// device's clock is ahead of it.
+    pub fn tick(previous: Option<HybridTimestamp>, device: u64) -> Self {
+        let wall = Utc::now().timestamp();
+        match previous {
+            Some(previous) if previous.physical >= wall => Self {
+                physical: previous.physical,
+                counter: previous.counter.saturating_add(1),
+                device,
+            },
+            _ => Self {
+                physical: wall,
+                counter: 0,
+                device,
+            },
+        }
+    }
+
+    /// the device part of timestamps made by an endpoint
+    pub fn device_id(endpoint_id: &iroh::PublicKey) -> u64 {
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&endpoint_id.as_bytes()[..8]);
+        u64::from_be_bytes(prefix)
+    }
+
+    /// the wall clock part, for display and for comparing with other times
+    pub fn to_lamport(self) -> LamportTimestamp {
+        LamportTimestamp(self.physical)
+    }
+
+    pub fn relative_time_string(self) -> String {
+        self.to_lamport().relative_time_string()
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.counter == 0 && self.device == 0
+    }
+}
+
+/// The clock edits made on a device are stamped with: the device part of its
+/// timestamps, and the latest version it has received from another device.
+/// Every edit sorts after what the device has received, even when its wall
+/// clock is behind the device that made it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceClock {
+    pub device: u64,
+    pub observed: Option<HybridTimestamp>,
+}
+
+impl DeviceClock {
+    /// Timestamp for an edit made on this device. After `previous`, the
+    /// version of the note that was edited, and after every version received.
+    pub fn tick(&self, previous: Option<HybridTimestamp>) -> HybridTimestamp {
+        HybridTimestamp::tick(previous.max(self.observed), self.device)
+    }
+}
+
+fn clock_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("clock")
+}
+
+/// the latest version this device has received, see `DeviceClock`
+pub fn clock_read(vault_path: &Path) -> Result<Option<HybridTimestamp>> {
+    let path = clock_path(vault_path);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read clock: {}", path.display()))?;
+    Ok(Some(text.trim().parse()?))
+}
+
+/// Move the clock of the vault past a version received from another device.
+/// The caller holds the VaultLock.
+pub fn clock_receive(vault_path: &Path, remote: HybridTimestamp) -> Result<()> {
+    if remote.physical > Utc::now().timestamp() + MAX_DRIFT_SECS {
+        tracing::warn!("not following version {} from the future", remote);
+        return Ok(());
+    }
+    if clock_read(vault_path)?.is_some_and(|observed| observed >= remote) {
+        return Ok(());
+    }
+    let path = clock_path(vault_path);
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, remote.to_string())?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+impl From<LamportTimestamp> for HybridTimestamp {
+    fn from(timestamp: LamportTimestamp) -> Self {
+        Self::from_secs(timestamp.as_i64())
+    }
+}
+
+/// `<seconds>-<counter>-<device as 16 hex digits>`, or only the seconds for
+/// a legacy version
+impl fmt::Display for HybridTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_legacy() {
+            write!(f, "{}", self.physical)
+        } else {
+            write!(f, "{}-{}-{:016x}", self.physical, self.counter, self.device)
+        }
+    }
+}
+
+impl FromStr for HybridTimestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, '-');
+        let physical = parts.next().unwrap_or_default().parse()?;
+        match (parts.next(), parts.next()) {
+            (None, None) => Ok(Self::from_secs(physical)),
+            (Some(counter), Some(device)) => Ok(Self {
+                physical,
+                counter: counter.parse()?,
+                device: u64::from_str_radix(device, 16)?,
+            }),
+            _ => anyhow::bail!("invalid timestamp: {}", s),
+        }
+    }
+}
+
+impl Serialize for HybridTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_legacy() {
+            serializer.serialize_i64(self.physical)
+        } else {
+            serializer.serialize_str(&self.to_string())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HybridTimestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HybridTimestampVisitor;
+
+        impl Visitor<'_> for HybridTimestampVisitor {
+            type Value = HybridTimestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an integer or a hybrid timestamp string")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                Ok(HybridTimestamp::from_secs(v))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                i64::try_from(v)
+                    .map(HybridTimestamp::from_secs)
+                    .map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(HybridTimestampVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_second_edits_are_ordered() {
+        let laptop = 1;
+        let phone = 2;
+        let base = HybridTimestamp::from_secs(Utc::now().timestamp() + 60);
+
+        let on_laptop = HybridTimestamp::tick(Some(base), laptop);
+        let on_phone = HybridTimestamp::tick(Some(base), phone);
+        assert!(on_laptop > base);
+        assert_ne!(on_laptop, on_phone);
+        assert!(on_phone > on_laptop);
+
+        let again = HybridTimestamp::tick(Some(on_phone), laptop);
+        assert!(again > on_phone);
+        assert_eq!(again.physical, base.physical);
+    }
+
+    #[test]
+    fn test_edits_sort_after_received_versions() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join(".footnote")).unwrap();
+        // made on a device whose clock is ten minutes ahead of ours
+        let remote = HybridTimestamp {
+            physical: Utc::now().timestamp() + 600,
+            counter: 0,
+            device: 2,
+        };
+        clock_receive(dir.path(), remote).unwrap();
+        clock_receive(dir.path(), HybridTimestamp::from_secs(1)).unwrap();
+
+        let clock = DeviceClock {
+            device: 1,
+            observed: clock_read(dir.path()).unwrap(),
+        };
+        assert_eq!(clock.observed, Some(remote));
+        assert!(clock.tick(None) > remote);
+
+        let far_ahead = HybridTimestamp::from_secs(Utc::now().timestamp() + 2 * MAX_DRIFT_SECS);
+        clock_receive(dir.path(), far_ahead).unwrap();
+        assert_eq!(clock_read(dir.path()).unwrap(), Some(remote));
+    }
+
+    #[test]
+    fn test_legacy_integer_round_trip() {
+        let legacy: HybridTimestamp = serde_yaml::from_str("1705316400").unwrap();
+        assert_eq!(legacy, HybridTimestamp::from_secs(1705316400));
+        assert_eq!(serde_yaml::to_string(&legacy).unwrap().trim(), "1705316400");
+
+        let hybrid = HybridTimestamp {
+            physical: 1705316400,
+            counter: 3,
+            device: 0xabc,
+        };
+        let yaml = serde_yaml::to_string(&hybrid).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<HybridTimestamp>(&yaml).unwrap(),
+            hybrid
+        );
+        let json = serde_json::to_string(&hybrid).unwrap();
+        assert_eq!(json, r#""1705316400-3-0000000000000abc""#);
+        assert_eq!(
+            serde_json::from_str::<HybridTimestamp>(&json).unwrap(),
+            hybrid
+        );
+    }
+}
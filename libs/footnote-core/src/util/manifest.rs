@@ -1,5 +1,8 @@
+use crate::model::group::{self, ContactGroup};
 use crate::model::note::Note;
-use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::model::settings::VaultSettings;
+use crate::util::hybrid_timestamp::HybridTimestamp;
+use crate::util::version_vector::{SyncVerdict, VersionVector};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -11,7 +14,10 @@ use walkdir::WalkDir;
 pub struct ManifestEntry {
     pub uuid: Uuid,
     pub path: PathBuf,
-    pub modified: LamportTimestamp,
+    pub modified: HybridTimestamp,
+    /// empty for notes from before version vectors
+    #[serde(default)]
+    pub versions: VersionVector,
 }
 
 pub type Manifest = HashMap<Uuid, ManifestEntry>;
@@ -24,16 +30,13 @@ pub fn create_manifest_full(vault_path: &Path) -> Result<Manifest> {
     for entry in WalkDir::new(vault_path)
         .follow_links(false)
         .into_iter()
+        // nothing under .footnote/ is a note, conflict copies stay on the
+        // device that kept them
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_str().unwrap_or("").starts_with('.'))
         .filter_map(|e| e.ok())
     {
         let path = entry.path();
 
-        if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-            if file_name.starts_with('.') {
-                continue;
-            }
-        }
-
         if !path.is_file() || path.extension().and_then(|s| s.to_str()) != Some("md") {
             continue;
         }
@@ -56,6 +59,7 @@ pub fn create_manifest_full(vault_path: &Path) -> Result<Manifest> {
             uuid: note.frontmatter.uuid,
             path: relative_path,
             modified: note.frontmatter.modified,
+            versions: note.frontmatter.versions,
         };
         manifest.insert(note.frontmatter.uuid, entry);
     }
@@ -63,17 +67,99 @@ pub fn create_manifest_full(vault_path: &Path) -> Result<Manifest> {
     Ok(manifest)
 }
 
-/// walk our own notes (not notes replicated to us) and add them to view for the
-/// shared_with user
-pub fn create_manifest_for_share(vault_path: &Path, shared_with: &str) -> Result<Manifest> {
-    let mut manifest = Manifest::new();
+/// Why a note is in a contact's share view.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ShareReason {
+    /// the contact's nickname is in the note's share_with
+    Direct,
+    /// a group the contact is in is in the note's share_with
+    Group(String),
+    /// a share rule covers the note's path. name is the nickname or group
+    /// listed in the rule.
+    Rule { pattern: String, name: String },
+    /// the note is in a shared folder the contact is a member of
+    Folder(String),
+}
+
+impl std::fmt::Display for ShareReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShareReason::Direct => write!(f, "share_with"),
+            ShareReason::Group(group) => write!(f, "group {}", group),
+            ShareReason::Rule { pattern, name } => write!(f, "rule {} via {}", pattern, name),
+            ShareReason::Folder(path) => write!(f, "shared folder {}", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharePreviewEntry {
+    pub entry: ManifestEntry,
+    pub reasons: Vec<ShareReason>,
+}
+
+/// every reason the note at relative_path with the given share_with is shared
+/// with nickname. share_names is the nickname and the groups it belongs to,
+/// see `group::share_names_for`. empty when the note is not shared.
+pub fn share_reasons(
+    nickname: &str,
+    share_names: &[String],
+    note_share_with: &[String],
+    relative_path: &Path,
+    settings: &VaultSettings,
+) -> Vec<ShareReason> {
+    let mut reasons = Vec::new();
+    for name in note_share_with.iter().filter(|n| share_names.contains(n)) {
+        let reason = if name == nickname {
+            ShareReason::Direct
+        } else {
+            ShareReason::Group(name.clone())
+        };
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    }
+    for rule in settings
+        .share_rules
+        .iter()
+        .filter(|r| r.matches(relative_path))
+    {
+        for name in rule.share_with.iter().filter(|n| share_names.contains(n)) {
+            reasons.push(ShareReason::Rule {
+                pattern: rule.pattern.clone(),
+                name: name.clone(),
+            });
+        }
+    }
+    if let Some(folder) = settings
+        .shared_folder_for(relative_path)
+        .filter(|f| f.has_member(share_names))
+    {
+        reasons.push(ShareReason::Folder(folder.path.display().to_string()));
+    }
+    reasons
+}
+
+/// walk our own notes (not notes replicated to us) and return every note
+/// shared with the contact, along with why, sorted by path. this is exactly
+/// the set of notes `create_manifest_for_share` offers the contact. groups
+/// are the contact groups of the vault.
+pub fn share_preview(
+    vault_path: &Path,
+    shared_with: &str,
+    groups: &[ContactGroup],
+) -> Result<Vec<SharePreviewEntry>> {
+    let mut preview = Vec::new();
+    let settings = VaultSettings::read(vault_path)?;
+    let share_names = group::share_names_for(shared_with, groups);
 
     for entry in WalkDir::new(vault_path)
         .follow_links(false)
         .into_iter()
         .filter_entry(|e| {
+            // the vault itself may live in a hidden directory
             let name = e.file_name().to_str().unwrap_or("");
-            !name.starts_with('.') && name != "footnotes"
+            e.depth() == 0 || (!name.starts_with('.') && name != "footnotes")
         })
         .filter_map(|e| e.ok())
         .filter(|e| {
@@ -84,29 +170,50 @@ pub fn create_manifest_for_share(vault_path: &Path, shared_with: &str) -> Result
         let Ok(note) = Note::from_path(entry.path(), false) else {
             continue;
         };
-        if !note
-            .frontmatter
-            .share_with
-            .contains(&shared_with.to_string())
-        {
-            continue;
-        }
 
         let relative_path = path
             .strip_prefix(vault_path)
             .context("Failed to get relative path")?
             .to_path_buf();
 
-        let entry = ManifestEntry {
-            uuid: note.frontmatter.uuid,
-            path: relative_path,
-            modified: note.frontmatter.modified,
-        };
+        let reasons = share_reasons(
+            shared_with,
+            &share_names,
+            &note.frontmatter.share_with,
+            &relative_path,
+            &settings,
+        );
+        if reasons.is_empty() {
+            continue;
+        }
 
-        manifest.insert(note.frontmatter.uuid, entry);
+        preview.push(SharePreviewEntry {
+            entry: ManifestEntry {
+                uuid: note.frontmatter.uuid,
+                path: relative_path,
+                modified: note.frontmatter.modified,
+                versions: note.frontmatter.versions,
+            },
+            reasons,
+        });
     }
 
-    Ok(manifest)
+    preview.sort_by(|a, b| a.entry.path.cmp(&b.entry.path));
+    Ok(preview)
+}
+
+/// the view of our notes for the shared_with user. a note is in the view if
+/// the user, or a group the user is in, is in the note's share_with or a share
+/// rule in the vault settings that covers the note's path.
+pub fn create_manifest_for_share(
+    vault_path: &Path,
+    shared_with: &str,
+    groups: &[ContactGroup],
+) -> Result<Manifest> {
+    Ok(share_preview(vault_path, shared_with, groups)?
+        .into_iter()
+        .map(|p| (p.entry.uuid, p.entry))
+        .collect())
 }
 
 /// manifest of all files i might read and write to
@@ -145,6 +252,7 @@ pub fn create_manifest_local(vault_path: &Path) -> Result<Manifest> {
             uuid: note.frontmatter.uuid,
             path: relative_path,
             modified: note.frontmatter.modified,
+            versions: note.frontmatter.versions,
         };
 
         manifest.insert(note.frontmatter.uuid, entry);
@@ -188,6 +296,7 @@ pub fn find_responses(vault_path: &Path, target_uuid: Uuid) -> Result<Vec<Manife
                 uuid: note.frontmatter.uuid,
                 path: relative_path,
                 modified: note.frontmatter.modified,
+                versions: note.frontmatter.versions,
             });
         }
     }
@@ -195,22 +304,35 @@ pub fn find_responses(vault_path: &Path, target_uuid: Uuid) -> Result<Vec<Manife
     Ok(responses)
 }
 
-pub fn diff_manifests(local: &Manifest, remote: &Manifest) -> Vec<ManifestEntry> {
+/// how the remote version of a note relates to ours
+pub fn sync_verdict(local: &ManifestEntry, remote: &ManifestEntry) -> SyncVerdict {
+    SyncVerdict::between(
+        local.modified,
+        &local.versions,
+        remote.modified,
+        &remote.versions,
+    )
+}
+
+/// The remote notes to fetch, with how each relates to ours. A concurrent
+/// version is fetched whichever timestamp is newer, to be merged with ours or
+/// kept as a copy by the side it loses on.
+pub fn diff_manifests(local: &Manifest, remote: &Manifest) -> Vec<(ManifestEntry, SyncVerdict)> {
     let mut files_to_sync = Vec::new();
 
     for (uuid, remote_entry) in remote {
-        match local.get(uuid) {
-            None => {
-                files_to_sync.push(remote_entry.clone());
-            }
-            Some(local_entry) => {
-                if remote_entry.modified > local_entry.modified {
-                    files_to_sync.push(remote_entry.clone());
-                }
-            }
+        let verdict = match local.get(uuid) {
+            None => SyncVerdict::FastForward,
+            Some(local_entry) => sync_verdict(local_entry, remote_entry),
+        };
+        if verdict != SyncVerdict::UpToDate {
+            files_to_sync.push((remote_entry.clone(), verdict));
         }
     }
 
+    // in a stable order, so a sync cut short always leaves the same files
+    // behind
+    files_to_sync.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
     files_to_sync
 }
 
@@ -229,13 +351,15 @@ mod tests {
             ManifestEntry {
                 uuid,
                 path: PathBuf::from("note.md"),
-                modified: LamportTimestamp::new(None),
+                modified: HybridTimestamp::tick(None, 1),
+                versions: VersionVector::default(),
             },
         );
 
         let diff = diff_manifests(&local, &remote);
         assert_eq!(diff.len(), 1);
-        assert_eq!(diff[0].uuid, uuid);
+        assert_eq!(diff[0].0.uuid, uuid);
+        assert_eq!(diff[0].1, SyncVerdict::FastForward);
     }
 
     #[test]
@@ -244,14 +368,15 @@ mod tests {
         let mut remote = Manifest::new();
 
         let uuid = Uuid::new_v4();
-        let base_timestamp = LamportTimestamp::new(None);
+        let base_timestamp = HybridTimestamp::tick(None, 1);
 
         local.insert(
             uuid,
             ManifestEntry {
                 path: PathBuf::from("note.md"),
                 uuid,
-                modified: base_timestamp.clone(),
+                modified: base_timestamp,
+                versions: VersionVector::default(),
             },
         );
 
@@ -260,12 +385,54 @@ mod tests {
             ManifestEntry {
                 path: PathBuf::from("note.md"),
                 uuid,
-                modified: LamportTimestamp::new(Some(base_timestamp)),
+                modified: HybridTimestamp::tick(Some(base_timestamp), 1),
+                versions: VersionVector::default(),
             },
         );
 
+        // without vectors, the remote edit may not have seen ours
         let diff = diff_manifests(&local, &remote);
         assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].1, SyncVerdict::Concurrent);
+    }
+
+    #[test]
+    fn test_diff_manifests_version_vectors() {
+        let uuid = Uuid::new_v4();
+        let entry = |modified: HybridTimestamp, versions: &VersionVector| {
+            let mut manifest = Manifest::new();
+            manifest.insert(
+                uuid,
+                ManifestEntry {
+                    uuid,
+                    path: PathBuf::from("note.md"),
+                    modified,
+                    versions: versions.clone(),
+                },
+            );
+            manifest
+        };
+        let (laptop, phone) = (1, 2);
+        let mut base = VersionVector::default();
+        base.increment(laptop);
+        let base_time = HybridTimestamp::tick(None, laptop);
+
+        // edited on the phone after it had the laptop's version
+        let mut after = base.clone();
+        after.increment(phone);
+        let after_time = HybridTimestamp::tick(Some(base_time), phone);
+        let diff = diff_manifests(&entry(base_time, &base), &entry(after_time, &after));
+        assert_eq!(diff[0].1, SyncVerdict::FastForward);
+        assert!(diff_manifests(&entry(after_time, &after), &entry(base_time, &base)).is_empty());
+
+        // edited on the laptop again without seeing the phone's edit
+        let mut other = base.clone();
+        other.increment(laptop);
+        let other_time = HybridTimestamp::tick(Some(after_time), laptop);
+        let diff = diff_manifests(&entry(after_time, &after), &entry(other_time, &other));
+        assert_eq!(diff[0].1, SyncVerdict::Concurrent);
+        let diff = diff_manifests(&entry(other_time, &other), &entry(after_time, &after));
+        assert_eq!(diff[0].1, SyncVerdict::Concurrent);
     }
 
     #[test]
@@ -274,8 +441,8 @@ mod tests {
         let mut remote = Manifest::new();
 
         let uuid = Uuid::new_v4();
-        let old_time = LamportTimestamp(1000);
-        let new_time = LamportTimestamp(2000);
+        let old_time = HybridTimestamp::from_secs(1000);
+        let new_time = HybridTimestamp::from_secs(2000);
 
         local.insert(
             uuid,
@@ -283,6 +450,7 @@ mod tests {
                 uuid,
                 path: PathBuf::from("note.md"),
                 modified: new_time,
+                versions: VersionVector::default(),
             },
         );
 
@@ -292,6 +460,7 @@ mod tests {
                 path: PathBuf::from("note.md"),
                 uuid,
                 modified: old_time,
+                versions: VersionVector::default(),
             },
         );
 
@@ -305,14 +474,15 @@ mod tests {
         let mut remote = Manifest::new();
 
         let uuid = Uuid::new_v4();
-        let time = LamportTimestamp(1500);
+        let time = HybridTimestamp::from_secs(1500);
 
         local.insert(
             uuid,
             ManifestEntry {
                 path: PathBuf::from("note.md"),
                 uuid,
-                modified: time.clone(),
+                modified: time,
+                versions: VersionVector::default(),
             },
         );
 
@@ -322,12 +492,47 @@ mod tests {
                 path: PathBuf::from("note.md"),
                 uuid,
                 modified: time,
+                versions: VersionVector::default(),
             },
         );
 
         let diff = diff_manifests(&local, &remote);
         assert_eq!(diff.len(), 0);
     }
+
+    #[test]
+    fn test_share_reasons() {
+        use crate::model::settings::ShareRule;
+
+        let settings = VaultSettings {
+            share_rules: vec![ShareRule::new("recipes", vec!["family".to_string()]).unwrap()],
+            ..Default::default()
+        };
+        let share_names = vec!["mom".to_string(), "family".to_string()];
+        let share_with = vec!["mom".to_string(), "family".to_string(), "bob".to_string()];
+
+        let reasons = share_reasons(
+            "mom",
+            &share_names,
+            &share_with,
+            Path::new("recipes/soup.md"),
+            &settings,
+        );
+        assert_eq!(
+            reasons,
+            vec![
+                ShareReason::Direct,
+                ShareReason::Group("family".to_string()),
+                ShareReason::Rule {
+                    pattern: "recipes".to_string(),
+                    name: "family".to_string()
+                },
+            ]
+        );
+
+        let reasons = share_reasons("mom", &share_names, &[], Path::new("todo.md"), &settings);
+        assert!(reasons.is_empty());
+    }
 }
 
 pub fn create_manifest_for_contact(contact_path: &Path) -> Result<Manifest> {
@@ -362,6 +567,7 @@ pub fn create_manifest_for_contact(contact_path: &Path) -> Result<Manifest> {
                 uuid: note.frontmatter.uuid,
                 path: relative_path,
                 modified: note.frontmatter.modified,
+                versions: note.frontmatter.versions,
             },
         );
     }
@@ -1,9 +1,23 @@
+pub mod bundle;
+pub mod change_signal;
+pub mod collab;
+pub mod conflict;
 pub mod crypto;
+pub mod delivery_ledger;
+pub mod endpoint;
 pub mod filesystem;
+pub mod hybrid_timestamp;
 pub mod lamport_timestamp;
 pub mod manifest;
 pub mod network;
+pub mod peer_address;
+pub mod presence;
+pub mod quota;
+pub mod sync_lock;
 pub mod sync_status_record;
 pub mod tombstone;
 pub mod transfer;
+pub mod transport;
 pub mod tree_node;
+pub mod vault_lock;
+pub mod version_vector;
@@ -1,57 +1,93 @@
-use anyhow::Result;
-use iroh::endpoint::{RecvStream, SendStream};
-use n0_error::StdResultExt;
-
-pub async fn send_u32(stream: &mut SendStream, value: u32) -> Result<()> {
-    SendStream::write_all(stream, &value.to_be_bytes())
-        .await
-        .anyerr()?;
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::service::APP_VERSION;
+
+pub async fn send_u32(stream: &mut (impl AsyncWrite + Unpin), value: u32) -> Result<()> {
+    stream.write_all(&value.to_be_bytes()).await?;
     Ok(())
 }
 
-pub async fn receive_u32(stream: &mut RecvStream) -> Result<u32> {
+pub async fn receive_u32(stream: &mut (impl AsyncRead + Unpin)) -> Result<u32> {
     let mut buf = [0u8; 4];
-    RecvStream::read_exact(stream, &mut buf).await.anyerr()?;
+    stream.read_exact(&mut buf).await?;
     Ok(u32::from_be_bytes(buf))
 }
 
-pub async fn send_u64(stream: &mut SendStream, value: u64) -> Result<()> {
-    SendStream::write_all(stream, &value.to_be_bytes())
-        .await
-        .anyerr()?;
+pub async fn send_u64(stream: &mut (impl AsyncWrite + Unpin), value: u64) -> Result<()> {
+    stream.write_all(&value.to_be_bytes()).await?;
     Ok(())
 }
 
-pub async fn receive_u64(stream: &mut RecvStream) -> Result<u64> {
+pub async fn receive_u64(stream: &mut (impl AsyncRead + Unpin)) -> Result<u64> {
     let mut buf = [0u8; 8];
-    RecvStream::read_exact(stream, &mut buf).await.anyerr()?;
+    stream.read_exact(&mut buf).await?;
     Ok(u64::from_be_bytes(buf))
 }
 
-pub async fn send_bytes(stream: &mut SendStream, data: &[u8]) -> Result<()> {
+pub async fn send_bytes(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> Result<()> {
     let len = data.len() as u32;
     send_u32(stream, len).await?;
-    SendStream::write_all(stream, data).await.anyerr()?;
+    stream.write_all(data).await?;
     Ok(())
 }
 
-pub async fn receive_bytes(stream: &mut RecvStream) -> Result<Vec<u8>> {
+pub async fn receive_bytes(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
     let len = receive_u32(stream).await? as usize;
     let mut buf = vec![0u8; len];
-    RecvStream::read_exact(stream, &mut buf).await.anyerr()?;
+    stream.read_exact(&mut buf).await?;
     Ok(buf)
 }
 
-pub async fn send_file_request(stream: &mut SendStream, uuid: &uuid::Uuid) -> Result<()> {
-    SendStream::write_all(stream, uuid.as_bytes())
-        .await
-        .anyerr()?;
+pub async fn send_app_version(stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    send_bytes(stream, APP_VERSION.as_bytes()).await
+}
+
+pub async fn receive_app_version(stream: &mut (impl AsyncRead + Unpin)) -> Result<String> {
+    let bytes = receive_bytes(stream).await?;
+    String::from_utf8(bytes).context("peer sent an invalid app version")
+}
+
+/// collaborative documents by note: a count, then each note's uuid and its
+/// document
+pub async fn send_docs(
+    stream: &mut (impl AsyncWrite + Unpin),
+    docs: &[(uuid::Uuid, Vec<u8>)],
+) -> Result<()> {
+    send_u32(stream, docs.len() as u32).await?;
+    for (uuid, doc) in docs {
+        stream.write_all(uuid.as_bytes()).await?;
+        send_bytes(stream, doc).await?;
+    }
+    Ok(())
+}
+
+pub async fn receive_docs(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Vec<(uuid::Uuid, Vec<u8>)>> {
+    let count = receive_u32(stream).await?;
+    let mut docs = Vec::new();
+    for _ in 0..count {
+        let mut buf = [0u8; 16];
+        stream.read_exact(&mut buf).await?;
+        docs.push((uuid::Uuid::from_bytes(buf), receive_bytes(stream).await?));
+    }
+    Ok(docs)
+}
+
+pub async fn send_file_request(
+    stream: &mut (impl AsyncWrite + Unpin),
+    uuid: &uuid::Uuid,
+) -> Result<()> {
+    stream.write_all(uuid.as_bytes()).await?;
     Ok(())
 }
 
-pub async fn receive_file_request(stream: &mut RecvStream) -> Result<Option<uuid::Uuid>> {
+pub async fn receive_file_request(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<uuid::Uuid>> {
     let mut buf = [0u8; 16];
-    RecvStream::read_exact(stream, &mut buf).await.anyerr()?;
+    stream.read_exact(&mut buf).await?;
 
     // Check for EOF signal (all zeros)
     if buf == [0u8; 16] {
@@ -61,23 +97,47 @@ pub async fn receive_file_request(stream: &mut RecvStream) -> Result<Option<uuid
     Ok(Some(uuid::Uuid::from_bytes(buf)))
 }
 
-pub async fn send_file_contents(stream: &mut SendStream, contents: &[u8]) -> Result<()> {
+pub async fn send_file_contents(
+    stream: &mut (impl AsyncWrite + Unpin),
+    contents: &[u8],
+) -> Result<()> {
     send_u64(stream, contents.len() as u64).await?;
-    SendStream::write_all(stream, contents).await.anyerr()?;
+    stream.write_all(contents).await?;
     Ok(())
 }
 
-pub async fn receive_file_contents(stream: &mut RecvStream) -> Result<Vec<u8>> {
-    let len = receive_u64(stream).await? as usize;
-    let mut buf = vec![0u8; len];
-    RecvStream::read_exact(stream, &mut buf).await.anyerr()?;
+pub async fn receive_file_contents(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let len = receive_file_len(stream).await?;
+    receive_file_body(stream, len).await
+}
+
+/// receive_file_contents in two steps, so the receiver can check the length
+/// before reading the body
+pub async fn receive_file_len(stream: &mut (impl AsyncRead + Unpin)) -> Result<u64> {
+    receive_u64(stream).await
+}
+
+pub async fn receive_file_body(stream: &mut (impl AsyncRead + Unpin), len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len as usize];
+    stream.read_exact(&mut buf).await?;
     Ok(buf)
 }
 
-pub async fn send_eof(stream: &mut SendStream) -> Result<()> {
+/// read and drop a file body the receiver won't keep, without holding it in
+/// memory
+pub async fn discard_file_body(stream: &mut (impl AsyncRead + Unpin), len: u64) -> Result<()> {
+    let mut buf = [0u8; 64 * 1024];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk = remaining.min(buf.len() as u64) as usize;
+        stream.read_exact(&mut buf[..chunk]).await?;
+        remaining -= chunk as u64;
+    }
+    Ok(())
+}
+
+pub async fn send_eof(stream: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
     let zero_uuid = uuid::Uuid::nil();
-    SendStream::write_all(stream, zero_uuid.as_bytes())
-        .await
-        .anyerr()?;
+    stream.write_all(zero_uuid.as_bytes()).await?;
     Ok(())
 }
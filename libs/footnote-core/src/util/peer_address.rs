@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use iroh::{EndpointAddr, PublicKey, TransportAddr};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::vault_lock::VaultLock;
+
+static PEER_ADDRESS_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+/// how long a learned address is offered to iroh. phones change networks
+/// often, a stale address only costs a failed attempt before discovery.
+pub const PEER_ADDRESS_TTL_SECS: i64 = 24 * 60 * 60;
+
+/// How a device or contact endpoint was last reached, learned from a
+/// successful sync in either direction. Stored in
+/// .footnote/peer_addresses.json by endpoint id, and given to iroh when the
+/// next endpoint is bound so a connect can skip the discovery lookup.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerAddress {
+    #[serde(default)]
+    pub relay_url: Option<String>,
+    #[serde(default)]
+    pub direct_addresses: Vec<SocketAddr>,
+    pub seen_at: LamportTimestamp,
+}
+
+impl PeerAddress {
+    fn from_addr(addr: &EndpointAddr, seen_at: LamportTimestamp) -> Self {
+        Self {
+            relay_url: addr.relay_urls().next().map(|url| url.to_string()),
+            direct_addresses: addr.ip_addrs().copied().collect(),
+            seen_at,
+        }
+    }
+
+    pub fn is_expired(&self, now: LamportTimestamp) -> bool {
+        now.as_i64() - self.seen_at.as_i64() > PEER_ADDRESS_TTL_SECS
+    }
+
+    pub fn to_addr(&self, endpoint_id: PublicKey) -> EndpointAddr {
+        let mut addrs: Vec<TransportAddr> = self
+            .direct_addresses
+            .iter()
+            .copied()
+            .map(TransportAddr::Ip)
+            .collect();
+        if let Some(relay_url) = self.relay_url.as_ref().and_then(|url| url.parse().ok()) {
+            addrs.push(TransportAddr::Relay(relay_url));
+        }
+        EndpointAddr::from_parts(endpoint_id, addrs)
+    }
+}
+
+fn peer_addresses_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("peer_addresses.json")
+}
+
+pub fn peer_addresses_read(vault_path: &Path) -> Result<HashMap<String, PeerAddress>> {
+    let path = peer_addresses_path(vault_path);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read peer addresses: {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse peer addresses")
+}
+
+/// the addresses still worth trying
+pub fn peer_addresses_fresh(vault_path: &Path) -> Result<Vec<EndpointAddr>> {
+    let now = LamportTimestamp::now();
+    Ok(peer_addresses_read(vault_path)?
+        .into_iter()
+        .filter(|(_, address)| !address.is_expired(now))
+        .filter_map(|(endpoint_id, address)| Some(address.to_addr(endpoint_id.parse().ok()?)))
+        .collect())
+}
+
+/// remember how a peer was reached, dropping anything expired
+pub async fn peer_address_record(vault_path: &Path, addr: &EndpointAddr) -> Result<()> {
+    if addr.is_empty() {
+        return Ok(());
+    }
+    let lock = PEER_ADDRESS_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    let now = LamportTimestamp::now();
+    let mut addresses = peer_addresses_read(vault_path)?;
+    addresses.retain(|_, address| !address.is_expired(now));
+    addresses.insert(addr.id.to_string(), PeerAddress::from_addr(addr, now));
+
+    let path = peer_addresses_path(vault_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(&addresses)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_recorded_address_expires() {
+        let dir = TempDir::new().unwrap();
+        let endpoint_id = iroh::SecretKey::generate(&mut rand::rng()).public();
+        let direct: SocketAddr = "192.168.1.20:4433".parse().unwrap();
+        let addr = EndpointAddr::from_parts(endpoint_id, [TransportAddr::Ip(direct)])
+            .with_relay_url("https://relay.example.com".parse().unwrap());
+
+        peer_address_record(dir.path(), &addr).await.unwrap();
+        assert_eq!(peer_addresses_fresh(dir.path()).unwrap(), vec![addr]);
+
+        let address = &peer_addresses_read(dir.path()).unwrap()[&endpoint_id.to_string()];
+        assert_eq!(address.direct_addresses, vec![direct]);
+        let later = LamportTimestamp(address.seen_at.as_i64() + PEER_ADDRESS_TTL_SECS + 1);
+        assert!(address.is_expired(later));
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use iroh::PublicKey;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::vault_lock::VaultLock;
+
+static PRESENCE_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
+
+/// When a device or contact endpoint last completed a sync or ping with this
+/// device, in either direction, and the app version it announced. Stored in
+/// .footnote/presence.json by endpoint id.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DevicePresence {
+    pub last_seen: LamportTimestamp,
+    #[serde(default)]
+    pub app_version: Option<String>,
+}
+
+fn presence_path(vault_path: &Path) -> PathBuf {
+    vault_path.join(".footnote").join("presence.json")
+}
+
+pub fn presence_read(vault_path: &Path) -> Result<HashMap<String, DevicePresence>> {
+    let path = presence_path(vault_path);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let json = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read presence: {}", path.display()))?;
+    serde_json::from_str(&json).context("Failed to parse presence")
+}
+
+/// note that the endpoint was just reached. without a version, the one it
+/// last announced is kept.
+pub async fn presence_record(
+    vault_path: &Path,
+    endpoint_id: &PublicKey,
+    app_version: Option<&str>,
+) -> Result<()> {
+    let lock = PRESENCE_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
+    let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    let mut presence = presence_read(vault_path)?;
+    let known_version = presence
+        .get(&endpoint_id.to_string())
+        .and_then(|p| p.app_version.clone());
+    presence.insert(
+        endpoint_id.to_string(),
+        DevicePresence {
+            last_seen: LamportTimestamp::now(),
+            app_version: app_version.map(str::to_string).or(known_version),
+        },
+    );
+
+    let path = presence_path(vault_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string_pretty(&presence)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_presence_keeps_last_known_version() {
+        let dir = TempDir::new().unwrap();
+        let endpoint_id = iroh::SecretKey::generate(&mut rand::rng()).public();
+
+        presence_record(dir.path(), &endpoint_id, Some("0.4.7"))
+            .await
+            .unwrap();
+        let first = presence_read(dir.path()).unwrap()[&endpoint_id.to_string()].clone();
+        assert_eq!(first.app_version.as_deref(), Some("0.4.7"));
+
+        presence_record(dir.path(), &endpoint_id, None)
+            .await
+            .unwrap();
+        let second = presence_read(dir.path()).unwrap()[&endpoint_id.to_string()].clone();
+        assert_eq!(second.app_version.as_deref(), Some("0.4.7"));
+        assert!(second.last_seen >= first.last_seen);
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use walkdir::WalkDir;
+
+use crate::model::settings::ShareQuota;
+
+/// Space a contact's shared notes take up in footnotes/<nickname>/
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub struct StorageUsage {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum RejectReason {
+    NoteTooLarge { bytes: u64, max_note_bytes: u64 },
+    TotalBytesQuota { max_total_bytes: u64 },
+    FileCountQuota { max_files: usize },
+}
+
+impl RejectReason {
+    /// the note is fine on its own but the contact is out of space
+    pub fn is_quota_hit(&self) -> bool {
+        !matches!(self, RejectReason::NoteTooLarge { .. })
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::NoteTooLarge {
+                bytes,
+                max_note_bytes,
+            } => write!(f, "note is {} bytes, limit is {}", bytes, max_note_bytes),
+            RejectReason::TotalBytesQuota { max_total_bytes } => {
+                write!(f, "storage quota of {} bytes reached", max_total_bytes)
+            }
+            RejectReason::FileCountQuota { max_files } => {
+                write!(f, "file quota of {} notes reached", max_files)
+            }
+        }
+    }
+}
+
+pub fn storage_usage(contact_dir: &Path) -> Result<StorageUsage> {
+    let mut usage = StorageUsage::default();
+    if !contact_dir.exists() {
+        return Ok(usage);
+    }
+    for entry in WalkDir::new(contact_dir)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        usage.files += 1;
+        usage.bytes += entry.metadata()?.len();
+    }
+    Ok(usage)
+}
+
+/// a new file counts against the file quota before it is requested
+pub fn check_new_file(quota: &ShareQuota, usage: &StorageUsage) -> Option<RejectReason> {
+    if usage.files >= quota.max_files {
+        return Some(RejectReason::FileCountQuota {
+            max_files: quota.max_files,
+        });
+    }
+    None
+}
+
+/// incoming_bytes replaces existing_bytes, the size of the file's current
+/// version, or 0 for a new file
+pub fn check_file_size(
+    quota: &ShareQuota,
+    usage: &StorageUsage,
+    existing_bytes: u64,
+    incoming_bytes: u64,
+) -> Option<RejectReason> {
+    if incoming_bytes > quota.max_note_bytes {
+        return Some(RejectReason::NoteTooLarge {
+            bytes: incoming_bytes,
+            max_note_bytes: quota.max_note_bytes,
+        });
+    }
+    let total = usage.bytes.saturating_sub(existing_bytes) + incoming_bytes;
+    if total > quota.max_total_bytes {
+        return Some(RejectReason::TotalBytesQuota {
+            max_total_bytes: quota.max_total_bytes,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quota() -> ShareQuota {
+        ShareQuota {
+            max_note_bytes: 100,
+            max_total_bytes: 1000,
+            max_files: 2,
+        }
+    }
+
+    #[test]
+    fn test_note_too_large() {
+        let usage = StorageUsage::default();
+        assert_eq!(check_file_size(&quota(), &usage, 0, 100), None);
+        assert_eq!(
+            check_file_size(&quota(), &usage, 0, 101),
+            Some(RejectReason::NoteTooLarge {
+                bytes: 101,
+                max_note_bytes: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_total_bytes_counts_replaced_version() {
+        let usage = StorageUsage {
+            files: 1,
+            bytes: 950,
+        };
+        assert!(check_file_size(&quota(), &usage, 0, 60).is_some());
+        // replacing a 50 byte version with 100 bytes fits exactly
+        assert_eq!(check_file_size(&quota(), &usage, 50, 100), None);
+    }
+
+    #[test]
+    fn test_file_count() {
+        let usage = StorageUsage { files: 2, bytes: 0 };
+        assert_eq!(
+            check_new_file(&quota(), &usage),
+            Some(RejectReason::FileCountQuota { max_files: 2 })
+        );
+        assert_eq!(
+            check_new_file(&quota(), &StorageUsage { files: 1, bytes: 0 }),
+            None
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::{Mutex, MutexGuard, OwnedMutexGuard};
+use uuid::Uuid;
+
+use crate::model::note::{Frontmatter, Note};
+use crate::model::vault::Vault;
+use crate::util::conflict;
+use crate::util::hybrid_timestamp::{self, DeviceClock, HybridTimestamp};
+use crate::util::vault_lock::VaultLock;
+use crate::util::version_vector::{SyncVerdict, VersionVector};
+
+/// Inbound syncs run concurrently, these locks keep them from writing over
+/// each other. Notes are written one at a time and only if newer than what is
+/// on disk by then, since another sync may have written a newer version after
+/// this one diffed its manifest.
+static NOTE_WRITE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+/// user record, contacts and groups
+static RECORDS_WRITE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+/// one share per contact at a time, when several of their devices deliver at
+/// once they write to the same folder and count against the same quota
+static SHARE_LOCKS: OnceLock<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>> = OnceLock::new();
+
+pub async fn records_lock() -> MutexGuard<'static, ()> {
+    RECORDS_WRITE_LOCK
+        .get_or_init(|| Mutex::new(()))
+        .lock()
+        .await
+}
+
+/// held while writing a note outside of this module
+pub async fn note_lock() -> MutexGuard<'static, ()> {
+    NOTE_WRITE_LOCK.get_or_init(|| Mutex::new(())).lock().await
+}
+
+pub async fn share_lock(nickname: &str) -> OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = SHARE_LOCKS
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        locks.entry(nickname.to_string()).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+/// the version of the note at path, None if there is no note there
+fn modified_on_disk(path: &Path) -> Option<HybridTimestamp> {
+    if !path.exists() {
+        return None;
+    }
+    Note::from_path(path, false)
+        .ok()
+        .map(|note| note.frontmatter.modified)
+}
+
+/// the frontmatter of the note with this uuid at path, None if there is no
+/// such note there
+fn version_on_disk(path: &Path, uuid: Uuid) -> Option<Frontmatter> {
+    if !path.exists() {
+        return None;
+    }
+    Note::from_path(path, false)
+        .ok()
+        .filter(|note| note.frontmatter.uuid == uuid)
+        .map(|note| note.frontmatter)
+}
+
+/// a log and a version of it from another device edited alongside it, merged
+/// as an edit made here. A collaborative note arrives with the body of its
+/// merged document, which already holds the edits made here. None unless both
+/// are logs or both are collaborative.
+fn merged_note(current: Note, incoming: &[u8], clock: &DeviceClock) -> Option<String> {
+    let mut merged = Note::from_string(std::str::from_utf8(incoming).ok()?, false).ok()?;
+    if current.is_log() && merged.is_log() {
+        merged.merge_log(&current, clock);
+    } else if current.frontmatter.collaborative && merged.frontmatter.collaborative {
+        merged.merge_versions(&current, clock);
+    } else {
+        return None;
+    }
+    merged.to_string().ok()
+}
+
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// write a received note, unless the version on disk is already as new.
+/// false if it was skipped.
+pub async fn note_write_if_newer(
+    vault_path: &Path,
+    path: &Path,
+    contents: &[u8],
+    modified: HybridTimestamp,
+) -> Result<bool> {
+    let _guard = NOTE_WRITE_LOCK.get_or_init(|| Mutex::new(())).lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    hybrid_timestamp::clock_receive(vault_path, modified)?;
+    if modified_on_disk(path).is_some_and(|current| current >= modified) {
+        return Ok(false);
+    }
+    write_atomic(path, contents)?;
+    Ok(true)
+}
+
+/// A note version received from another of our devices.
+pub struct MirroredNote<'a> {
+    pub uuid: Uuid,
+    pub path: &'a Path,
+    /// where the note is in our vault, if it was renamed since
+    pub previous_path: Option<&'a Path>,
+    pub contents: &'a [u8],
+    pub modified: HybridTimestamp,
+    pub versions: &'a VersionVector,
+}
+
+impl MirroredNote<'_> {
+    fn verdict(&self, current: &Frontmatter) -> SyncVerdict {
+        SyncVerdict::between(
+            current.modified,
+            &current.versions,
+            self.modified,
+            self.versions,
+        )
+    }
+}
+
+/// Like `note_write_if_newer`, but the version vectors decide. A version made
+/// from ours is written whatever its timestamp. Of two edits made alongside
+/// each other, logs and collaborative notes are merged, otherwise the newer
+/// one stays and the other is copied to the conflicts: the local version if
+/// it was never mirrored, or the incoming one. A note the other device
+/// renamed is moved. false if it was skipped.
+pub async fn note_mirror_write(
+    vault_path: &Path,
+    note: MirroredNote<'_>,
+    endpoint_id: &str,
+) -> Result<bool> {
+    let _guard = NOTE_WRITE_LOCK.get_or_init(|| Mutex::new(())).lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    hybrid_timestamp::clock_receive(vault_path, note.modified)?;
+    let incoming = Some((note.contents, note.modified));
+
+    if note.path.exists() {
+        match Note::from_path(note.path, false) {
+            Ok(current) if current.frontmatter.uuid == note.uuid => {
+                match note.verdict(&current.frontmatter) {
+                    SyncVerdict::UpToDate => return Ok(false),
+                    // made from ours, whatever its timestamp
+                    SyncVerdict::FastForward => {}
+                    SyncVerdict::Concurrent => {
+                        let modified = current.frontmatter.modified;
+                        let clock = Vault::new(vault_path)?.clock();
+                        if let Some(merged) = merged_note(current, note.contents, &clock) {
+                            write_atomic(note.path, merged.as_bytes())?;
+                            conflict::sync_base_set(vault_path, note.uuid, Some(note.modified))?;
+                            return Ok(true);
+                        }
+                        // the newer edit stays, the other is kept as a copy
+                        if modified >= note.modified {
+                            conflict::preserve_incoming(
+                                vault_path,
+                                note.path,
+                                note.uuid,
+                                modified,
+                                (note.contents, note.modified),
+                                endpoint_id,
+                            )?;
+                            return Ok(false);
+                        }
+                        conflict::preserve_unsynced(
+                            vault_path,
+                            note.path,
+                            note.uuid,
+                            modified,
+                            incoming,
+                            endpoint_id,
+                        )?;
+                    }
+                }
+            }
+            Ok(other) => {
+                // a different note was created at the same path
+                conflict::preserve_unsynced(
+                    vault_path,
+                    note.path,
+                    other.frontmatter.uuid,
+                    other.frontmatter.modified,
+                    None,
+                    endpoint_id,
+                )?;
+            }
+            Err(_) => {}
+        }
+    }
+
+    let previous = note
+        .previous_path
+        .filter(|previous| *previous != note.path)
+        .and_then(|previous| Some((previous, version_on_disk(previous, note.uuid)?)));
+    if let Some((previous, current)) = &previous {
+        match note.verdict(current) {
+            SyncVerdict::UpToDate => return Ok(false),
+            SyncVerdict::Concurrent if current.modified >= note.modified => {
+                conflict::preserve_incoming(
+                    vault_path,
+                    previous,
+                    note.uuid,
+                    current.modified,
+                    (note.contents, note.modified),
+                    endpoint_id,
+                )?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+    }
+
+    write_atomic(note.path, note.contents)?;
+    if let Some((previous, current)) = previous {
+        if note.verdict(&current) == SyncVerdict::Concurrent {
+            conflict::preserve_unsynced(
+                vault_path,
+                previous,
+                note.uuid,
+                current.modified,
+                incoming,
+                endpoint_id,
+            )?;
+        }
+        fs::remove_file(previous)?;
+    }
+    conflict::sync_base_set(vault_path, note.uuid, Some(note.modified))?;
+    Ok(true)
+}
+
+/// Like `note_remove_unless_newer` for a tombstone from another of our
+/// devices, copying a local version that was never mirrored to the conflicts
+/// first. false if it was kept.
+pub async fn note_mirror_remove(
+    vault_path: &Path,
+    path: &Path,
+    uuid: Uuid,
+    deleted_at: HybridTimestamp,
+    endpoint_id: &str,
+) -> Result<bool> {
+    let _guard = NOTE_WRITE_LOCK.get_or_init(|| Mutex::new(())).lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    hybrid_timestamp::clock_receive(vault_path, deleted_at)?;
+    let Some(current) = version_on_disk(path, uuid).map(|f| f.modified) else {
+        // already gone
+        return Ok(true);
+    };
+    if current > deleted_at {
+        return Ok(false);
+    }
+    conflict::preserve_unsynced(vault_path, path, uuid, current, None, endpoint_id)?;
+    fs::remove_file(path)?;
+    conflict::sync_base_set(vault_path, uuid, None)?;
+    Ok(true)
+}
+
+/// remove a note deleted at deleted_at, unless it was edited after that.
+/// false if it was kept.
+pub async fn note_remove_unless_newer(
+    vault_path: &Path,
+    path: &Path,
+    deleted_at: HybridTimestamp,
+) -> Result<bool> {
+    let _guard = NOTE_WRITE_LOCK.get_or_init(|| Mutex::new(())).lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
+    hybrid_timestamp::clock_receive(vault_path, deleted_at)?;
+    if modified_on_disk(path).is_some_and(|current| current > deleted_at) {
+        return Ok(false);
+    }
+    fs::remove_file(path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(modified: i64) -> String {
+        format!(
+            "---\nuuid: 6d1f0f7e-3b0a-4a4c-9a59-0b1a2b3c4d5e\nmodified: {}\nshare_with: []\n---\nbody {}\n",
+            modified, modified
+        )
+    }
+
+    #[tokio::test]
+    async fn test_older_version_never_replaces_newer() {
+        let dir = std::env::temp_dir().join(format!("footnote-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("note.md");
+
+        assert!(note_write_if_newer(
+            &dir,
+            &path,
+            note(10).as_bytes(),
+            HybridTimestamp::from_secs(10)
+        )
+        .await
+        .unwrap());
+        assert!(!note_write_if_newer(
+            &dir,
+            &path,
+            note(5).as_bytes(),
+            HybridTimestamp::from_secs(5)
+        )
+        .await
+        .unwrap());
+        assert_eq!(
+            modified_on_disk(&path),
+            Some(HybridTimestamp::from_secs(10))
+        );
+
+        assert!(
+            !note_remove_unless_newer(&dir, &path, HybridTimestamp::from_secs(9))
+                .await
+                .unwrap()
+        );
+        assert!(
+            note_remove_unless_newer(&dir, &path, HybridTimestamp::from_secs(10))
+                .await
+                .unwrap()
+        );
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mirror_keeps_unsynced_version() {
+        let dir = std::env::temp_dir().join(format!("footnote-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("note.md");
+        let uuid: Uuid = "6d1f0f7e-3b0a-4a4c-9a59-0b1a2b3c4d5e".parse().unwrap();
+
+        // edited here, never mirrored
+        fs::write(&path, note(10)).unwrap();
+        let received = note(20);
+        let mirrored = MirroredNote {
+            uuid,
+            path: &path,
+            previous_path: None,
+            contents: received.as_bytes(),
+            modified: HybridTimestamp::from_secs(20),
+            versions: &VersionVector::default(),
+        };
+        assert!(note_mirror_write(&dir, mirrored, "peer").await.unwrap());
+        let conflicts = conflict::conflicts_read(&dir).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].replaced, HybridTimestamp::from_secs(10));
+        assert_eq!(conflicts[0].kept, Some(HybridTimestamp::from_secs(20)));
+        assert_eq!(
+            fs::read_to_string(dir.join(&conflicts[0].copy)).unwrap(),
+            note(10)
+        );
+
+        // version 20 came from a mirror, replacing it loses nothing
+        let received = note(30);
+        let mirrored = MirroredNote {
+            uuid,
+            path: &path,
+            previous_path: None,
+            contents: received.as_bytes(),
+            modified: HybridTimestamp::from_secs(30),
+            versions: &VersionVector::default(),
+        };
+        assert!(note_mirror_write(&dir, mirrored, "peer").await.unwrap());
+        assert_eq!(conflict::conflicts_read(&dir).unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
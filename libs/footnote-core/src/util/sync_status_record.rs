@@ -1,4 +1,5 @@
 use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::quota::RejectReason;
 use anyhow::Result;
 use iroh::PublicKey;
 use serde::{Deserialize, Serialize};
@@ -44,6 +45,20 @@ pub struct RecentFile {
     pub timestamp: LamportTimestamp,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RejectedFile {
+    pub uuid: Uuid,
+    pub filename: String,
+    pub reason: RejectReason,
+    pub rejected_at: LamportTimestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaHit {
+    pub at: LamportTimestamp,
+    pub reason: RejectReason,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncStatusRecord {
     pub endpoint_id: String,
@@ -51,6 +66,11 @@ pub struct SyncStatusRecord {
     pub direction: SyncDirection,
     #[serde(default)]
     pub recent_files: Vec<RecentFile>,
+    /// files a share peer sent that were over a size limit or quota
+    #[serde(default)]
+    pub rejected_files: Vec<RejectedFile>,
+    #[serde(default)]
+    pub last_quota_hit: Option<QuotaHit>,
 
     pub current: Option<InProgressSync>,
     pub last_success: Option<SuccessfulSync>,
@@ -88,6 +108,11 @@ impl SyncStatusRecord {
                 .as_ref()
                 .map(|e| e.recent_files.clone())
                 .unwrap_or_default(),
+            rejected_files: existing
+                .as_ref()
+                .map(|e| e.rejected_files.clone())
+                .unwrap_or_default(),
+            last_quota_hit: existing.as_ref().and_then(|e| e.last_quota_hit.clone()),
             last_success: existing.as_ref().and_then(|e| e.last_success.clone()),
             last_failure: existing.as_ref().and_then(|e| e.last_failure.clone()),
         };
@@ -135,6 +160,20 @@ impl SyncStatusRecord {
         Ok(())
     }
 
+    pub fn record_file_rejected(&mut self, rejected_file: RejectedFile) -> Result<()> {
+        if rejected_file.reason.is_quota_hit() {
+            self.last_quota_hit = Some(QuotaHit {
+                at: rejected_file.rejected_at,
+                reason: rejected_file.reason.clone(),
+            });
+        }
+        self.rejected_files.push(rejected_file);
+        self.rejected_files
+            .sort_by_key(|f| std::cmp::Reverse(f.rejected_at));
+        self.rejected_files.truncate(10);
+        Ok(())
+    }
+
     pub fn record_success(mut self) -> Result<()> {
         if let Some(current) = self.current.take() {
             if current.files_transferred > 0 {
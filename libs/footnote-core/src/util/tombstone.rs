@@ -5,7 +5,8 @@ use std::path::Path;
 use std::sync::OnceLock;
 use uuid::Uuid;
 
-use crate::util::lamport_timestamp::LamportTimestamp;
+use crate::util::hybrid_timestamp::HybridTimestamp;
+use crate::util::vault_lock::VaultLock;
 
 // todo: architect tombstones in a bit cleaner
 static TOMBSTONE_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
@@ -13,7 +14,7 @@ static TOMBSTONE_WRITE_LOCK: OnceLock<tokio::sync::Mutex<()>> = OnceLock::new();
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tombstone {
     pub uuid: Uuid,
-    pub deleted_at: LamportTimestamp,
+    pub deleted_at: HybridTimestamp,
 }
 
 pub fn tombstones_read(vault_path: &Path) -> Result<Vec<Tombstone>> {
@@ -28,10 +29,11 @@ pub fn tombstones_read(vault_path: &Path) -> Result<Vec<Tombstone>> {
 pub async fn tombstone_create(
     vault_path: &Path,
     uuid: Uuid,
-    deleted_at: LamportTimestamp,
+    deleted_at: HybridTimestamp,
 ) -> Result<()> {
     let lock = TOMBSTONE_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
     let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
     let mut entries = tombstones_read(vault_path)?;
     entries.retain(|t| t.uuid != uuid);
     entries.push(Tombstone { uuid, deleted_at });
@@ -41,6 +43,7 @@ pub async fn tombstone_create(
 pub async fn tombstone_delete(vault_path: &Path, uuid: &Uuid) -> Result<()> {
     let lock = TOMBSTONE_WRITE_LOCK.get_or_init(|| tokio::sync::Mutex::new(()));
     let _guard = lock.lock().await;
+    let _vault_lock = VaultLock::acquire_async(vault_path).await?;
     let mut entries = tombstones_read(vault_path)?;
     entries.retain(|t| &t.uuid != uuid);
     save(vault_path, &entries)
@@ -1,21 +1,47 @@
 use anyhow::{Context, Result};
-use iroh::endpoint::Connection;
-use iroh::Endpoint;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Component;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncWrite;
 
 use crate::model::contact::Contact;
+use crate::model::group::{self, ContactGroup};
+use crate::model::note::Note;
+use crate::model::settings::SharedFolder;
 use crate::model::vault::Vault;
+use crate::service::ALPN_SYNC;
 
+use crate::util::bundle::{Bundle, BundleHeader, BundleScope};
+use crate::util::collab;
+use crate::util::delivery_ledger::{
+    delivery_ledgers_read, delivery_merge, delivery_record, DeliveryLedger, SharedNotes,
+};
 use crate::util::lamport_timestamp::LamportTimestamp;
 use crate::util::manifest::{
-    create_manifest_for_contact, create_manifest_full, diff_manifests, Manifest,
+    create_manifest_for_contact, create_manifest_for_share, create_manifest_full, diff_manifests,
+    Manifest, ManifestEntry,
 };
 use crate::util::network;
-use crate::util::sync_status_record::{RecentFile, SyncDirection, SyncStatusRecord, SyncType};
+use crate::util::peer_address;
+use crate::util::presence;
+use crate::util::quota;
+use crate::util::sync_lock::{self, MirroredNote};
+use crate::util::sync_status_record::{
+    RecentFile, RejectedFile, SyncDirection, SyncStatusRecord, SyncType,
+};
 use crate::util::tombstone::{tombstone_create, tombstone_delete, tombstones_read, Tombstone};
+use crate::util::transport::{SyncConnection, Transport};
+use crate::util::vault_lock::VaultLock;
+use crate::util::version_vector::SyncVerdict;
 
-pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection) -> Result<()> {
+/// Receive a share from a contact's device. Returns the app version the
+/// sender announced.
+pub async fn receive_share(
+    vault: &Vault,
+    nickname: &str,
+    connection: impl SyncConnection,
+) -> Result<String> {
+    let _share_guard = sync_lock::share_lock(nickname).await;
     let Ok(mut transfer_record) = SyncStatusRecord::start(
         vault.base_path(),
         connection.remote_id(),
@@ -29,6 +55,8 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
     // accept_bi(). Calling open_bi then waiting on the RecvStream without
     // writing anything to the connected SendStream will never succeed.
     let (mut send, mut recv) = connection.accept_bi().await?;
+    let remote_version = network::receive_app_version(&mut recv).await?;
+    network::send_app_version(&mut send).await?;
 
     let contact_record_bytes = network::receive_bytes(&mut recv).await?;
     if contact_record_bytes.is_empty() {
@@ -38,7 +66,11 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
 
     let mut incoming_contact: Contact = serde_json::from_slice(&contact_record_bytes)?;
     incoming_contact.verify()?;
-    vault.contact_update(nickname, &mut incoming_contact)?;
+    {
+        let _records_guard = sync_lock::records_lock().await;
+        let _vault_lock = VaultLock::acquire_async(&vault.path).await?;
+        vault.contact_update(nickname, &mut incoming_contact)?;
+    }
 
     let contacts_bytes = network::receive_bytes(&mut recv).await?;
     let incoming_contacts: Vec<Contact> =
@@ -48,21 +80,108 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
         tracing::error!("received non empty contact list from share peer");
     }
 
+    let groups_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_groups: Vec<ContactGroup> =
+        serde_json::from_slice(&groups_bytes).context("Failed to deserialize groups")?;
+
+    if !incoming_groups.is_empty() {
+        tracing::error!("received non empty group list from share peer");
+    }
+
+    let ledgers_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_ledgers: HashMap<String, DeliveryLedger> =
+        serde_json::from_slice(&ledgers_bytes).context("Failed to deserialize delivery ledgers")?;
+
+    if !incoming_ledgers.is_empty() {
+        tracing::error!("received non empty delivery ledgers from share peer");
+    }
+
     let manifest_bytes = network::receive_bytes(&mut recv).await?;
     let remote_manifest: Manifest =
         serde_json::from_slice(&manifest_bytes).context("Failed to deserialize manifest")?;
 
     let tombstone_bytes = network::receive_bytes(&mut recv).await?;
-    let _remote_tombstones: Vec<Tombstone> =
+    let remote_tombstones: Vec<Tombstone> =
         serde_json::from_slice(&tombstone_bytes).context("Failed to deserialize tombstone")?;
 
+    let remote_docs = network::receive_docs(&mut recv).await?;
+
+    let folders_bytes = network::receive_bytes(&mut recv).await?;
+    let offered_folders: Vec<(uuid::Uuid, PathBuf)> =
+        serde_json::from_slice(&folders_bytes).context("Failed to deserialize shared folders")?;
+
     let local_manifest =
         create_manifest_full(&vault.path).context("Failed to create local manifest")?;
-    let files_to_sync = diff_manifests(&local_manifest, &remote_manifest);
+    // a share only writes notes newer than ours, there is no copy to keep
+    // of an older one edited alongside
+    let files_to_sync: Vec<_> = diff_manifests(&local_manifest, &remote_manifest)
+        .into_iter()
+        .filter(|(remote, verdict)| {
+            *verdict != SyncVerdict::Concurrent
+                || local_manifest
+                    .get(&remote.uuid)
+                    .is_none_or(|local| remote.modified > local.modified)
+        })
+        .collect();
+
+    // the shared folders we write to with the contact, by where each is in
+    // their vault
+    let settings = vault.settings_read()?;
+    let share_names = group::share_names_for(nickname, &vault.group_read()?);
+    let folders: Vec<(PathBuf, SharedFolder)> = offered_folders
+        .into_iter()
+        .filter_map(|(id, theirs)| {
+            if !is_folder_path(&theirs) {
+                tracing::warn!("{} offered a folder at {}", nickname, theirs.display());
+                return None;
+            }
+            let ours = settings
+                .shared_folders_with(&share_names)
+                .into_iter()
+                .find(|f| f.id == id)?;
+            Some((theirs, ours.clone()))
+        })
+        .collect();
+    let member = incoming_contact.id_public_key.clone();
+    // who may claim to have written a note in a shared folder: us, and the
+    // contacts who are members of it
+    let our_id_key = vault.user_read()?.map(|u| u.id_public_key);
+    let our_devices = vault.device_read()?;
+    let contacts = vault.contact_read()?;
+    let groups = vault.group_read()?;
+
+    // the contact may edit our collaborative notes shared with them and their
+    // own, and start documents for notes new to us
+    let mut exchanged = collab::exchanged_notes(
+        vault,
+        &create_manifest_for_share(&vault.path, nickname, &vault.group_read()?)?,
+        Some(nickname),
+    )?;
+    let accepted: HashSet<_> = remote_manifest
+        .keys()
+        .filter(|uuid| !local_manifest.contains_key(uuid))
+        .chain(exchanged.iter())
+        .copied()
+        .collect();
+    collab::absorb(vault).await?;
+    let merged = collab::merge(vault, remote_docs, &accepted).await?;
+
+    // a contact can only fill footnotes/<nickname>/, and the shared folders
+    // with the notes they wrote, up to its quota
+    let quota = settings.quota_for(nickname);
+    let contact_dir = vault.path.join("footnotes").join(nickname);
+    let mut usage = quota::storage_usage(&contact_dir)?;
+    let written = folder_usage(&vault.path, &folders, &member)?;
+    usage.files += written.files;
+    usage.bytes += written.bytes;
+    // sent back so the sender doesn't count them as delivered
+    let mut rejected = Vec::new();
+    // where their notes are now, to notice the ones they renamed
+    let received_before = create_manifest_for_contact(&contact_dir)?;
     if let Err(e) = transfer_record.update(0, Some(files_to_sync.len())) {
         tracing::warn!("could not update transfer record: {}", e);
     }
-    for file_to_sync in &files_to_sync {
+    for (file_to_sync, _) in &files_to_sync {
         let path_components: Vec<_> = file_to_sync.path.components().collect();
         for component in &path_components {
             match component {
@@ -78,12 +197,37 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
             }
         }
 
-        let full_path = vault
-            .path
-            .join("footnotes")
-            .join(nickname)
-            .join(&file_to_sync.path);
-        let contact_base = vault.path.join("footnotes").join(nickname);
+        // a note in a shared folder goes in ours, anything else in theirs
+        let folder = folders
+            .iter()
+            .find_map(|(theirs, ours)| Some((ours, file_to_sync.path.strip_prefix(theirs).ok()?)));
+        let (contact_base, full_path) = match folder {
+            Some((ours, within)) => {
+                let base = vault.path.join(&ours.path);
+                let full_path = base.join(within);
+                (base, full_path)
+            }
+            None => (contact_dir.clone(), contact_dir.join(&file_to_sync.path)),
+        };
+        // in a shared folder the note may have been ours, or one they shared
+        // with us before, but a member can't take over any other note
+        let previous = local_manifest
+            .get(&file_to_sync.uuid)
+            .filter(|_| folder.is_some())
+            .map(|local| vault.path.join(&local.path))
+            .filter(|previous| *previous != full_path);
+        if previous
+            .as_ref()
+            .is_some_and(|p| !p.starts_with(&contact_base) && !p.starts_with(&contact_dir))
+        {
+            tracing::warn!(
+                "{} from {} is a note outside the shared folder",
+                file_to_sync.path.display(),
+                nickname
+            );
+            continue;
+        }
+
         let canonical_base = contact_base
             .canonicalize()
             .or_else(|_| {
@@ -115,21 +259,185 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
             );
         }
 
+        let existing_bytes = fs::metadata(&canonical_full).map(|m| m.len()).ok();
+        if existing_bytes.is_none() {
+            if let Some(reason) = quota::check_new_file(&quota, &usage) {
+                reject_file(&mut transfer_record, file_to_sync, reason);
+                rejected.push(file_to_sync.uuid);
+                continue;
+            }
+        }
+
         network::send_file_request(&mut send, &file_to_sync.uuid).await?;
-        let file_contents = network::receive_file_contents(&mut recv).await?;
-        let temp_path = canonical_full.with_extension("tmp");
-        fs::write(&temp_path, file_contents)?;
-        fs::rename(&temp_path, &canonical_full)?;
+        let file_len = network::receive_file_len(&mut recv).await?;
+        if let Some(reason) =
+            quota::check_file_size(&quota, &usage, existing_bytes.unwrap_or(0), file_len)
+        {
+            network::discard_file_body(&mut recv, file_len).await?;
+            reject_file(&mut transfer_record, file_to_sync, reason);
+            rejected.push(file_to_sync.uuid);
+            continue;
+        }
+        let file_contents = network::receive_file_body(&mut recv, file_len).await?;
+        let file_contents = collab::with_doc_content(vault, file_contents)?;
+        let file_contents = match folder {
+            // a note we have keeps the author it has here, one new to us the
+            // author it names, if one of their devices signed the claim
+            Some((ours, _)) => match local_manifest
+                .get(&file_to_sync.uuid)
+                .map(|local| vault.path.join(&local.path))
+                .filter(|local| !local.starts_with(&contact_dir))
+                .and_then(|local| Note::from_path(&local, false).ok())
+            {
+                Some(local) => with_author(
+                    file_contents,
+                    local.frontmatter.author,
+                    local.frontmatter.author_signature,
+                ),
+                None => {
+                    let Ok(note) =
+                        Note::from_string(&String::from_utf8_lossy(&file_contents), false)
+                    else {
+                        continue;
+                    };
+                    let Some(author) = note.frontmatter.author.as_deref() else {
+                        tracing::warn!(
+                            "{} from {} names no author",
+                            file_to_sync.path.display(),
+                            nickname
+                        );
+                        continue;
+                    };
+                    if our_id_key.as_deref() == Some(author) && note.author_signed_by(&our_devices)
+                    {
+                        with_author(file_contents, None, None)
+                    } else if contacts.iter().any(|c| {
+                        c.id_public_key == author
+                            && ours.has_member(&group::share_names_for(&c.nickname, &groups))
+                            && note.author_signed_by(&c.devices)
+                    }) {
+                        file_contents
+                    } else {
+                        tracing::warn!(
+                            "{} from {} names an author who didn't sign it",
+                            file_to_sync.path.display(),
+                            nickname
+                        );
+                        continue;
+                    }
+                }
+            },
+            None => file_contents,
+        };
+        if !sync_lock::note_write_if_newer(
+            &vault.path,
+            &canonical_full,
+            &file_contents,
+            file_to_sync.modified,
+        )
+        .await?
+        {
+            tracing::debug!("{} is already up to date", file_to_sync.path.display());
+            continue;
+        }
+        usage.bytes = usage.bytes.saturating_sub(existing_bytes.unwrap_or(0)) + file_len;
+        if existing_bytes.is_none() {
+            usage.files += 1;
+        }
+        if folder.is_some() {
+            if let Some(previous) = &previous {
+                if let Err(e) = sync_lock::note_remove_unless_newer(
+                    &vault.path,
+                    previous,
+                    file_to_sync.modified,
+                )
+                .await
+                {
+                    tracing::warn!("could not remove moved {}: {}", previous.display(), e);
+                }
+            }
+        } else if let Some(renamed) = received_before
+            .get(&file_to_sync.uuid)
+            .filter(|before| before.path != file_to_sync.path)
+        {
+            let old_path = contact_dir.join(&renamed.path);
+            let old_bytes = fs::metadata(&old_path).map(|m| m.len()).unwrap_or(0);
+            match sync_lock::note_remove_unless_newer(&vault.path, &old_path, file_to_sync.modified)
+                .await
+            {
+                Ok(true) => {
+                    usage.bytes = usage.bytes.saturating_sub(old_bytes);
+                    usage.files = usage.files.saturating_sub(1);
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!("could not remove renamed {}: {}", old_path.display(), e)
+                }
+            }
+        }
 
         if let Err(e) = transfer_record.record_file_complete(RecentFile {
             uuid: file_to_sync.uuid,
             filename: file_to_sync.path.to_string_lossy().to_string(),
-            timestamp: file_to_sync.modified,
+            timestamp: file_to_sync.modified.to_lamport(),
         }) {
             tracing::warn!("error writing transfer record: {}", e);
         }
     }
 
+    // notes that are no longer shared with us
+    for (uuid, local_entry) in create_manifest_for_contact(&contact_dir)? {
+        let Some(tombstone) = remote_tombstones.iter().find(|t| t.uuid == uuid) else {
+            continue;
+        };
+        if local_entry.modified > tombstone.deleted_at {
+            continue;
+        }
+        let full_path = contact_dir.join(&local_entry.path);
+        tracing::info!("removing withdrawn {}", full_path.display());
+        if let Err(e) =
+            sync_lock::note_remove_unless_newer(&vault.path, &full_path, tombstone.deleted_at).await
+        {
+            tracing::warn!("could not remove {}: {}", full_path.display(), e);
+        }
+    }
+
+    // notes a member deleted from a shared folder, or moved out of it. They
+    // can only delete the notes they wrote.
+    for tombstone in &remote_tombstones {
+        let Some(local_entry) = local_manifest
+            .get(&tombstone.uuid)
+            .filter(|e| folders.iter().any(|(_, ours)| ours.contains(&e.path)))
+        else {
+            continue;
+        };
+        if local_entry.modified > tombstone.deleted_at {
+            continue;
+        }
+        let full_path = vault.path.join(&local_entry.path);
+        let author = Note::from_path(&full_path, false)
+            .ok()
+            .and_then(|n| n.frontmatter.author);
+        if author.as_deref() != Some(member.as_str()) {
+            tracing::warn!(
+                "{} deleted {}, a note they didn't write",
+                nickname,
+                full_path.display()
+            );
+            continue;
+        }
+        match sync_lock::note_remove_unless_newer(&vault.path, &full_path, tombstone.deleted_at)
+            .await
+        {
+            // our other devices delete it on the next mirror
+            Ok(true) => {
+                tombstone_create(&vault.base_path(), tombstone.uuid, tombstone.deleted_at).await?
+            }
+            Ok(false) => {}
+            Err(e) => tracing::warn!("could not remove {}: {}", full_path.display(), e),
+        }
+    }
+
     // this manifest freshness check may not be worth it because it's at best a
     // mitigation. it's to protect against a stale device coming online and
     // removing a bunch of files. they would just re-replicate when a newer
@@ -139,9 +447,8 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
         .values()
         .map(|e| e.modified)
         .max()
-        .unwrap_or(LamportTimestamp(0));
+        .unwrap_or_default();
 
-    let contact_dir = vault.path.join("footnotes").join(nickname);
     let local_contact_manifest = create_manifest_for_contact(&contact_dir)?;
 
     for (uuid, local_entry) in &local_contact_manifest {
@@ -152,7 +459,10 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
                 "removing file that was not in the manifest: {}",
                 full_path.display()
             );
-            if let Err(e) = fs::remove_file(&full_path) {
+            if let Err(e) =
+                sync_lock::note_remove_unless_newer(&vault.path, &full_path, manifest_timestamp)
+                    .await
+            {
                 tracing::debug!(
                     "failed to delete file missing from manifest {}: {}",
                     full_path.display(),
@@ -162,13 +472,124 @@ pub async fn receive_share(vault: &Vault, nickname: &str, connection: Connection
         }
     }
 
+    collab::render(vault, &merged).await?;
+    exchanged.extend(merged);
+
     transfer_record.record_success()?;
     network::send_eof(&mut send).await?;
+    network::send_docs(&mut send, &collab::docs_read(vault, &exchanged)?).await?;
+    network::send_bytes(&mut send, &serde_json::to_vec(&rejected)?).await?;
     connection.closed().await;
-    Ok(())
+    Ok(remote_version)
+}
+
+/// A note a member of a shared folder sent us, with `author` and its
+/// signature whatever the note names
+fn with_author(contents: Vec<u8>, author: Option<String>, signature: Option<String>) -> Vec<u8> {
+    let Ok(mut note) = Note::from_string(&String::from_utf8_lossy(&contents), false) else {
+        return contents;
+    };
+    note.frontmatter.author = author;
+    note.frontmatter.author_signature = signature;
+    note.to_string().map(String::into_bytes).unwrap_or(contents)
 }
 
-pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()> {
+/// A note of ours in a shared folder going to a member, named as ours and
+/// signed by this device, so the members it reaches through another member
+/// know who wrote it. A note another member wrote keeps their claim.
+fn with_own_author(
+    vault: &Vault,
+    folders: &[SharedFolder],
+    entry: &ManifestEntry,
+    contents: Vec<u8>,
+) -> Result<Vec<u8>> {
+    if !folders.iter().any(|f| f.contains(&entry.path)) {
+        return Ok(contents);
+    }
+    let Ok(mut note) = Note::from_string(&String::from_utf8_lossy(&contents), false) else {
+        return Ok(contents);
+    };
+    if note.frontmatter.author.is_some() {
+        return Ok(contents);
+    }
+    let Some(user) = vault.user_read()? else {
+        return Ok(contents);
+    };
+    let (device_key, _) = vault.device_secret_key()?;
+    note.sign_author(&user.id_public_key, &device_key);
+    Ok(note.to_string()?.into_bytes())
+}
+
+/// the shared folders we write to with a contact
+fn folders_shared_with(vault: &Vault, nickname: &str) -> Result<Vec<SharedFolder>> {
+    let share_names = group::share_names_for(nickname, &vault.group_read()?);
+    Ok(vault
+        .settings_read()?
+        .shared_folders_with(&share_names)
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+/// where a member keeps a shared folder, a path within their vault
+fn is_folder_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Space the notes a member wrote take up in the shared folders, which
+/// count against their quota like the notes in footnotes/<nickname>/
+fn folder_usage(
+    vault_path: &Path,
+    folders: &[(PathBuf, SharedFolder)],
+    member: &str,
+) -> Result<quota::StorageUsage> {
+    let mut usage = quota::StorageUsage::default();
+    for (_, folder) in folders {
+        let base = vault_path.join(&folder.path);
+        for entry in create_manifest_for_contact(&base)?.into_values() {
+            let path = base.join(&entry.path);
+            let Ok(note) = Note::from_path(&path, false) else {
+                continue;
+            };
+            if note.frontmatter.author.as_deref() == Some(member) {
+                usage.files += 1;
+                usage.bytes += fs::metadata(&path)?.len();
+            }
+        }
+    }
+    Ok(usage)
+}
+
+fn contacts_unchanged(vault: &Vault, incoming: &[Contact]) -> bool {
+    let Ok(mut local) = vault.contact_read() else {
+        return false;
+    };
+    let mut incoming = incoming.to_vec();
+    local.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+    incoming.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+    local == incoming
+}
+
+fn reject_file(
+    transfer_record: &mut SyncStatusRecord,
+    file: &ManifestEntry,
+    reason: quota::RejectReason,
+) {
+    tracing::warn!("rejected {}: {}", file.path.display(), reason);
+    if let Err(e) = transfer_record.record_file_rejected(RejectedFile {
+        uuid: file.uuid,
+        filename: file.path.to_string_lossy().to_string(),
+        reason,
+        rejected_at: LamportTimestamp::now(),
+    }) {
+        tracing::warn!("error writing transfer record: {}", e);
+    }
+}
+
+/// Receive a mirror from one of our devices. Returns the app version the
+/// sender announced.
+pub async fn receive_mirror(vault: &Vault, connection: impl SyncConnection) -> Result<String> {
     let Ok(mut transfer_record) = SyncStatusRecord::start(
         vault.base_path(),
         connection.remote_id(),
@@ -182,6 +603,8 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
     // accept_bi(). Calling open_bi then waiting on the RecvStream without
     // writing anything to the connected SendStream will never succeed.
     let (mut send, mut recv) = connection.accept_bi().await?;
+    let remote_version = network::receive_app_version(&mut recv).await?;
+    network::send_app_version(&mut send).await?;
     let user_record_bytes = network::receive_bytes(&mut recv).await?;
     if user_record_bytes.is_empty() {
         anyhow::bail!("expected user record");
@@ -190,6 +613,18 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
     let incoming_user_record: Contact = serde_json::from_slice(&user_record_bytes)?;
     incoming_user_record.verify()?;
 
+    let contacts_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_contacts: Vec<Contact> =
+        serde_json::from_slice(&contacts_bytes).context("Failed to deserialize contact records")?;
+
+    let groups_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_groups: Vec<ContactGroup> =
+        serde_json::from_slice(&groups_bytes).context("Failed to deserialize groups")?;
+
+    // another of our devices may be mirroring to us at the same time, the
+    // records are read and replaced under one lock
+    let records_guard = sync_lock::records_lock().await;
+    let vault_lock = VaultLock::acquire_async(&vault.path).await?;
     let Some(user_record) = vault.user_read()? else {
         //TODO: we may be able to consolidate the pairing code with this code by
         // allowing a user record on our very first sync
@@ -200,21 +635,23 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
         tracing::error!("failed successor check: {}", e);
         anyhow::bail!("received invalid user record update");
     }
-    vault.user_write(&incoming_user_record)?;
+    // unchanged records are not rewritten, a write is a change to anyone
+    // watching the peer records
+    if incoming_user_record != user_record {
+        vault.user_write(&incoming_user_record)?;
+    }
 
-    let contacts_bytes = network::receive_bytes(&mut recv).await?;
-    let incoming_contacts: Vec<Contact> =
-        serde_json::from_slice(&contacts_bytes).context("Failed to deserialize contact records")?;
+    let sender_is_leader = incoming_user_record
+        .device_leader
+        .parse::<iroh::PublicKey>()
+        .map(|leader_key| leader_key == connection.remote_id())
+        .unwrap_or(false);
 
     if !incoming_contacts.is_empty() {
-        let sender_is_leader = incoming_user_record
-            .device_leader
-            .parse::<iroh::PublicKey>()
-            .map(|leader_key| leader_key == connection.remote_id())
-            .unwrap_or(false);
-
         if sender_is_leader {
-            if let Err(e) = vault.contacts_replace(&incoming_contacts) {
+            if contacts_unchanged(vault, &incoming_contacts) {
+                tracing::debug!("contacts from mirror are unchanged");
+            } else if let Err(e) = vault.contacts_replace(&incoming_contacts) {
                 tracing::error!("failed to sync contacts from mirror: {}", e);
             }
         } else {
@@ -225,6 +662,47 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
         }
     }
 
+    // the leader always sends its full group list, so an empty list from the
+    // leader means the last group was deleted
+    if sender_is_leader {
+        if vault
+            .group_read()
+            .is_ok_and(|groups| groups == incoming_groups)
+        {
+            tracing::debug!("groups from mirror are unchanged");
+        } else if let Err(e) = vault.groups_replace(&incoming_groups) {
+            tracing::error!("failed to sync groups from mirror: {}", e);
+        }
+    } else if !incoming_groups.is_empty() {
+        tracing::warn!(
+            "received groups from non-manager device {}, ignoring",
+            connection.remote_id()
+        );
+    }
+    drop(vault_lock);
+    drop(records_guard);
+
+    let ledgers_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_ledgers: HashMap<String, DeliveryLedger> =
+        serde_json::from_slice(&ledgers_bytes).context("Failed to deserialize delivery ledgers")?;
+
+    // every device shares, merging what the others delivered keeps us from
+    // pushing the same notes again
+    let known_nicknames: Vec<String> = vault
+        .contact_read()?
+        .into_iter()
+        .map(|c| c.nickname)
+        .collect();
+    for (nickname, ledger) in incoming_ledgers {
+        if !known_nicknames.contains(&nickname) {
+            tracing::warn!("ignoring delivery ledger for unknown contact {}", nickname);
+            continue;
+        }
+        if let Err(e) = delivery_merge(&vault.path, &nickname, ledger).await {
+            tracing::error!("failed to merge delivery ledger for {}: {}", nickname, e);
+        }
+    }
+
     let manifest_bytes = network::receive_bytes(&mut recv).await?;
 
     let remote_manifest: Manifest =
@@ -234,15 +712,33 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
     let remote_tombstones: Vec<Tombstone> =
         serde_json::from_slice(&tombstone_bytes).context("Failed to deserialize tombstone")?;
 
+    let remote_docs = network::receive_docs(&mut recv).await?;
+
+    let folders_bytes = network::receive_bytes(&mut recv).await?;
+    let incoming_folders: Vec<(uuid::Uuid, PathBuf)> =
+        serde_json::from_slice(&folders_bytes).context("Failed to deserialize shared folders")?;
+    if !incoming_folders.is_empty() {
+        tracing::error!("received shared folders from mirror peer");
+    }
+
     let local_manifest =
         create_manifest_full(&vault.path).context("Failed to create local manifest")?;
     let files_to_sync = diff_manifests(&local_manifest, &remote_manifest);
 
+    let accepted: HashSet<_> = local_manifest
+        .keys()
+        .chain(remote_manifest.keys())
+        .copied()
+        .collect();
+    collab::absorb(vault).await?;
+    let merged = collab::merge(vault, remote_docs, &accepted).await?;
+
     if let Err(e) = transfer_record.update(0, Some(files_to_sync.len())) {
         tracing::warn!("could not update transfer record: {}", e);
     }
     let local_tombstones_cache = tombstones_read(&vault.base_path())?;
-    for file_to_sync in &files_to_sync {
+    let remote_id = connection.remote_id().to_string();
+    for (file_to_sync, verdict) in &files_to_sync {
         let path_components: Vec<_> = file_to_sync.path.components().collect();
         for component in &path_components {
             match component {
@@ -293,15 +789,44 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
             tombstone_delete(&vault.base_path(), &tombstone.uuid).await?;
         }
 
+        if *verdict == SyncVerdict::Concurrent {
+            tracing::info!(
+                "{} was edited here and on {} without seeing the other edit",
+                file_to_sync.path.display(),
+                remote_id
+            );
+        }
+
+        // the other device renamed the note
+        let previous_path = local_manifest
+            .get(&file_to_sync.uuid)
+            .filter(|local| local.path != file_to_sync.path)
+            .map(|local| vault.path.join(&local.path));
+
         network::send_file_request(&mut send, &file_to_sync.uuid).await?;
         let file_contents = network::receive_file_contents(&mut recv).await?;
-        let temp_path = canonical_full.with_extension("tmp");
-        fs::write(&temp_path, file_contents)?;
-        fs::rename(&temp_path, &canonical_full)?;
+        let file_contents = collab::with_doc_content(vault, file_contents)?;
+        if !sync_lock::note_mirror_write(
+            &vault.path,
+            MirroredNote {
+                uuid: file_to_sync.uuid,
+                path: &canonical_full,
+                previous_path: previous_path.as_deref(),
+                contents: &file_contents,
+                modified: file_to_sync.modified,
+                versions: &file_to_sync.versions,
+            },
+            &remote_id,
+        )
+        .await?
+        {
+            tracing::debug!("{} is already up to date", file_to_sync.path.display());
+            continue;
+        }
         if let Err(e) = transfer_record.record_file_complete(RecentFile {
             uuid: file_to_sync.uuid,
             filename: file_to_sync.path.to_string_lossy().to_string(),
-            timestamp: file_to_sync.modified,
+            timestamp: file_to_sync.modified.to_lamport(),
         }) {
             tracing::warn!("could not write transfer complete: {}", e);
         }
@@ -319,26 +844,60 @@ pub async fn receive_mirror(vault: &Vault, connection: Connection) -> Result<()>
                 continue;
             }
             tracing::info!("deleting {}", entry.path.display());
-            if let Err(e) = fs::remove_file(&vault.base_path().join(&entry.path)) {
-                tracing::warn!("cound not remove {}: {}", entry.path.display(), e);
+            match sync_lock::note_mirror_remove(
+                &vault.path,
+                &vault.base_path().join(&entry.path),
+                entry_to_delete.uuid,
+                entry_to_delete.deleted_at,
+                &remote_id,
+            )
+            .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::info!("keeping {} - edited while syncing", entry.path.display());
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!("cound not remove {}: {}", entry.path.display(), e);
+                }
             }
-            tombstone_create(&vault.base_path(), entry_to_delete.uuid, entry.modified).await?;
+            tombstone_create(
+                &vault.base_path(),
+                entry_to_delete.uuid,
+                entry_to_delete.deleted_at,
+            )
+            .await?;
         }
     }
 
+    collab::render(vault, &merged).await?;
+    let exchanged = collab::mirrored_notes(vault)?;
+
     transfer_record.record_success()?;
     network::send_eof(&mut send).await?;
+    network::send_docs(&mut send, &collab::docs_read(vault, &exchanged)?).await?;
+    // our own devices have no quota
+    network::send_bytes(&mut send, &serde_json::to_vec(&Vec::<uuid::Uuid>::new())?).await?;
     connection.closed().await;
-    Ok(())
+    Ok(remote_version)
+}
+
+/// Records only the device leader maintains, mirrored to the other devices in
+/// the group. Empty when the sender is not the leader or for a share.
+#[derive(Debug, Default)]
+pub struct LeaderRecords {
+    pub contacts: Vec<Contact>,
+    pub groups: Vec<ContactGroup>,
 }
 
 pub async fn sync_to_target(
     vault: &Vault,
-    endpoint: Endpoint,
+    transport: impl Transport,
     sync_type: SyncType,
     manifest: Manifest,
     tombstone: Vec<Tombstone>,
-    contacts_to_send: Vec<Contact>,
+    leader_records: LeaderRecords,
     remote_endpoint_id: iroh::PublicKey,
     alpn: &[u8],
 ) -> Result<()> {
@@ -347,6 +906,7 @@ pub async fn sync_to_target(
         return Ok(());
     }
 
+    let is_share = matches!(sync_type, SyncType::Share);
     let Ok(mut transfer_record) = SyncStatusRecord::start(
         vault.base_path(),
         remote_endpoint_id,
@@ -356,62 +916,292 @@ pub async fn sync_to_target(
         anyhow::bail!("could not create log record");
     };
 
-    let conn = endpoint
-        .connect(remote_endpoint_id, alpn)
-        .await
-        .context("Failed to connect to remote device")?;
-
-    // Calling open_bi then waiting on the RecvStream without writing anything
-    // to SendStream will never succeed.
-    let (mut send, mut recv) = conn.open_bi().await?;
+    // any failure from here on is left in the outbound status for the
+    // activity panel
+    let sent = async {
+        let share_with = if is_share {
+            Some(
+                vault
+                    .find_contact_by_endpoint(&remote_endpoint_id)?
+                    .nickname,
+            )
+        } else {
+            None
+        };
+        collab::absorb(vault).await?;
+        let exchanged = collab::exchanged_notes(vault, &manifest, share_with.as_deref())?;
+        let docs = collab::docs_read(vault, &exchanged)?;
 
-    if let Ok(Some(user_record)) = vault.user_read() {
-        let user_record_bytes = serde_json::to_vec(&user_record)?;
-        network::send_bytes(&mut send, &user_record_bytes).await?;
-    } else {
-        anyhow::bail!("cannot send files without a user record");
-    }
+        let conn = transport
+            .connect(remote_endpoint_id, alpn)
+            .await
+            .context("Failed to connect to remote device")?;
 
-    let contacts_bytes =
-        serde_json::to_vec(&contacts_to_send).context("Failed to serialize contacts for mirror")?;
-    network::send_bytes(&mut send, &contacts_bytes).await?;
+        // Calling open_bi then waiting on the RecvStream without writing anything
+        // to SendStream will never succeed.
+        let (mut send, mut recv) = conn.open_bi().await?;
 
-    let serialised_manifest =
-        serde_json::to_vec(&manifest).context("Failed to serialize manifest")?;
-    network::send_bytes(&mut send, &serialised_manifest).await?;
+        send_records(
+            &mut send,
+            vault,
+            share_with.as_deref(),
+            &manifest,
+            &tombstone,
+            &leader_records,
+            &docs,
+        )
+        .await?;
+        let remote_version = network::receive_app_version(&mut recv).await?;
 
-    let serialised_tombstone =
-        serde_json::to_vec(&tombstone).context("Failed to serialize tombstone")?;
-    network::send_bytes(&mut send, &serialised_tombstone).await?;
-
-    let mut files_transferred = 0;
-    loop {
-        let file_uuid = match network::receive_file_request(&mut recv).await? {
-            Some(uuid) => uuid,
-            None => break,
+        let folders = match &share_with {
+            Some(nickname) => folders_shared_with(vault, nickname)?,
+            None => Vec::new(),
         };
+        let mut files_transferred = 0;
+        let mut withheld = Vec::new();
+        loop {
+            let file_uuid = match network::receive_file_request(&mut recv).await? {
+                Some(uuid) => uuid,
+                None => break,
+            };
 
-        let entry = manifest
-            .get(&file_uuid)
-            .ok_or_else(|| anyhow::anyhow!("Requested file UUID not in manifest"))?;
+            let entry = manifest
+                .get(&file_uuid)
+                .ok_or_else(|| anyhow::anyhow!("Requested file UUID not in manifest"))?;
 
-        let full_path = vault.path.join(&entry.path);
-        if !vault.can_device_read_note(&remote_endpoint_id, &full_path)? {
-            continue;
-        }
+            let full_path = vault.path.join(&entry.path);
+            if !vault.can_device_read_note(&remote_endpoint_id, &full_path)? {
+                withheld.push(file_uuid);
+                continue;
+            }
+
+            let file_contents = fs::read(&full_path)
+                .with_context(|| format!("Failed to read file: {}", full_path.display()))?;
+            let file_contents = with_own_author(vault, &folders, entry, file_contents)?;
 
-        let file_contents = fs::read(&full_path)
-            .with_context(|| format!("Failed to read file: {}", full_path.display()))?;
+            network::send_file_contents(&mut send, &file_contents).await?;
+            files_transferred += 1;
+            if let Err(e) = transfer_record.update(files_transferred, None) {
+                tracing::warn!("could not update status: {}", e);
+            }
+        }
 
-        network::send_file_contents(&mut send, &file_contents).await?;
-        files_transferred += 1;
-        if let Err(e) = transfer_record.update(files_transferred, None) {
-            tracing::warn!("could not update status: {}", e);
+        // the receiver's documents, merged with ours
+        let reply = network::receive_docs(&mut recv).await?;
+        let rejected: Vec<uuid::Uuid> =
+            serde_json::from_slice(&network::receive_bytes(&mut recv).await?)
+                .context("Failed to deserialize rejected notes")?;
+        withheld.extend(rejected);
+        let merged = collab::merge(vault, reply, &exchanged).await?;
+        collab::render(vault, &merged).await?;
+        if let Some(nickname) = &share_with {
+            collab::record_exchanged(vault, nickname, &exchanged).await?;
         }
+        anyhow::Ok((conn, withheld, remote_version))
     }
+    .await;
+    let (conn, withheld, remote_version) = match sent {
+        Ok(sent) => sent,
+        Err(e) => {
+            if let Err(record_error) = transfer_record.record_failure(&format!("{:#}", e)) {
+                tracing::warn!("could not record failure: {}", record_error);
+            }
+            return Err(e);
+        }
+    };
 
     transfer_record.record_success()?;
-    conn.close(0u8.into(), b"done");
+    if let Err(e) =
+        presence::presence_record(&vault.path, &remote_endpoint_id, Some(&remote_version)).await
+    {
+        tracing::warn!("could not record presence: {}", e);
+    }
+    if let Some(addr) = transport.remote_addr(remote_endpoint_id) {
+        if let Err(e) = peer_address::peer_address_record(&vault.path, &addr).await {
+            tracing::warn!("could not record peer address: {}", e);
+        }
+    }
+    conn.close(0, b"done");
     conn.closed().await;
+
+    // after a successful share the contact holds every version in the
+    // manifest, but for the notes it may not read and the ones it turned
+    // away for its quota, which are tried again on the next share
+    if is_share {
+        if let Err(e) =
+            record_share_delivery(vault, &remote_endpoint_id, &manifest, &withheld, &tombstone)
+                .await
+        {
+            tracing::warn!("could not update delivery ledger: {}", e);
+        }
+    }
     Ok(())
 }
+
+/// The sync `sync_to_target` would send to `reader`, as a signed bundle
+/// holding every note in the manifest the reader may read.
+pub async fn export_bundle(
+    vault: &Vault,
+    scope: BundleScope,
+    sync_type: SyncType,
+    mut manifest: Manifest,
+    tombstones: Vec<Tombstone>,
+    leader_records: LeaderRecords,
+    reader: iroh::PublicKey,
+) -> Result<Vec<u8>> {
+    let (secret_key, _) = vault.device_secret_key()?;
+    let is_share = matches!(sync_type, SyncType::Share);
+    let share_with = if is_share {
+        Some(vault.find_contact_by_endpoint(&reader)?.nickname)
+    } else {
+        None
+    };
+    let folders = match &share_with {
+        Some(nickname) => folders_shared_with(vault, nickname)?,
+        None => Vec::new(),
+    };
+    let mut files = BTreeMap::new();
+    for entry in manifest.values() {
+        let full_path = vault.path.join(&entry.path);
+        if vault.can_device_read_note(&reader, &full_path)? {
+            let contents = fs::read(&full_path)
+                .with_context(|| format!("Failed to read file: {}", full_path.display()))?;
+            files.insert(
+                entry.uuid,
+                with_own_author(vault, &folders, entry, contents)?,
+            );
+        }
+    }
+    // the receiver won't ask for notes that aren't in the manifest
+    manifest.retain(|uuid, _| files.contains_key(uuid));
+
+    collab::absorb(vault).await?;
+    let exchanged = collab::exchanged_notes(vault, &manifest, share_with.as_deref())?;
+    let docs = collab::docs_read(vault, &exchanged)?;
+
+    let mut records = Vec::new();
+    send_records(
+        &mut records,
+        vault,
+        share_with.as_deref(),
+        &manifest,
+        &tombstones,
+        &leader_records,
+        &docs,
+    )
+    .await?;
+
+    let header = BundleHeader {
+        scope,
+        from: secret_key.public().to_string(),
+        created_at: LamportTimestamp::now(),
+        protocol: String::from_utf8_lossy(ALPN_SYNC).to_string(),
+    };
+    Bundle::new(header, records, files)
+        .to_bytes(&secret_key)
+        .await
+}
+
+/// Everything a sync sends before the receiver requests files, in wire order:
+/// app version, user record, leader records, delivery ledgers, manifest,
+/// tombstones, the documents of collaborative notes and the shared folders
+/// the receiver writes to with us. `share_with` is the contact for a share.
+pub async fn send_records(
+    send: &mut (impl AsyncWrite + Unpin),
+    vault: &Vault,
+    share_with: Option<&str>,
+    manifest: &Manifest,
+    tombstones: &[Tombstone],
+    leader_records: &LeaderRecords,
+    docs: &[(uuid::Uuid, Vec<u8>)],
+) -> Result<()> {
+    network::send_app_version(send).await?;
+    if let Ok(Some(user_record)) = vault.user_read() {
+        let user_record_bytes = serde_json::to_vec(&user_record)?;
+        network::send_bytes(send, &user_record_bytes).await?;
+    } else {
+        anyhow::bail!("cannot send files without a user record");
+    }
+
+    let contacts_bytes = serde_json::to_vec(&leader_records.contacts)
+        .context("Failed to serialize contacts for mirror")?;
+    network::send_bytes(send, &contacts_bytes).await?;
+
+    let groups_bytes = serde_json::to_vec(&leader_records.groups)
+        .context("Failed to serialize groups for mirror")?;
+    network::send_bytes(send, &groups_bytes).await?;
+
+    let ledgers = if share_with.is_some() {
+        HashMap::new()
+    } else {
+        delivery_ledgers_read(&vault.path)?
+    };
+    let ledgers_bytes =
+        serde_json::to_vec(&ledgers).context("Failed to serialize delivery ledgers")?;
+    network::send_bytes(send, &ledgers_bytes).await?;
+
+    let serialised_manifest =
+        serde_json::to_vec(manifest).context("Failed to serialize manifest")?;
+    network::send_bytes(send, &serialised_manifest).await?;
+
+    let serialised_tombstone =
+        serde_json::to_vec(tombstones).context("Failed to serialize tombstone")?;
+    network::send_bytes(send, &serialised_tombstone).await?;
+    network::send_docs(send, docs).await?;
+
+    // the receiver finds the notes of each folder under its path in our vault
+    let folders: Vec<(uuid::Uuid, PathBuf)> = match share_with {
+        Some(nickname) => folders_shared_with(vault, nickname)?
+            .into_iter()
+            .map(|f| (f.id, f.path))
+            .collect(),
+        None => Vec::new(),
+    };
+    let folders_bytes =
+        serde_json::to_vec(&folders).context("Failed to serialize shared folders")?;
+    network::send_bytes(send, &folders_bytes).await?;
+    Ok(())
+}
+
+async fn record_share_delivery(
+    vault: &Vault,
+    remote_endpoint_id: &iroh::PublicKey,
+    manifest: &Manifest,
+    withheld: &[uuid::Uuid],
+    withdrawals: &[Tombstone],
+) -> Result<()> {
+    let contact = vault.find_contact_by_endpoint(remote_endpoint_id)?;
+    let share_names = group::share_names_for(&contact.nickname, &vault.group_read()?);
+    let named: HashSet<uuid::Uuid> = manifest
+        .values()
+        .filter(|e| {
+            Note::from_path(vault.path.join(&e.path), false).is_ok_and(|note| {
+                note.frontmatter
+                    .share_with
+                    .iter()
+                    .any(|n| share_names.contains(n))
+            })
+        })
+        .map(|e| e.uuid)
+        .collect();
+    let endpoint_id = remote_endpoint_id.to_string();
+    let device_name = contact
+        .devices
+        .iter()
+        .find(|d| d.iroh_endpoint_id == endpoint_id)
+        .map(|d| d.name.clone())
+        .unwrap_or_default();
+    delivery_record(
+        &vault.path,
+        &contact.nickname,
+        &endpoint_id,
+        &device_name,
+        SharedNotes {
+            manifest,
+            withheld,
+            named: &named,
+            withdrawals,
+        },
+    )
+    .await
+}
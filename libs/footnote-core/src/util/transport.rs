@@ -0,0 +1,373 @@
+use anyhow::Result;
+use iroh::endpoint::{Connection, ConnectionType, RecvStream, SendStream, VarInt};
+use iroh::{Endpoint, EndpointAddr, PublicKey, TransportAddr, Watcher};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+/// an accepted connection still finishing its handshake
+pub type Connecting<C> = Pin<Box<dyn Future<Output = Result<C>> + Send>>;
+
+/// What the sync code needs from the network: connect to a peer by endpoint
+/// id and accept connections from peers. `iroh::Endpoint` is the real one,
+/// `MemoryTransport` connects vaults in the same process for tests.
+pub trait Transport: Clone + Send + Sync + 'static {
+    type Connection: SyncConnection;
+
+    /// the endpoint id peers know us by
+    fn id(&self) -> PublicKey;
+
+    fn connect(
+        &self,
+        remote: PublicKey,
+        alpn: &[u8],
+    ) -> impl Future<Output = Result<Self::Connection>> + Send;
+
+    /// None once the transport is closed
+    fn accept(&self) -> impl Future<Output = Option<Connecting<Self::Connection>>> + Send;
+
+    /// how the peer is reached right now, for the peer address cache
+    fn remote_addr(&self, _remote: PublicKey) -> Option<EndpointAddr> {
+        None
+    }
+}
+
+/// An authenticated connection to one peer, carrying bidirectional streams.
+pub trait SyncConnection: Clone + Send + Sync + 'static {
+    type SendStream: AsyncWrite + Unpin + Send;
+    type RecvStream: AsyncRead + Unpin + Send;
+
+    fn remote_id(&self) -> PublicKey;
+
+    /// the protocol the connecting side asked for
+    fn alpn(&self) -> Vec<u8>;
+
+    /// the opening side must write before the other side can accept
+    fn open_bi(&self) -> impl Future<Output = Result<(Self::SendStream, Self::RecvStream)>> + Send;
+
+    fn accept_bi(
+        &self,
+    ) -> impl Future<Output = Result<(Self::SendStream, Self::RecvStream)>> + Send;
+
+    fn close(&self, code: u32, reason: &[u8]);
+
+    /// resolves once either side closed the connection
+    fn closed(&self) -> impl Future<Output = ()> + Send;
+}
+
+impl Transport for Endpoint {
+    type Connection = Connection;
+
+    fn id(&self) -> PublicKey {
+        Endpoint::id(self)
+    }
+
+    async fn connect(&self, remote: PublicKey, alpn: &[u8]) -> Result<Connection> {
+        Ok(Endpoint::connect(self, remote, alpn).await?)
+    }
+
+    async fn accept(&self) -> Option<Connecting<Connection>> {
+        let incoming = Endpoint::accept(self).await?;
+        Some(Box::pin(async move { Ok(incoming.await?) }))
+    }
+
+    fn remote_addr(&self, remote: PublicKey) -> Option<EndpointAddr> {
+        let addrs = match self.conn_type(remote)?.get() {
+            ConnectionType::Direct(addr) => vec![TransportAddr::Ip(addr)],
+            ConnectionType::Relay(url) => vec![TransportAddr::Relay(url)],
+            ConnectionType::Mixed(addr, url) => {
+                vec![TransportAddr::Ip(addr), TransportAddr::Relay(url)]
+            }
+            ConnectionType::None => return None,
+        };
+        Some(EndpointAddr::from_parts(remote, addrs))
+    }
+}
+
+impl SyncConnection for Connection {
+    type SendStream = SendStream;
+    type RecvStream = RecvStream;
+
+    fn remote_id(&self) -> PublicKey {
+        Connection::remote_id(self)
+    }
+
+    fn alpn(&self) -> Vec<u8> {
+        Connection::alpn(self).to_vec()
+    }
+
+    async fn open_bi(&self) -> Result<(SendStream, RecvStream)> {
+        Ok(Connection::open_bi(self).await?)
+    }
+
+    async fn accept_bi(&self) -> Result<(SendStream, RecvStream)> {
+        Ok(Connection::accept_bi(self).await?)
+    }
+
+    fn close(&self, code: u32, reason: &[u8]) {
+        Connection::close(self, VarInt::from_u32(code), reason);
+    }
+
+    async fn closed(&self) {
+        Connection::closed(self).await;
+    }
+}
+
+/// bytes a memory stream buffers before the writer waits for the reader
+const MEMORY_STREAM_BUFFER: usize = 64 * 1024;
+
+/// Endpoints in the same process that can reach each other. There is no
+/// handshake, the endpoint id is taken as given and any ALPN is accepted. Used by tests, and
+/// to replay an imported bundle to the receive code.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    endpoints: Arc<Mutex<HashMap<PublicKey, UnboundedSender<MemoryConnection>>>>,
+    next_budget: Arc<Mutex<Option<usize>>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// bring an endpoint online, replacing an earlier one with the same id
+    pub fn bind(&self, id: PublicKey) -> MemoryTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.endpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, tx);
+        MemoryTransport {
+            id,
+            network: self.clone(),
+            incoming: Arc::new(tokio::sync::Mutex::new(rx)),
+        }
+    }
+
+    /// take an endpoint offline. connecting to it fails and its accept
+    /// returns None.
+    pub fn unbind(&self, id: &PublicKey) {
+        self.endpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(id);
+    }
+
+    /// cut the next connection once its two sides have written this many
+    /// bytes between them, as if the network dropped mid sync
+    pub fn fail_after(&self, bytes: usize) {
+        *self.next_budget.lock().unwrap_or_else(|e| e.into_inner()) = Some(bytes);
+    }
+
+    fn take_budget(&self) -> Option<Arc<AtomicUsize>> {
+        self.next_budget
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+            .map(|bytes| Arc::new(AtomicUsize::new(bytes)))
+    }
+
+    fn route(&self, id: &PublicKey) -> Option<UnboundedSender<MemoryConnection>> {
+        self.endpoints
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(id)
+            .cloned()
+    }
+}
+
+#[derive(Clone)]
+pub struct MemoryTransport {
+    id: PublicKey,
+    network: MemoryNetwork,
+    incoming: Arc<tokio::sync::Mutex<UnboundedReceiver<MemoryConnection>>>,
+}
+
+impl Transport for MemoryTransport {
+    type Connection = MemoryConnection;
+
+    fn id(&self) -> PublicKey {
+        self.id
+    }
+
+    async fn connect(&self, remote: PublicKey, alpn: &[u8]) -> Result<MemoryConnection> {
+        let Some(route) = self.network.route(&remote) else {
+            anyhow::bail!("{} is not reachable", remote);
+        };
+        let (local, peer) =
+            MemoryConnection::pair(self.id, remote, alpn, self.network.take_budget());
+        if route.send(peer).is_err() {
+            anyhow::bail!("{} is not reachable", remote);
+        }
+        Ok(local)
+    }
+
+    async fn accept(&self) -> Option<Connecting<MemoryConnection>> {
+        let connection = self.incoming.lock().await.recv().await?;
+        Some(Box::pin(async move { Ok(connection) }))
+    }
+}
+
+/// One side of an in-memory connection. The connection closes when either
+/// side calls close or drops its last handle.
+#[derive(Clone)]
+pub struct MemoryConnection {
+    side: Arc<MemoryConnectionSide>,
+}
+
+struct MemoryConnectionSide {
+    remote_id: PublicKey,
+    alpn: Vec<u8>,
+    streams_out: UnboundedSender<DuplexStream>,
+    streams_in: tokio::sync::Mutex<UnboundedReceiver<DuplexStream>>,
+    closed: CancellationToken,
+    budget: Option<Arc<AtomicUsize>>,
+}
+
+impl Drop for MemoryConnectionSide {
+    fn drop(&mut self) {
+        self.closed.cancel();
+    }
+}
+
+impl MemoryConnection {
+    fn pair(
+        a: PublicKey,
+        b: PublicKey,
+        alpn: &[u8],
+        budget: Option<Arc<AtomicUsize>>,
+    ) -> (Self, Self) {
+        let closed = CancellationToken::new();
+        let (a_to_b, b_from_a) = mpsc::unbounded_channel();
+        let (b_to_a, a_from_b) = mpsc::unbounded_channel();
+        let side_a = MemoryConnectionSide {
+            remote_id: b,
+            alpn: alpn.to_vec(),
+            streams_out: a_to_b,
+            streams_in: tokio::sync::Mutex::new(a_from_b),
+            closed: closed.clone(),
+            budget: budget.clone(),
+        };
+        let side_b = MemoryConnectionSide {
+            remote_id: a,
+            alpn: alpn.to_vec(),
+            streams_out: b_to_a,
+            streams_in: tokio::sync::Mutex::new(b_from_a),
+            closed,
+            budget,
+        };
+        (
+            Self {
+                side: Arc::new(side_a),
+            },
+            Self {
+                side: Arc::new(side_b),
+            },
+        )
+    }
+}
+
+impl MemoryConnection {
+    fn send_stream(&self, inner: WriteHalf<DuplexStream>) -> MemorySendStream {
+        MemorySendStream {
+            inner,
+            budget: self.side.budget.clone(),
+        }
+    }
+}
+
+impl SyncConnection for MemoryConnection {
+    type SendStream = MemorySendStream;
+    type RecvStream = ReadHalf<DuplexStream>;
+
+    fn remote_id(&self) -> PublicKey {
+        self.side.remote_id
+    }
+
+    fn alpn(&self) -> Vec<u8> {
+        self.side.alpn.clone()
+    }
+
+    async fn open_bi(&self) -> Result<(Self::SendStream, Self::RecvStream)> {
+        if self.side.closed.is_cancelled() {
+            anyhow::bail!("connection closed");
+        }
+        let (local, peer) = tokio::io::duplex(MEMORY_STREAM_BUFFER);
+        if self.side.streams_out.send(peer).is_err() {
+            anyhow::bail!("connection closed");
+        }
+        let (recv, send) = tokio::io::split(local);
+        Ok((self.send_stream(send), recv))
+    }
+
+    async fn accept_bi(&self) -> Result<(Self::SendStream, Self::RecvStream)> {
+        let mut streams_in = self.side.streams_in.lock().await;
+        tokio::select! {
+            _ = self.side.closed.cancelled() => anyhow::bail!("connection closed"),
+            stream = streams_in.recv() => {
+                let Some(stream) = stream else {
+                    anyhow::bail!("connection closed");
+                };
+                let (recv, send) = tokio::io::split(stream);
+                Ok((self.send_stream(send), recv))
+            }
+        }
+    }
+
+    fn close(&self, _code: u32, _reason: &[u8]) {
+        self.side.closed.cancel();
+    }
+
+    async fn closed(&self) {
+        self.side.closed.cancelled().await;
+    }
+}
+
+/// Writes to a memory stream, failing once the connection's byte budget from
+/// `MemoryNetwork::fail_after` is spent.
+pub struct MemorySendStream {
+    inner: WriteHalf<DuplexStream>,
+    budget: Option<Arc<AtomicUsize>>,
+}
+
+impl AsyncWrite for MemorySendStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let Some(budget) = self.budget.clone() else {
+            return Pin::new(&mut self.inner).poll_write(cx, buf);
+        };
+        let remaining = budget.load(Ordering::SeqCst);
+        if remaining == 0 {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "connection lost",
+            )));
+        }
+        let len = buf.len().min(remaining);
+        let written = Pin::new(&mut self.inner).poll_write(cx, &buf[..len]);
+        if let Poll::Ready(Ok(n)) = written {
+            let _ = budget.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                Some(left.saturating_sub(n))
+            });
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// how long to wait for another process to finish before giving up
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+const LOCK_RETRY: Duration = Duration::from_millis(50);
+
+/// lock files this process holds, with how many guards are alive for each
+static HELD: OnceLock<Mutex<HashMap<PathBuf, Held>>> = OnceLock::new();
+
+struct Held {
+    _file: File,
+    count: usize,
+}
+
+/// Advisory lock on .footnote/vault.lock, held while changing tombstones,
+/// contacts, groups, the user record, settings or notes received by sync, so
+/// the CLI and the app can run on the same vault.
+///
+/// The lock is between processes only. Within a process it is reentrant:
+/// every guard after the first is free, and the static write locks next to
+/// each file keep tasks in the same process in order.
+pub struct VaultLock {
+    lock_path: PathBuf,
+}
+
+enum Attempt {
+    Acquired(VaultLock),
+    Busy,
+}
+
+impl VaultLock {
+    /// Waits a few seconds for another process to let go, then fails. This
+    /// blocks the thread, it is for the CLI and the sync Vault methods. Async
+    /// code takes `acquire_async` before calling those, the lock is then
+    /// already held and they don't wait, the app calls them in
+    /// `spawn_blocking`.
+    pub fn acquire(vault_path: &Path) -> Result<Self> {
+        Self::acquire_within(vault_path, LOCK_WAIT)
+    }
+
+    fn acquire_within(vault_path: &Path, wait: Duration) -> Result<Self> {
+        let lock_path = Self::lock_path(vault_path)?;
+        let deadline = Instant::now() + wait;
+        loop {
+            if let Attempt::Acquired(lock) = Self::try_acquire(&lock_path)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                return Err(Self::busy_error(&lock_path));
+            }
+            std::thread::sleep(LOCK_RETRY);
+        }
+    }
+
+    /// the same as acquire without blocking the runtime while waiting
+    pub async fn acquire_async(vault_path: &Path) -> Result<Self> {
+        let lock_path = Self::lock_path(vault_path)?;
+        let deadline = Instant::now() + LOCK_WAIT;
+        loop {
+            if let Attempt::Acquired(lock) = Self::try_acquire(&lock_path)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                return Err(Self::busy_error(&lock_path));
+            }
+            tokio::time::sleep(LOCK_RETRY).await;
+        }
+    }
+
+    fn lock_path(vault_path: &Path) -> Result<PathBuf> {
+        let footnote_dir = vault_path.join(".footnote");
+        fs::create_dir_all(&footnote_dir)?;
+        // the same vault may be reached by different paths
+        let footnote_dir = footnote_dir
+            .canonicalize()
+            .context("Failed to canonicalize vault path")?;
+        Ok(footnote_dir.join("vault.lock"))
+    }
+
+    fn try_acquire(lock_path: &Path) -> Result<Attempt> {
+        let mut held = HELD
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = held.get_mut(lock_path) {
+            existing.count += 1;
+            return Ok(Attempt::Acquired(Self {
+                lock_path: lock_path.to_path_buf(),
+            }));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(lock_path)
+            .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(Attempt::Busy),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", lock_path.display()))
+            }
+        }
+        // only for the error message of whoever waits on us
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+
+        held.insert(
+            lock_path.to_path_buf(),
+            Held {
+                _file: file,
+                count: 1,
+            },
+        );
+        Ok(Attempt::Acquired(Self {
+            lock_path: lock_path.to_path_buf(),
+        }))
+    }
+
+    fn busy_error(lock_path: &Path) -> anyhow::Error {
+        let holder = fs::read_to_string(lock_path).unwrap_or_default();
+        let holder = holder.trim();
+        if holder.is_empty() {
+            anyhow::anyhow!(
+                "vault is in use by another footnote process ({}), try again",
+                lock_path.display()
+            )
+        } else {
+            anyhow::anyhow!(
+                "vault is in use by another footnote process (pid {}), try again",
+                holder
+            )
+        }
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let mut held = HELD
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = held.get_mut(&self.lock_path) {
+            existing.count -= 1;
+            if existing.count == 0 {
+                // closing the file releases the lock
+                held.remove(&self.lock_path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_vault() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("footnote-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_reentrant_within_process() {
+        let vault = temp_vault();
+        let outer = VaultLock::acquire_within(&vault, Duration::ZERO).unwrap();
+        let inner = VaultLock::acquire_within(&vault, Duration::ZERO).unwrap();
+        drop(inner);
+        drop(outer);
+        fs::remove_dir_all(vault).unwrap();
+    }
+
+    #[test]
+    fn test_busy_while_another_process_holds_it() {
+        let vault = temp_vault();
+        // a second open file stands in for another process, locks on
+        // separate opens conflict
+        let lock_path = VaultLock::lock_path(&vault).unwrap();
+        let other = File::create(&lock_path).unwrap();
+        other.lock().unwrap();
+        fs::write(&lock_path, "4242").unwrap();
+
+        let err = VaultLock::acquire_within(&vault, Duration::ZERO)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("pid 4242"));
+
+        other.unlock().unwrap();
+        assert!(VaultLock::acquire_within(&vault, Duration::ZERO).is_ok());
+        fs::remove_dir_all(vault).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::util::hybrid_timestamp::HybridTimestamp;
+
+/// How many times each device has edited a note, keyed by the device part of
+/// its hybrid timestamps as 16 hex digits.
+///
+/// A version whose vector counts at least as many edits from every device as
+/// another's was made after seeing that one. When each has edits the other
+/// hasn't seen, the two were edited concurrently.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    /// count an edit made on `device`
+    pub fn increment(&mut self, device: u64) {
+        *self.0.entry(format!("{:016x}", device)).or_default() += 1;
+    }
+
+    /// count every edit either has seen
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (device, count) in &other.0 {
+            let ours = self.0.entry(device.clone()).or_default();
+            *ours = (*ours).max(*count);
+        }
+    }
+
+    /// notes from before version vectors have none
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn get(&self, device: &str) -> u64 {
+        self.0.get(device).copied().unwrap_or(0)
+    }
+
+    /// Greater if this version was made after seeing `other`, Less if
+    /// `other` was made after seeing this one, None if neither saw the other.
+    pub fn compare(&self, other: &VersionVector) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        for device in self.0.keys().chain(other.0.keys()) {
+            let step = self.get(device).cmp(&other.get(device));
+            ordering = match (ordering, step) {
+                (ordering, Ordering::Equal) => ordering,
+                (Ordering::Equal, step) => step,
+                (ordering, step) if ordering == step => ordering,
+                _ => return None,
+            };
+        }
+        Some(ordering)
+    }
+}
+
+/// How a version of a note from another device relates to ours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncVerdict {
+    /// theirs was made after seeing ours, or we have none
+    FastForward,
+    /// ours is theirs, or was made after seeing it
+    UpToDate,
+    /// each has edits the other hasn't seen, or there are no vectors to
+    /// tell. the newer timestamp wins, the device holding the other version
+    /// keeps a copy of it, or merges the two if the note is a log.
+    Concurrent,
+}
+
+impl SyncVerdict {
+    pub fn between(
+        local_modified: HybridTimestamp,
+        local: &VersionVector,
+        remote_modified: HybridTimestamp,
+        remote: &VersionVector,
+    ) -> Self {
+        // a version from before vectors may or may not have seen the other
+        if local.is_empty() || remote.is_empty() {
+            return if remote_modified > local_modified {
+                SyncVerdict::Concurrent
+            } else {
+                SyncVerdict::UpToDate
+            };
+        }
+        match remote.compare(local) {
+            Some(Ordering::Greater) => SyncVerdict::FastForward,
+            Some(Ordering::Less) => SyncVerdict::UpToDate,
+            // edited by something that doesn't keep the vector
+            Some(Ordering::Equal) if remote_modified > local_modified => SyncVerdict::Concurrent,
+            Some(Ordering::Equal) => SyncVerdict::UpToDate,
+            None => SyncVerdict::Concurrent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_and_concurrent_edits() {
+        let laptop = 1;
+        let phone = 2;
+        let mut base = VersionVector::default();
+        base.increment(laptop);
+
+        let mut after = base.clone();
+        after.increment(phone);
+        assert_eq!(after.compare(&base), Some(Ordering::Greater));
+        assert_eq!(base.compare(&after), Some(Ordering::Less));
+        assert_eq!(base.compare(&base.clone()), Some(Ordering::Equal));
+
+        let mut other = base.clone();
+        other.increment(laptop);
+        assert_eq!(after.compare(&other), None);
+
+        let t1 = HybridTimestamp::from_secs(1);
+        let t2 = HybridTimestamp::from_secs(2);
+        assert_eq!(
+            SyncVerdict::between(t1, &base, t2, &after),
+            SyncVerdict::FastForward
+        );
+        assert_eq!(
+            SyncVerdict::between(t2, &after, t1, &base),
+            SyncVerdict::UpToDate
+        );
+        assert_eq!(
+            SyncVerdict::between(t1, &other, t2, &after),
+            SyncVerdict::Concurrent
+        );
+        let none = VersionVector::default();
+        assert_eq!(
+            SyncVerdict::between(t1, &none, t2, &after),
+            SyncVerdict::Concurrent
+        );
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let mut versions = VersionVector::default();
+        versions.increment(0);
+        versions.increment(0x1234);
+        versions.increment(0xabcd_ef01_2345_6789);
+        let yaml = serde_yaml::to_string(&versions).unwrap();
+        assert_eq!(
+            serde_yaml::from_str::<VersionVector>(&yaml).unwrap(),
+            versions
+        );
+    }
+}
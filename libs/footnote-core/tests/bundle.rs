@@ -0,0 +1,121 @@
+mod common;
+
+use common::{introduce, TestDevice};
+use footnote_core::service::sync_service::SyncService;
+use footnote_core::util::bundle::Bundle;
+use footnote_core::util::hybrid_timestamp::HybridTimestamp;
+use footnote_core::util::tombstone::tombstone_create;
+use footnote_core::util::transport::MemoryNetwork;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_bundle_mirrors_to_own_device() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+
+    let kept = Uuid::new_v4();
+    let deleted = Uuid::new_v4();
+    desktop.write_note("recipes/soup.md", kept, 10, &[]);
+    laptop.write_note("old.md", deleted, 10, &[]);
+    tombstone_create(desktop.path(), deleted, HybridTimestamp::from_secs(11))
+        .await
+        .unwrap();
+
+    let bundle = SyncService::mirror_bundle(&desktop.vault, "laptop")
+        .await
+        .unwrap();
+    SyncService::import_bundle(&laptop.vault, &bundle)
+        .await
+        .unwrap();
+
+    let mirrored = laptop.read_note("recipes/soup.md").unwrap();
+    assert_eq!(mirrored.frontmatter.uuid, kept);
+    assert!(laptop.read_note("old.md").is_none());
+}
+
+#[tokio::test]
+async fn test_bundle_shares_only_shared_notes() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+
+    alice.write_note("shared.md", Uuid::new_v4(), 10, &["bob"]);
+    alice.write_note("private.md", Uuid::new_v4(), 10, &[]);
+
+    let bundle = SyncService::share_bundle(&alice.vault, "bob")
+        .await
+        .unwrap();
+    SyncService::import_bundle(&bob.vault, &bundle)
+        .await
+        .unwrap();
+
+    assert!(bob
+        .read_note(bob.shared_from("alice", "shared.md"))
+        .is_some());
+    assert!(bob
+        .read_note(bob.shared_from("alice", "private.md"))
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_tampered_bundle_is_rejected() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    desktop.write_note("soup.md", Uuid::new_v4(), 10, &[]);
+
+    let mut bundle = SyncService::mirror_bundle(&desktop.vault, "laptop")
+        .await
+        .unwrap();
+    let soup = bundle
+        .windows(b"soup".len())
+        .position(|w| w == b"soup")
+        .unwrap();
+    bundle[soup] = b'S';
+
+    assert!(SyncService::import_bundle(&laptop.vault, &bundle)
+        .await
+        .is_err());
+    assert!(laptop.read_note("soup.md").is_none());
+}
+
+#[tokio::test]
+async fn test_bundle_for_another_device_is_rejected() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let _laptop = TestDevice::join(&network, &desktop, "laptop");
+    let phone = TestDevice::join(&network, &desktop, "phone");
+    desktop.write_note("soup.md", Uuid::new_v4(), 10, &[]);
+
+    let bundle = SyncService::mirror_bundle(&desktop.vault, "laptop")
+        .await
+        .unwrap();
+    assert!(SyncService::import_bundle(&phone.vault, &bundle)
+        .await
+        .is_err());
+    assert!(phone.read_note("soup.md").is_none());
+}
+
+#[tokio::test]
+async fn test_bundle_from_another_protocol_is_rejected() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    desktop.write_note("soup.md", Uuid::new_v4(), 10, &[]);
+
+    let bytes = SyncService::mirror_bundle(&desktop.vault, "laptop")
+        .await
+        .unwrap();
+    let mut bundle = Bundle::from_bytes(&bytes).await.unwrap();
+    bundle.header.protocol = "footnote/sync/2".to_string();
+    let (secret_key, _) = desktop.vault.device_secret_key().unwrap();
+    let older = bundle.to_bytes(&secret_key).await.unwrap();
+
+    let error = SyncService::import_bundle(&laptop.vault, &older)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("footnote/sync/2"));
+    assert!(laptop.read_note("soup.md").is_none());
+}
@@ -0,0 +1,99 @@
+#![allow(dead_code)]
+
+use footnote_core::model::note::Note;
+use footnote_core::model::user::LocalUser;
+use footnote_core::model::vault::Vault;
+use footnote_core::service::sync_service::SyncService;
+use footnote_core::util::transport::{MemoryNetwork, MemoryTransport};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// A vault in a temporary directory with an endpoint on the memory network.
+pub struct TestDevice {
+    _dir: TempDir,
+    pub vault: Vault,
+    pub transport: MemoryTransport,
+}
+
+impl TestDevice {
+    /// the first device of a new user
+    pub fn primary(network: &MemoryNetwork, username: &str, device_name: &str) -> Self {
+        let dir = TempDir::new().unwrap();
+        let vault = Vault::create_primary(dir.path(), username, device_name).unwrap();
+        Self::bind(network, dir, vault)
+    }
+
+    /// a new device joined to primary's user, as pairing would
+    pub fn join(network: &MemoryNetwork, primary: &TestDevice, device_name: &str) -> Self {
+        let dir = TempDir::new().unwrap();
+        let vault = Vault::create_secondary(dir.path(), device_name).unwrap();
+        let (endpoint_id, _) = vault.device_public_key().unwrap();
+        let user_record = LocalUser::new(&primary.vault.path)
+            .unwrap()
+            .bless_remote_device(device_name, &endpoint_id.to_string(), None)
+            .unwrap();
+        vault.user_write(&user_record).unwrap();
+        Self::bind(network, dir, vault)
+    }
+
+    fn bind(network: &MemoryNetwork, dir: TempDir, vault: Vault) -> Self {
+        let (endpoint_id, _) = vault.device_public_key().unwrap();
+        let transport = network.bind(endpoint_id);
+        Self {
+            _dir: dir,
+            vault,
+            transport,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.vault.path
+    }
+
+    /// accept syncs until cancelled
+    pub fn listen(&self, cancel: &CancellationToken) -> JoinHandle<anyhow::Result<()>> {
+        tokio::spawn(SyncService::listen(
+            self.vault.clone(),
+            self.transport.clone(),
+            cancel.clone(),
+        ))
+    }
+
+    pub fn write_note(&self, relative: &str, uuid: Uuid, modified: i64, share_with: &[&str]) {
+        let path = self.path().join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, note(uuid, modified, share_with, relative)).unwrap();
+    }
+
+    pub fn read_note(&self, relative: impl AsRef<Path>) -> Option<Note> {
+        Note::from_path(self.path().join(relative), false).ok()
+    }
+
+    /// where notes shared by a contact end up
+    pub fn shared_from(&self, nickname: &str, relative: &str) -> PathBuf {
+        PathBuf::from("footnotes").join(nickname).join(relative)
+    }
+}
+
+/// each side saves the other as a contact under the given nickname
+pub fn introduce(a: &TestDevice, nickname_of_b: &str, b: &TestDevice, nickname_of_a: &str) {
+    let a_record = a.vault.user_read().unwrap().unwrap().to_json().unwrap();
+    let b_record = b.vault.user_read().unwrap().unwrap().to_json().unwrap();
+    a.vault.contact_import(nickname_of_b, &b_record).unwrap();
+    b.vault.contact_import(nickname_of_a, &a_record).unwrap();
+}
+
+pub fn note(uuid: Uuid, modified: impl Display, share_with: &[&str], body: &str) -> String {
+    format!(
+        "---\nuuid: {}\nmodified: {}\nshare_with: [{}]\n---\n{}\n",
+        uuid,
+        modified,
+        share_with.join(", "),
+        body
+    )
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{introduce, TestDevice};
+use footnote_core::service::ping_service::{PathKind, PingOutcome, PingReport, PingService};
+use footnote_core::service::sync_service::SyncService;
+use footnote_core::service::APP_VERSION;
+use footnote_core::util::sync_status_record::{SyncDirection, SyncStatusRecord};
+use footnote_core::util::transport::{MemoryNetwork, Transport};
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_ping_own_device() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let listener = laptop.listen(&cancel);
+
+    let reports = PingService::ping_peer(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+    assert_eq!(reports.len(), 1);
+    let (device_name, report) = &reports[0];
+    assert_eq!(device_name, "laptop");
+    assert!(matches!(
+        report.outcome,
+        PingOutcome::Reachable {
+            path: PathKind::Unknown,
+            ..
+        }
+    ));
+
+    assert_eq!(report.app_version.as_deref(), Some(APP_VERSION));
+
+    let laptop_id = laptop.transport.id().to_string();
+    let stored = PingReport::read(desktop.path(), &laptop_id).unwrap();
+    assert_eq!(stored.as_ref(), Some(report));
+    let presence = desktop.vault.device_presence(&laptop_id).unwrap().unwrap();
+    assert_eq!(presence.app_version.as_deref(), Some(APP_VERSION));
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_ping_offline_device() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    network.unbind(&laptop.transport.id());
+
+    let report = PingService::ping(desktop.transport.clone(), laptop.transport.id()).await;
+    assert!(matches!(report.outcome, PingOutcome::Unreachable { .. }));
+}
+
+#[tokio::test]
+async fn test_ping_from_stranger_is_rejected() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    let mallory = TestDevice::primary(&network, "mallory", "laptop");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let listener = bob.listen(&cancel);
+
+    let report = PingService::ping(alice.transport.clone(), bob.transport.id()).await;
+    assert!(matches!(report.outcome, PingOutcome::Reachable { .. }));
+    let report = PingService::ping(mallory.transport.clone(), bob.transport.id()).await;
+    assert!(matches!(report.outcome, PingOutcome::Rejected { .. }));
+    let mallory_id = mallory.transport.id().to_string();
+    assert!(bob.vault.device_presence(&mallory_id).unwrap().is_none());
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_failed_mirror_is_recorded() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    network.unbind(&laptop.transport.id());
+
+    let result =
+        SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop").await;
+    assert!(result.is_err());
+
+    let status = SyncStatusRecord::read(
+        desktop.vault.base_path(),
+        &laptop.transport.id().to_string(),
+        SyncDirection::Outbound,
+    )
+    .unwrap()
+    .unwrap();
+    assert!(status.last_failure.is_some());
+}
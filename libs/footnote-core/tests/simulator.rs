@@ -0,0 +1,494 @@
+//! Drives several devices of one user through random edits, creates,
+//! deletes, renames, clock skews, mirrors cut short and shares to a contact,
+//! then mirrors until nothing changes and checks that:
+//!
+//! - every device holds the same notes at the same paths
+//! - every edit survives in a note, was deleted on purpose, or was kept as a
+//!   conflict copy on some device
+//! - the contact holds exactly the notes shared with them
+//!
+//! A run is reproducible from its seed: `FOOTNOTE_SIM_SEED=7 cargo test -p
+//! footnote-core --test simulator` replays seed 7 only.
+//!
+//! Device clocks never produce the same timestamp, sync does not resolve two
+//! different versions of a note with equal timestamps. Paths are never reused,
+//! two different notes created at the same path are not resolved either.
+
+mod common;
+
+use common::{note, TestDevice};
+use footnote_core::model::note::Note;
+use footnote_core::service::sync_service::SyncService;
+use footnote_core::util::conflict::conflicts_read;
+use footnote_core::util::hybrid_timestamp::HybridTimestamp;
+use footnote_core::util::manifest::{create_manifest_for_contact, create_manifest_full};
+use footnote_core::util::tombstone::tombstone_create;
+use footnote_core::util::transfer;
+use footnote_core::util::transport::{MemoryNetwork, Transport};
+use footnote_core::util::version_vector::VersionVector;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+const SEEDS: u64 = 4;
+const DEVICES: usize = 4;
+const STEPS: usize = 100;
+const MAX_SKEW: i64 = 40;
+/// how many bytes a connection cut short may carry, a mirror of a few notes
+/// is a few KiB
+const MAX_PARTIAL_BYTES: usize = 6000;
+const SYNC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// a note and its modified timestamp
+type Version = (Uuid, HybridTimestamp);
+
+/// what a device holds of one note
+#[derive(Debug, Clone, PartialEq)]
+struct Held {
+    path: PathBuf,
+    modified: HybridTimestamp,
+    versions: VersionVector,
+    body: String,
+    shared: bool,
+}
+
+struct Simulation {
+    seed: u64,
+    rng: StdRng,
+    network: MemoryNetwork,
+    devices: Vec<TestDevice>,
+    contact: TestDevice,
+    skews: Vec<i64>,
+    time: i64,
+    next_path: usize,
+    /// every version written on a device, with the version it was based on
+    parents: HashMap<Version, Option<Version>>,
+    /// versions a device deleted on purpose
+    deleted: HashSet<Version>,
+    log: Vec<String>,
+}
+
+impl Simulation {
+    fn new(seed: u64) -> Self {
+        let network = MemoryNetwork::new();
+        let mut devices = vec![TestDevice::primary(&network, "alice", "d0")];
+        for i in 1..DEVICES {
+            let device = TestDevice::join(&network, &devices[0], &format!("d{}", i));
+            devices.push(device);
+        }
+        // every device starts out knowing every other
+        let user_record = devices[0].vault.user_read().unwrap().unwrap();
+        for device in &devices[1..] {
+            device.vault.user_write(&user_record).unwrap();
+        }
+
+        let contact = TestDevice::primary(&network, "bob", "phone");
+        let bob_record = contact
+            .vault
+            .user_read()
+            .unwrap()
+            .unwrap()
+            .to_json()
+            .unwrap();
+        for device in &devices {
+            device.vault.contact_import("bob", &bob_record).unwrap();
+        }
+        contact
+            .vault
+            .contact_import("alice", &user_record.to_json().unwrap())
+            .unwrap();
+
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            network,
+            devices,
+            contact,
+            skews: vec![0; DEVICES],
+            time: 1000,
+            next_path: 0,
+            parents: HashMap::new(),
+            deleted: HashSet::new(),
+            log: Vec::new(),
+        }
+    }
+
+    /// a timestamp for device d later than after, as its skewed clock
+    /// would tick it
+    fn timestamp(&self, d: usize, after: HybridTimestamp) -> HybridTimestamp {
+        let clock = self.time + self.skews[d];
+        let (physical, counter) = if after.physical >= clock {
+            (after.physical, after.counter + 1)
+        } else {
+            (clock, 0)
+        };
+        HybridTimestamp {
+            physical,
+            counter,
+            device: d as u64 + 1,
+        }
+    }
+
+    /// the version vector of an edit on device d of a version with after
+    fn versions(&self, d: usize, after: &VersionVector) -> VersionVector {
+        let mut versions = after.clone();
+        versions.increment(d as u64 + 1);
+        versions
+    }
+
+    fn held(&self, d: usize) -> BTreeMap<Uuid, Held> {
+        let device = &self.devices[d];
+        create_manifest_full(device.path())
+            .unwrap()
+            .into_values()
+            .map(|entry| {
+                let note = device.read_note(&entry.path).unwrap();
+                let held = Held {
+                    path: entry.path,
+                    modified: entry.modified,
+                    versions: entry.versions,
+                    body: note.content.trim().to_string(),
+                    shared: note.frontmatter.share_with.iter().any(|n| n == "bob"),
+                };
+                (entry.uuid, held)
+            })
+            .collect()
+    }
+
+    fn pick_note(&mut self, d: usize) -> Option<(Uuid, Held)> {
+        let held = self.held(d);
+        if held.is_empty() {
+            return None;
+        }
+        let i = self.rng.random_range(0..held.len());
+        held.into_iter().nth(i)
+    }
+
+    fn new_path(&mut self) -> String {
+        self.next_path += 1;
+        let folder = ["", "journal/", "projects/"][self.rng.random_range(0..3)];
+        format!("{}n{}.md", folder, self.next_path)
+    }
+
+    fn write(&mut self, d: usize, relative: &str, uuid: Uuid, held: &Held) {
+        let path = self.devices[d].path().join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let share_with: &[&str] = if held.shared { &["bob"] } else { &[] };
+        let text = note(uuid, held.modified, share_with, &held.body);
+        let mut written = Note::from_string(&text, false).unwrap();
+        written.frontmatter.versions = held.versions.clone();
+        fs::write(path, written.to_string().unwrap()).unwrap();
+    }
+
+    fn create(&mut self, d: usize) {
+        let uuid = Uuid::from_bytes(self.rng.random());
+        let relative = self.new_path();
+        let held = Held {
+            path: PathBuf::from(&relative),
+            modified: self.timestamp(d, HybridTimestamp::default()),
+            versions: self.versions(d, &VersionVector::default()),
+            body: format!("created by d{} at {}", d, self.time),
+            shared: self.rng.random_bool(0.5),
+        };
+        self.write(d, &relative, uuid, &held);
+        self.parents.insert((uuid, held.modified), None);
+        self.log
+            .push(format!("d{} create {} {}", d, relative, held.modified));
+    }
+
+    fn edit(&mut self, d: usize) {
+        let Some((uuid, current)) = self.pick_note(d) else {
+            return self.create(d);
+        };
+        let held = Held {
+            modified: self.timestamp(d, current.modified),
+            versions: self.versions(d, &current.versions),
+            body: format!("edited by d{} at {}", d, self.time),
+            shared: if self.rng.random_bool(0.2) {
+                !current.shared
+            } else {
+                current.shared
+            },
+            ..current.clone()
+        };
+        let relative = held.path.to_string_lossy().to_string();
+        self.write(d, &relative, uuid, &held);
+        self.parents
+            .insert((uuid, held.modified), Some((uuid, current.modified)));
+        self.log.push(format!(
+            "d{} edit {} {} -> {}",
+            d, relative, current.modified, held.modified
+        ));
+    }
+
+    fn rename(&mut self, d: usize) {
+        let Some((uuid, current)) = self.pick_note(d) else {
+            return;
+        };
+        let relative = self.new_path();
+        let held = Held {
+            path: PathBuf::from(&relative),
+            modified: self.timestamp(d, current.modified),
+            versions: self.versions(d, &current.versions),
+            ..current.clone()
+        };
+        self.write(d, &relative, uuid, &held);
+        fs::remove_file(self.devices[d].path().join(&current.path)).unwrap();
+        self.parents
+            .insert((uuid, held.modified), Some((uuid, current.modified)));
+        self.log.push(format!(
+            "d{} rename {} -> {} {}",
+            d,
+            current.path.display(),
+            relative,
+            held.modified
+        ));
+    }
+
+    async fn delete(&mut self, d: usize) {
+        let Some((uuid, current)) = self.pick_note(d) else {
+            return;
+        };
+        // as the app does, deleting counts as a change after the loaded version
+        let deleted_at = self.timestamp(d, current.modified);
+        let device = &self.devices[d];
+        tombstone_create(device.path(), uuid, deleted_at)
+            .await
+            .unwrap();
+        fs::remove_file(device.path().join(&current.path)).unwrap();
+        self.deleted.insert((uuid, current.modified));
+        self.log.push(format!(
+            "d{} delete {} {} at {}",
+            d,
+            current.path.display(),
+            current.modified,
+            deleted_at
+        ));
+    }
+
+    /// mirror from a to b, receiving on b directly so the sync is finished on
+    /// both sides when this returns, even when it was cut short
+    async fn mirror(&mut self, a: usize, b: usize, partial: bool) {
+        let budget = self.rng.random_range(0..MAX_PARTIAL_BYTES);
+        if partial {
+            self.network.fail_after(budget);
+        }
+        self.log.push(if partial {
+            format!("d{} mirror to d{} cut after {} bytes", a, b, budget)
+        } else {
+            format!("d{} mirror to d{}", a, b)
+        });
+
+        let receiver_name = format!("d{}", b);
+        let sender = &self.devices[a];
+        let receiver = &self.devices[b];
+        let sending =
+            SyncService::mirror_to_device(&sender.vault, sender.transport.clone(), &receiver_name);
+        let receiving = async {
+            let connecting = receiver.transport.accept().await.unwrap();
+            transfer::receive_mirror(&receiver.vault, connecting.await?).await
+        };
+        let (sent, received) =
+            tokio::time::timeout(SYNC_TIMEOUT, async { tokio::join!(sending, receiving) })
+                .await
+                .unwrap_or_else(|_| self.fail("mirror timed out"));
+        if !partial {
+            if let Err(e) = sent.and(received) {
+                self.fail(&format!("mirror failed: {:#}", e));
+            }
+        }
+    }
+
+    /// the contact listens, a share that succeeded has been fully received
+    async fn share(&mut self, d: usize) {
+        let device = &self.devices[d];
+        let outcome =
+            SyncService::share_to_contact(&device.vault, device.transport.clone(), "bob").await;
+        self.log.push(format!(
+            "d{} share to bob: {:?}",
+            d,
+            outcome.map_err(|e| e.to_string())
+        ));
+    }
+
+    async fn step(&mut self) {
+        self.time += 1;
+        let d = self.rng.random_range(0..DEVICES);
+        match self.rng.random_range(0..100) {
+            0..25 => self.edit(d),
+            25..38 => self.create(d),
+            38..45 => self.delete(d).await,
+            45..52 => self.rename(d),
+            52..57 => {
+                self.skews[d] = self.rng.random_range(-MAX_SKEW..=MAX_SKEW);
+                self.log
+                    .push(format!("d{} clock skew {}", d, self.skews[d]));
+            }
+            57..92 => {
+                let other = (d + self.rng.random_range(1..DEVICES)) % DEVICES;
+                let partial = self.rng.random_bool(0.3);
+                self.mirror(d, other, partial).await;
+            }
+            _ => self.share(d).await,
+        }
+    }
+
+    /// mirror between every pair until a full round changes nothing
+    async fn settle(&mut self) {
+        for _ in 0..DEVICES * 2 {
+            let before: Vec<_> = (0..DEVICES).map(|d| self.held(d)).collect();
+            for a in 0..DEVICES {
+                for b in 0..DEVICES {
+                    if a != b {
+                        self.mirror(a, b, false).await;
+                    }
+                }
+            }
+            let after: Vec<_> = (0..DEVICES).map(|d| self.held(d)).collect();
+            if before == after {
+                return;
+            }
+        }
+        self.fail("mirrors did not settle");
+    }
+
+    fn check_converged(&self) -> BTreeMap<Uuid, Held> {
+        let first = self.held(0);
+        for d in 1..DEVICES {
+            let other = self.held(d);
+            if other != first {
+                self.fail(&format!(
+                    "d0 and d{} differ\n{}",
+                    d,
+                    differences(&first, "d0", &other, &format!("d{}", d))
+                ));
+            }
+        }
+        first
+    }
+
+    fn check_no_edit_lost(&self, notes: &BTreeMap<Uuid, Held>) {
+        let mut survivors: Vec<Version> = notes
+            .iter()
+            .map(|(uuid, held)| (*uuid, held.modified))
+            .collect();
+        survivors.extend(self.deleted.iter().copied());
+        for device in &self.devices {
+            for record in conflicts_read(device.path()).unwrap() {
+                let copy = Note::from_path(device.path().join(&record.copy), false).unwrap();
+                assert_eq!(copy.frontmatter.modified, record.replaced);
+                survivors.push((record.uuid, record.replaced));
+            }
+        }
+
+        let mut covered = HashSet::new();
+        for survivor in survivors {
+            let mut version = Some(survivor);
+            while let Some(v) = version {
+                if !covered.insert(v) {
+                    break;
+                }
+                version = self.parents.get(&v).copied().flatten();
+            }
+        }
+        for version in self.parents.keys() {
+            if !covered.contains(version) {
+                self.fail(&format!(
+                    "version {} of {} was lost without a conflict record",
+                    version.1, version.0
+                ));
+            }
+        }
+    }
+
+    async fn check_contact(&mut self, notes: &BTreeMap<Uuid, Held>) {
+        for d in 0..DEVICES {
+            self.share(d).await;
+        }
+        let received =
+            create_manifest_for_contact(&self.contact.path().join("footnotes").join("alice"))
+                .unwrap();
+        let received: BTreeMap<_, _> = received
+            .into_values()
+            .map(|entry| (entry.uuid, (entry.path, entry.modified)))
+            .collect();
+        let shared: BTreeMap<_, _> = notes
+            .iter()
+            .filter(|(_, held)| held.shared)
+            .map(|(uuid, held)| (*uuid, (held.path.clone(), held.modified)))
+            .collect();
+        if received != shared {
+            self.fail(&format!(
+                "bob does not hold the shared notes\n{}",
+                differences(&received, "bob", &shared, "shared")
+            ));
+        }
+    }
+
+    fn fail(&self, message: &str) -> ! {
+        panic!(
+            "seed {}: {}\n\nsteps:\n{}",
+            self.seed,
+            message,
+            self.log.join("\n")
+        );
+    }
+}
+
+/// the notes two maps disagree on
+fn differences<T: std::fmt::Debug + PartialEq>(
+    a: &BTreeMap<Uuid, T>,
+    a_name: &str,
+    b: &BTreeMap<Uuid, T>,
+    b_name: &str,
+) -> String {
+    let uuids: BTreeSet<_> = a.keys().chain(b.keys()).collect();
+    uuids
+        .into_iter()
+        .filter(|uuid| a.get(uuid) != b.get(uuid))
+        .map(|uuid| {
+            format!(
+                "{}\n  {}: {:?}\n  {}: {:?}",
+                uuid,
+                a_name,
+                a.get(uuid),
+                b_name,
+                b.get(uuid)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn run(seed: u64) {
+    let mut sim = Simulation::new(seed);
+    let cancel = CancellationToken::new();
+    let listener = sim.contact.listen(&cancel);
+
+    for _ in 0..STEPS {
+        sim.step().await;
+    }
+    sim.settle().await;
+    let notes = sim.check_converged();
+    sim.check_no_edit_lost(&notes);
+    sim.check_contact(&notes).await;
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_devices_converge() {
+    let seeds = match std::env::var("FOOTNOTE_SIM_SEED") {
+        Ok(seed) => vec![seed.parse().expect("FOOTNOTE_SIM_SEED is a number")],
+        Err(_) => (0..SEEDS).collect(),
+    };
+    for seed in seeds {
+        run(seed).await;
+    }
+}
@@ -0,0 +1,772 @@
+mod common;
+
+use common::{introduce, TestDevice};
+use footnote_core::model::device::DeviceRole;
+use footnote_core::model::note::Note;
+use footnote_core::service::sync_service::{InboundEvent, ShareOutcome, SyncService};
+use footnote_core::service::{ALPN_SYNC, APP_VERSION};
+use footnote_core::util::conflict::conflicts_read;
+use footnote_core::util::hybrid_timestamp::HybridTimestamp;
+use footnote_core::util::tombstone::tombstone_create;
+use footnote_core::util::transport::{MemoryNetwork, Transport};
+use std::fs;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_mirror_copies_notes_to_own_device() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let listener = laptop.listen(&cancel);
+
+    let uuid = Uuid::new_v4();
+    desktop.write_note("recipes/soup.md", uuid, 10, &[]);
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    let mirrored = laptop.read_note("recipes/soup.md").unwrap();
+    assert_eq!(mirrored.frontmatter.uuid, uuid);
+    assert_eq!(
+        mirrored.frontmatter.modified,
+        HybridTimestamp::from_secs(10)
+    );
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_listener_reports_inbound_syncs() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let stranger = TestDevice::primary(&network, "mallory", "phone");
+
+    let cancel = CancellationToken::new();
+    let (connects, _connected) = tokio::sync::mpsc::channel(8);
+    let (events, mut received) = tokio::sync::mpsc::channel(8);
+    let listener = tokio::spawn(SyncService::listen_reporting(
+        laptop.vault.clone(),
+        laptop.transport.clone(),
+        cancel.clone(),
+        connects,
+        events,
+    ));
+
+    desktop.write_note("soup.md", Uuid::new_v4(), 10, &[]);
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+    match received.recv().await.unwrap() {
+        InboundEvent::Received { peer } => assert_eq!(peer, "desktop"),
+        other => panic!("expected a received mirror, got {:?}", other),
+    }
+
+    // the laptop doesn't know mallory
+    let (laptop_id, _) = laptop.vault.device_public_key().unwrap();
+    let _connection = stranger
+        .transport
+        .connect(laptop_id, ALPN_SYNC)
+        .await
+        .unwrap();
+    let (stranger_id, _) = stranger.vault.device_public_key().unwrap();
+    match received.recv().await.unwrap() {
+        InboundEvent::Rejected { endpoint_id, .. } => {
+            assert_eq!(endpoint_id, stranger_id.to_string())
+        }
+        other => panic!("expected a rejected share, got {:?}", other),
+    }
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_keeps_newer_local_edit() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let listener = laptop.listen(&cancel);
+
+    let uuid = Uuid::new_v4();
+    desktop.write_note("soup.md", uuid, 10, &[]);
+    laptop.write_note("soup.md", uuid, 20, &[]);
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    let kept = laptop.read_note("soup.md").unwrap();
+    assert_eq!(kept.frontmatter.modified, HybridTimestamp::from_secs(20));
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_keeps_copy_only_of_concurrent_edits() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let desktop_listener = desktop.listen(&cancel);
+    let laptop_listener = laptop.listen(&cancel);
+
+    Note::create(
+        &desktop.path().join("soup.md"),
+        "from the desktop",
+        &desktop.vault.clock(),
+    )
+    .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    // the laptop edits the version it was sent, nothing is lost by taking it
+    let path = laptop.path().join("soup.md");
+    let mut note = Note::from_path(&path, false).unwrap();
+    note.update(&path, "edited on the laptop", &laptop.vault.clock())
+        .unwrap();
+    SyncService::mirror_to_device(&laptop.vault, laptop.transport.clone(), "desktop")
+        .await
+        .unwrap();
+    let synced = desktop.read_note("soup.md").unwrap();
+    assert_eq!(synced.content, "edited on the laptop");
+    assert!(conflicts_read(desktop.path()).unwrap().is_empty());
+
+    // both edit without seeing the other's edit
+    let path = desktop.path().join("soup.md");
+    let mut note = Note::from_path(&path, false).unwrap();
+    note.update(&path, "edited on the desktop", &desktop.vault.clock())
+        .unwrap();
+    let path = laptop.path().join("soup.md");
+    let mut note = Note::from_path(&path, false).unwrap();
+    note.update(&path, "edited on the laptop again", &laptop.vault.clock())
+        .unwrap();
+    SyncService::mirror_to_device(&laptop.vault, laptop.transport.clone(), "desktop")
+        .await
+        .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    let desktop_note = desktop.read_note("soup.md").unwrap();
+    let laptop_note = laptop.read_note("soup.md").unwrap();
+    assert_eq!(desktop_note.content, laptop_note.content);
+    // the edit that lost is kept, by the device that made it and by the one
+    // that got it first if it arrived after the newer one
+    let conflicts: Vec<_> = [&desktop, &laptop]
+        .iter()
+        .flat_map(|device| conflicts_read(device.path()).unwrap())
+        .collect();
+    assert!(!conflicts.is_empty());
+    assert!(conflicts.iter().all(|c| c.replaced == conflicts[0].replaced
+        && c.kept == Some(desktop_note.frontmatter.modified)));
+
+    cancel.cancel();
+    desktop_listener.await.unwrap().unwrap();
+    laptop_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_keeps_copy_of_older_concurrent_edit() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let desktop_listener = desktop.listen(&cancel);
+
+    Note::create(
+        &desktop.path().join("soup.md"),
+        "from the desktop",
+        &desktop.vault.clock(),
+    )
+    .unwrap();
+    let laptop_listener = laptop.listen(&cancel);
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    // the laptop's edit is older, and reaches the desktop first
+    let path = laptop.path().join("soup.md");
+    let mut note = Note::from_path(&path, false).unwrap();
+    note.update(&path, "edited on the laptop", &laptop.vault.clock())
+        .unwrap();
+    let path = desktop.path().join("soup.md");
+    let mut note = Note::from_path(&path, false).unwrap();
+    for content in ["edited", "edited on the desktop"] {
+        note.update(&path, content, &desktop.vault.clock()).unwrap();
+    }
+    SyncService::mirror_to_device(&laptop.vault, laptop.transport.clone(), "desktop")
+        .await
+        .unwrap();
+
+    assert_eq!(
+        desktop.read_note("soup.md").unwrap().content,
+        "edited on the desktop"
+    );
+    let conflicts = conflicts_read(desktop.path()).unwrap();
+    assert_eq!(conflicts.len(), 1);
+    let copy = Note::from_path(desktop.path().join(&conflicts[0].copy), false).unwrap();
+    assert_eq!(copy.content, "edited on the laptop");
+
+    cancel.cancel();
+    desktop_listener.await.unwrap().unwrap();
+    laptop_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_merges_log_appends() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let phone = TestDevice::join(&network, &desktop, "phone");
+    let cancel = CancellationToken::new();
+    let desktop_listener = desktop.listen(&cancel);
+    let phone_listener = phone.listen(&cancel);
+
+    let path = desktop.path().join("runs.md");
+    let mut log = Note::new(&desktop.vault.clock());
+    log.frontmatter.log = true;
+    log.append(&path, "- mon 5k", &desktop.vault.clock())
+        .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "phone")
+        .await
+        .unwrap();
+
+    let mut on_desktop = Note::from_path(&path, false).unwrap();
+    on_desktop
+        .append(&path, "- tue 8k", &desktop.vault.clock())
+        .unwrap();
+    let path = phone.path().join("runs.md");
+    let mut on_phone = Note::from_path(&path, false).unwrap();
+    on_phone
+        .append(&path, "- tue intervals", &phone.vault.clock())
+        .unwrap();
+    SyncService::mirror_to_device(&phone.vault, phone.transport.clone(), "desktop")
+        .await
+        .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "phone")
+        .await
+        .unwrap();
+    SyncService::mirror_to_device(&phone.vault, phone.transport.clone(), "desktop")
+        .await
+        .unwrap();
+
+    for device in [&desktop, &phone] {
+        let merged = device.read_note("runs.md").unwrap();
+        assert!(merged.is_log());
+        let lines: Vec<_> = merged.content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "- mon 5k");
+        assert!(lines.contains(&"- tue 8k"));
+        assert!(lines.contains(&"- tue intervals"));
+        assert!(conflicts_read(device.path()).unwrap().is_empty());
+    }
+    assert_eq!(
+        desktop.read_note("runs.md").unwrap().frontmatter.modified,
+        phone.read_note("runs.md").unwrap().frontmatter.modified
+    );
+
+    cancel.cancel();
+    desktop_listener.await.unwrap().unwrap();
+    phone_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_mirror_applies_tombstones() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let listener = laptop.listen(&cancel);
+
+    let uuid = Uuid::new_v4();
+    desktop.write_note("soup.md", uuid, 10, &[]);
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+    assert!(laptop.read_note("soup.md").is_some());
+
+    fs::remove_file(desktop.path().join("soup.md")).unwrap();
+    tombstone_create(desktop.path(), uuid, HybridTimestamp::from_secs(11))
+        .await
+        .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+    assert!(laptop.read_note("soup.md").is_none());
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_share_delivers_only_shared_notes() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let listener = bob.listen(&cancel);
+
+    alice.write_note("shared.md", Uuid::new_v4(), 10, &["bob"]);
+    alice.write_note("private.md", Uuid::new_v4(), 10, &[]);
+
+    let outcome = SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::Delivered);
+    assert!(bob
+        .read_note(bob.shared_from("alice", "shared.md"))
+        .is_some());
+    assert!(bob
+        .read_note(bob.shared_from("alice", "private.md"))
+        .is_none());
+    assert!(bob.read_note("shared.md").is_none());
+
+    // the delivery ledger knows bob has everything
+    let outcome = SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::UpToDate);
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_note_rejected_for_quota_is_shared_again() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let listener = bob.listen(&cancel);
+
+    let mut settings = bob.vault.settings_read().unwrap();
+    settings.default_quota.max_files = 1;
+    bob.vault.settings_write(&settings).unwrap();
+    alice.write_note("first.md", Uuid::new_v4(), 10, &["bob"]);
+    alice.write_note("second.md", Uuid::new_v4(), 10, &["bob"]);
+
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    let received = ["first.md", "second.md"]
+        .iter()
+        .filter(|name| bob.read_note(bob.shared_from("alice", name)).is_some())
+        .count();
+    assert_eq!(received, 1);
+
+    // the rejected note isn't counted as delivered
+    settings.default_quota.max_files = 2;
+    bob.vault.settings_write(&settings).unwrap();
+    let outcome = SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::Delivered);
+    assert!(bob
+        .read_note(bob.shared_from("alice", "second.md"))
+        .is_some());
+    assert!(bob
+        .read_note(bob.shared_from("alice", "first.md"))
+        .is_some());
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_unshared_note_is_removed_from_contact() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let listener = bob.listen(&cancel);
+
+    let uuid = Uuid::new_v4();
+    alice.write_note("shared.md", uuid, 10, &["bob"]);
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert!(bob
+        .read_note(bob.shared_from("alice", "shared.md"))
+        .is_some());
+
+    // removal only covers notes older than the newest note still shared, so
+    // a stale device can't empty the contact's copy
+    alice.write_note("shared.md", uuid, 11, &[]);
+    alice.write_note("still_shared.md", Uuid::new_v4(), 12, &["bob"]);
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert!(bob
+        .read_note(bob.shared_from("alice", "shared.md"))
+        .is_none());
+
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_note_shared_by_rule_on_another_device_is_kept() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&desktop, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let laptop_listener = laptop.listen(&cancel);
+    let bob_listener = bob.listen(&cancel);
+
+    // share rules are kept per device, only the desktop has this one
+    desktop
+        .vault
+        .share_rule_add("recipes", vec!["bob".to_string()])
+        .unwrap();
+    desktop.write_note("recipes/soup.md", Uuid::new_v4(), 10, &[]);
+    SyncService::share_to_contact(&desktop.vault, desktop.transport.clone(), "bob")
+        .await
+        .unwrap();
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+
+    let outcome = SyncService::share_to_contact(&laptop.vault, laptop.transport.clone(), "bob")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::UpToDate);
+    assert!(bob
+        .read_note(bob.shared_from("alice", "recipes/soup.md"))
+        .is_some());
+
+    cancel.cancel();
+    laptop_listener.await.unwrap().unwrap();
+    bob_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_share_to_offline_contact_fails() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    network.unbind(&bob.transport.id());
+
+    alice.write_note("shared.md", Uuid::new_v4(), 10, &["bob"]);
+    let result = SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob").await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_sync_records_presence_on_both_sides() {
+    let network = MemoryNetwork::new();
+    let desktop = TestDevice::primary(&network, "alice", "desktop");
+    let laptop = TestDevice::join(&network, &desktop, "laptop");
+    let cancel = CancellationToken::new();
+    let listener = laptop.listen(&cancel);
+
+    SyncService::mirror_to_device(&desktop.vault, desktop.transport.clone(), "laptop")
+        .await
+        .unwrap();
+    cancel.cancel();
+    listener.await.unwrap().unwrap();
+
+    let laptop_id = laptop.transport.id().to_string();
+    let desktop_id = desktop.transport.id().to_string();
+    let seen_by_desktop = desktop.vault.device_presence(&laptop_id).unwrap().unwrap();
+    let seen_by_laptop = laptop.vault.device_presence(&desktop_id).unwrap().unwrap();
+    assert_eq!(seen_by_desktop.app_version.as_deref(), Some(APP_VERSION));
+    assert_eq!(seen_by_laptop.app_version.as_deref(), Some(APP_VERSION));
+}
+
+#[tokio::test]
+async fn test_share_prefers_often_on_device() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    let bob_server = TestDevice::join(&network, &bob, "server");
+    bob.vault
+        .device_role_set("server", Some(DeviceRole::OftenOn))
+        .unwrap();
+    let bob_record = bob.vault.user_read().unwrap().unwrap();
+    bob_server.vault.user_write(&bob_record).unwrap();
+    introduce(&alice, "bob", &bob, "alice");
+    let alice_record = alice.vault.user_read().unwrap().unwrap().to_json().unwrap();
+    bob_server
+        .vault
+        .contact_import("alice", &alice_record)
+        .unwrap();
+
+    let cancel = CancellationToken::new();
+    let phone_listener = bob.listen(&cancel);
+    let server_listener = bob_server.listen(&cancel);
+
+    alice.write_note("shared.md", Uuid::new_v4(), 10, &["bob"]);
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+
+    assert!(bob_server
+        .read_note(bob_server.shared_from("alice", "shared.md"))
+        .is_some());
+    assert!(bob
+        .read_note(bob.shared_from("alice", "shared.md"))
+        .is_none());
+
+    cancel.cancel();
+    phone_listener.await.unwrap().unwrap();
+    server_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_share_merges_collaborative_edits() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let alice_listener = alice.listen(&cancel);
+    let bob_listener = bob.listen(&cancel);
+
+    let path = alice.path().join("meeting.md");
+    let mut meeting = Note::new(&alice.vault.clock());
+    meeting.frontmatter.collaborative = true;
+    meeting.frontmatter.share_with = vec!["bob".to_string()];
+    meeting.content = "# agenda\n\n- budget".to_string();
+    meeting.to_file(&path, &alice.vault.clock()).unwrap();
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+
+    let mut on_alice = Note::from_path(&path, false).unwrap();
+    on_alice.content = "# agenda\n\n- roadmap\n- budget".to_string();
+    on_alice.to_file(&path, &alice.vault.clock()).unwrap();
+    let bob_path = bob.path().join(bob.shared_from("alice", "meeting.md"));
+    let mut on_bob = Note::from_path(&bob_path, false).unwrap();
+    on_bob.content.push_str("\n- hiring");
+    on_bob.to_file(&bob_path, &bob.vault.clock()).unwrap();
+
+    // bob has nothing of his own to share, his edit goes out in the document
+    let outcome = SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::Delivered);
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+
+    let merged = "# agenda\n\n- roadmap\n- budget\n- hiring";
+    assert_eq!(alice.read_note("meeting.md").unwrap().content, merged);
+    assert_eq!(
+        bob.read_note(bob.shared_from("alice", "meeting.md"))
+            .unwrap()
+            .content,
+        merged
+    );
+    assert!(conflicts_read(alice.path()).unwrap().is_empty());
+    assert!(conflicts_read(bob.path()).unwrap().is_empty());
+
+    // both sides have every change to the document now
+    let outcome = SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    assert_eq!(outcome, ShareOutcome::UpToDate);
+
+    cancel.cancel();
+    alice_listener.await.unwrap().unwrap();
+    bob_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_shared_folder_syncs_both_ways() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let alice_listener = alice.listen(&cancel);
+    let bob_listener = bob.listen(&cancel);
+
+    // each member keeps the folder where they like
+    let id = alice
+        .vault
+        .shared_folder_create("trips", vec!["bob".to_string()])
+        .unwrap();
+    bob.vault
+        .shared_folder_join(id, "plans/", vec!["alice".to_string()])
+        .unwrap();
+    let rome = Uuid::new_v4();
+    let paris = Uuid::new_v4();
+    alice.write_note("trips/rome.md", rome, 10, &[]);
+    bob.write_note("plans/paris.md", paris, 20, &[]);
+
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+    let alice_key = alice.vault.user_read().unwrap().unwrap().id_public_key;
+    let on_bob = bob.read_note("plans/rome.md").unwrap();
+    assert_eq!(
+        on_bob.frontmatter.author.as_deref(),
+        Some(alice_key.as_str())
+    );
+    assert!(bob
+        .read_note(bob.shared_from("alice", "trips/rome.md"))
+        .is_none());
+
+    let path = bob.path().join("plans/rome.md");
+    let mut edited = Note::from_path(&path, false).unwrap();
+    edited.content.push_str("\nbook the train");
+    edited.to_file(&path, &bob.vault.clock()).unwrap();
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    let bob_key = bob.vault.user_read().unwrap().unwrap().id_public_key;
+    let on_alice = alice.read_note("trips/paris.md").unwrap();
+    assert_eq!(
+        on_alice.frontmatter.author.as_deref(),
+        Some(bob_key.as_str())
+    );
+    let on_alice = alice.read_note("trips/rome.md").unwrap();
+    assert!(on_alice.content.ends_with("book the train"));
+    assert!(on_alice.frontmatter.author.is_none());
+
+    // a member deleting a note they wrote deletes it for everyone
+    fs::remove_file(bob.path().join("plans/paris.md")).unwrap();
+    tombstone_create(bob.path(), paris, HybridTimestamp::from_secs(21))
+        .await
+        .unwrap();
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    assert!(alice.read_note("trips/paris.md").is_none());
+
+    cancel.cancel();
+    alice_listener.await.unwrap().unwrap();
+    bob_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_shared_folder_member_cannot_claim_notes() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    introduce(&alice, "bob", &bob, "alice");
+    let cancel = CancellationToken::new();
+    let alice_listener = alice.listen(&cancel);
+    let bob_listener = bob.listen(&cancel);
+
+    let id = alice
+        .vault
+        .shared_folder_create("trips", vec!["bob".to_string()])
+        .unwrap();
+    bob.vault
+        .shared_folder_join(id, "plans/", vec!["alice".to_string()])
+        .unwrap();
+    let rome = Uuid::new_v4();
+    let paris = Uuid::new_v4();
+    alice.write_note("trips/rome.md", rome, 10, &[]);
+    SyncService::share_to_contact(&alice.vault, alice.transport.clone(), "bob")
+        .await
+        .unwrap();
+
+    // bob names alice as the author of a note he wrote
+    let alice_key = alice.vault.user_read().unwrap().unwrap().id_public_key;
+    bob.write_note("plans/paris.md", paris, 20, &[]);
+    let path = bob.path().join("plans/paris.md");
+    let mut forged = Note::from_path(&path, false).unwrap();
+    forged.frontmatter.author = Some(alice_key);
+    forged.to_file(&path, &bob.vault.clock()).unwrap();
+    // and deletes her note
+    fs::remove_file(bob.path().join("plans/rome.md")).unwrap();
+    tombstone_create(bob.path(), rome, HybridTimestamp::from_secs(30))
+        .await
+        .unwrap();
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+
+    // none of alice's devices signed the note, so she doesn't take it
+    assert!(alice.read_note("trips/paris.md").is_none());
+    assert!(alice.read_note("trips/rome.md").is_some());
+
+    // a folder offered outside bob's vault isn't one alice writes to
+    let mut settings = bob.vault.settings_read().unwrap();
+    settings.shared_folders[0].path = "".into();
+    bob.vault.settings_write(&settings).unwrap();
+    let lisbon = Uuid::new_v4();
+    bob.write_note("lisbon.md", lisbon, 40, &["alice"]);
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    assert!(alice.read_note("trips/lisbon.md").is_none());
+    assert!(alice
+        .read_note(alice.shared_from("bob", "lisbon.md"))
+        .is_some());
+
+    cancel.cancel();
+    alice_listener.await.unwrap().unwrap();
+    bob_listener.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_shared_folder_keeps_author_of_relayed_note() {
+    let network = MemoryNetwork::new();
+    let alice = TestDevice::primary(&network, "alice", "desktop");
+    let bob = TestDevice::primary(&network, "bob", "phone");
+    let carol = TestDevice::primary(&network, "carol", "laptop");
+    introduce(&alice, "bob", &bob, "alice");
+    introduce(&alice, "carol", &carol, "alice");
+    introduce(&bob, "carol", &carol, "bob");
+    let cancel = CancellationToken::new();
+    let alice_listener = alice.listen(&cancel);
+    let bob_listener = bob.listen(&cancel);
+    let carol_listener = carol.listen(&cancel);
+
+    let id = alice
+        .vault
+        .shared_folder_create("trips", vec!["bob".to_string(), "carol".to_string()])
+        .unwrap();
+    bob.vault
+        .shared_folder_join(id, "trips/", vec!["alice".to_string(), "carol".to_string()])
+        .unwrap();
+    carol
+        .vault
+        .shared_folder_join(id, "trips/", vec!["alice".to_string(), "bob".to_string()])
+        .unwrap();
+
+    // carol's note reaches alice only through bob
+    let oslo = Uuid::new_v4();
+    carol.write_note("trips/oslo.md", oslo, 10, &[]);
+    SyncService::share_to_contact(&carol.vault, carol.transport.clone(), "bob")
+        .await
+        .unwrap();
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    let carol_key = carol.vault.user_read().unwrap().unwrap().id_public_key;
+    let on_alice = alice.read_note("trips/oslo.md").unwrap();
+    assert_eq!(
+        on_alice.frontmatter.author.as_deref(),
+        Some(carol_key.as_str())
+    );
+
+    // so bob can't delete it for her
+    fs::remove_file(bob.path().join("trips/oslo.md")).unwrap();
+    tombstone_create(bob.path(), oslo, HybridTimestamp::from_secs(20))
+        .await
+        .unwrap();
+    SyncService::share_to_contact(&bob.vault, bob.transport.clone(), "alice")
+        .await
+        .unwrap();
+    assert!(alice.read_note("trips/oslo.md").is_some());
+
+    cancel.cancel();
+    alice_listener.await.unwrap().unwrap();
+    bob_listener.await.unwrap().unwrap();
+    carol_listener.await.unwrap().unwrap();
+}